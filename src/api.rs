@@ -1,97 +1,1218 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
 use serde_json::Value;
+use tracing::Instrument;
+use web_time::Instant;
+
+use crate::transport::Transport;
+
+/// Controller answered with a non-success HTTP status
+#[derive(Debug)]
+pub struct ApiError {
+    pub context: String,
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to {} ({}): {}",
+            self.context, self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// TLS with the controller failed, most often because its certificate is self-signed or
+/// because of a wrong clock on either side
+#[derive(Debug)]
+pub struct TlsError {
+    pub context: String,
+    /// The certificate is expired or not yet valid according to this machine's clock
+    pub clock: bool,
+    /// The certificate is not issued by a trusted CA or not for this host, as with the
+    /// self-signed one UniFi consoles ship
+    pub untrusted: bool,
+    /// Innermost cause, as reported by the TLS library
+    pub detail: String,
+    pub source: reqwest::Error,
+}
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.clock {
+            write!(
+                f,
+                "Failed to {}: the controller certificate is not valid at this machine's \
+                 time ({}). Check the clock here and on the controller; `unifi doctor` shows \
+                 the certificate's validity window",
+                self.context,
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S %Z")
+            )
+        } else if self.untrusted {
+            write!(
+                f,
+                "Failed to {}: the controller certificate is not trusted ({}). UniFi consoles \
+                 (UDM, UCG, Cloud Key) ship a self-signed one: pin it with `fingerprint` in the \
+                 config's [tls] table (`unifi doctor` shows it), or accept any certificate \
+                 with --insecure (`unifi config --insecure` to keep it)",
+                self.context, self.detail
+            )
+        } else {
+            write!(
+                f,
+                "Failed to {}: TLS handshake failed ({}). Check the host and port; \
+                 `unifi doctor` shows the controller certificate",
+                self.context, self.detail
+            )
+        }
+    }
+}
+
+impl std::error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// rustls certificate errors meaning the certificate is fine but nobody vouches for it here
+const UNTRUSTED_CERT_ERRORS: &[&str] = &["UnknownIssuer", "CaUsedAsEndEntity", "NotValidForName"];
+
+/// Turn a transport error into `TlsError` when TLS is the cause, else add `context`
+fn transport_error(err: reqwest::Error, context: &str) -> anyhow::Error {
+    let chain: Vec<String> =
+        std::iter::successors(Some(&err as &dyn std::error::Error), |e| e.source())
+            .map(|e| e.to_string())
+            .collect();
+    let text = chain.join(": ");
+    let clock = ["Expired", "NotValidYet"].iter().any(|k| text.contains(k));
+    if clock
+        || ["certificate", "handshake", "TLS"]
+            .iter()
+            .any(|k| text.contains(k))
+    {
+        return TlsError {
+            context: context.to_string(),
+            clock,
+            untrusted: !clock && UNTRUSTED_CERT_ERRORS.iter().any(|k| text.contains(k)),
+            detail: chain.last().cloned().unwrap_or_default(),
+            source: err,
+        }
+        .into();
+    }
+    anyhow::Error::new(err).context(format!("Failed to {context}"))
+}
+
+/// A looked-up object (setting, network, ...) does not exist on the controller
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// One request as sent to the controller
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+    pub method: String,
+    /// Path and query (the host is the same for all requests of a client)
+    pub path: String,
+    /// `None` when no response came back
+    pub status: Option<u16>,
+    pub elapsed: Duration,
+}
+
+/// Log of the requests clients send, shared by its clones (e.g., by every client of a
+/// command); see `ClientBuilder::timings`
+#[derive(Debug, Clone, Default)]
+pub struct Timings(Arc<Mutex<Vec<RequestTiming>>>);
+
+impl Timings {
+    fn record(&self, timing: RequestTiming) {
+        self.0.lock().unwrap().push(timing);
+    }
+
+    /// The requests recorded so far, in the order they finished
+    pub fn requests(&self) -> Vec<RequestTiming> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// One line per request, repeated ones marked, then the totals against `wall` time
+    pub fn report(&self, wall: Duration) -> String {
+        let requests = self.requests();
+        let mut seen: HashMap<(&str, &str), usize> = HashMap::new();
+        for r in &requests {
+            *seen.entry((&r.method, &r.path)).or_default() += 1;
+        }
+        let mut out = String::new();
+        for r in &requests {
+            let status = r.status.map_or("---".to_string(), |s| s.to_string());
+            let repeats = match seen[&(r.method.as_str(), r.path.as_str())] {
+                1 => String::new(),
+                n => format!("  (sent {n}x)"),
+            };
+            out.push_str(&format!(
+                "{:>7} ms  {status}  {} {}{repeats}\n",
+                r.elapsed.as_millis(),
+                r.method,
+                r.path
+            ));
+        }
+        let in_requests: Duration = requests.iter().map(|r| r.elapsed).sum();
+        let repeated = seen.values().filter(|n| **n > 1).count();
+        out.push_str(&format!(
+            "{} request(s), {} ms in requests, {} ms wall time",
+            requests.len(),
+            in_requests.as_millis(),
+            wall.as_millis()
+        ));
+        if repeated > 0 {
+            out.push_str(&format!(", {repeated} sent more than once"));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Read-through cache of GET responses, keyed by URL
+struct Cache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+/// Cached responses dropped by `Client::invalidate`
+#[derive(Debug, Clone, Copy)]
+pub enum CacheScope<'a> {
+    All,
+    /// One endpoint and anything below it, by its path under the site (e.g., `rest/wlanconf`,
+    /// `stat/device`) or, for the v2 API, its first segment (e.g., `trafficrules`)
+    Endpoint(&'a str),
+}
+
+/// Whether a cached URL belongs to `endpoint`
+fn in_scope(url: &str, endpoint: &str) -> bool {
+    let path = url.split('?').next().unwrap_or(url);
+    path.match_indices(endpoint).any(|(at, _)| {
+        path[..at].ends_with('/')
+            && matches!(path[at + endpoint.len()..].chars().next(), None | Some('/'))
+    })
+}
+
+/// Endpoints whose cached responses a successful write to `url` makes stale; `None` when
+/// the write is not recognized and everything should go
+fn stale_endpoints(url: &str) -> Option<Vec<String>> {
+    let path = url.split('?').next()?;
+    if let Some((_, rest)) = path.split_once("/v2/api/site/") {
+        let (_, rest) = rest.split_once('/')?;
+        return Some(vec![rest.split('/').next()?.to_string()]);
+    }
+    let (_, rest) = path.split_once("/api/s/")?;
+    let (_, rest) = rest.split_once('/')?;
+    let mut parts = rest.split('/');
+    let (kind, name) = (parts.next()?, parts.next()?);
+    let endpoints: &[&str] = match (kind, name) {
+        // Client and device commands change the live stats as well as the stored objects
+        ("rest", "user") | ("cmd", "stamgr") => &["rest/user", "stat/sta", "stat/alluser"],
+        ("rest", "device") | ("cmd", "devmgr") => &["rest/device", "stat/device"],
+        ("rest", "setting") | ("set", "setting") => &["rest/setting", "get/setting"],
+        ("rest", _) => return Some(vec![format!("rest/{name}")]),
+        _ => return None,
+    };
+    Some(endpoints.iter().map(|e| e.to_string()).collect())
+}
+
+/// Asks the user for a two-factor (TOTP) code when a login needs one
+pub type MfaPrompt = Arc<dyn Fn() -> Result<String> + Send + Sync>;
 
+/// Shown each write `dry_run` keeps from the controller: method and URL, the extra write
+/// headers, and the body, one per line
+pub type DryRunReport = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Whether a failed login is the controller asking for a two-factor code: UniFi OS answers
+/// 499 `MFA_AUTH_REQUIRED`, classic controllers `api.err.Ubic2faTokenRequired`
+fn needs_mfa(status: StatusCode, body: &str) -> bool {
+    status.as_u16() == 499
+        || body.contains("MFA_AUTH_REQUIRED")
+        || body.contains("Ubic2faTokenRequired")
+}
+
+/// Network application root on UniFi OS consoles; classic controllers serve it at `/`
+const UNIFI_OS_NETWORK_ROOT: &str = "/proxy/network";
+
+/// Normalize a configured host into a base URL: `https://` by default, port and
+/// path prefix kept (`https://host:8443/unifi`), trailing slashes removed
+pub fn normalize_base_url(host: &str) -> Result<String> {
+    let host = host.trim();
+    let with_scheme = if host.contains("://") {
+        host.to_string()
+    } else {
+        format!("https://{host}")
+    };
+    let url = reqwest::Url::parse(&with_scheme)
+        .with_context(|| format!("Invalid controller address '{host}'"))?;
+    anyhow::ensure!(
+        matches!(url.scheme(), "http" | "https") && url.host_str().is_some(),
+        "Invalid controller address '{host}' (expected e.g. 192.168.1.1 or https://host:8443/prefix)"
+    );
+    anyhow::ensure!(
+        url.query().is_none() && url.fragment().is_none(),
+        "Controller address '{host}' must not contain a query or fragment"
+    );
+    let mut base = format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default());
+    if let Some(port) = url.port() {
+        base.push_str(&format!(":{port}"));
+    }
+    base.push_str(url.path().trim_end_matches('/'));
+    Ok(base)
+}
+
+/// How requests are authenticated
+#[derive(Clone)]
+pub enum Auth {
+    /// `X-API-Key` header (UniFi OS consoles)
+    ApiKey(String),
+    /// Session cookie from a username/password login (required by classic controllers)
+    Password { username: String, password: String },
+}
+
+/// Clones share the session (cookies, CSRF token) and the response cache
+#[derive(Clone)]
 pub struct Client {
-    pub(crate) http: reqwest::Client,
+    /// Builds requests; `transport` sends them
+    http: reqwest::Client,
+    transport: Arc<dyn Transport>,
     pub(crate) base_url: String,
-    pub(crate) api_key: String,
+    auth: Auth,
+    /// CSRF token UniFi OS hands out with a session login; sent back on every request
+    csrf_token: Arc<Mutex<Option<String>>>,
+    pub(crate) site: String,
+    /// Path of the Network application under `base_url`
+    network_root: &'static str,
+    cache: Option<Arc<Cache>>,
+    /// Report writes instead of sending them
+    dry_run: bool,
+    dry_run_report: Option<DryRunReport>,
+    /// Sent with every write
+    write_headers: HeaderMap,
+    mfa_prompt: Option<MfaPrompt>,
+    timings: Option<Timings>,
+}
+
+/// What `Client::send` got back: the controller's response, or under `dry_run` the reply the
+/// endpoint would have given (there is no `reqwest::Response` to fake on wasm)
+pub(crate) enum Reply {
+    Sent(reqwest::Response),
+    DryRun(Value),
+}
+
+impl Reply {
+    pub(crate) async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T> {
+        Ok(match self {
+            Reply::Sent(resp) => resp.json().await?,
+            Reply::DryRun(value) => serde_json::from_value(value)?,
+        })
+    }
+
+    pub(crate) async fn text(self) -> Result<String> {
+        Ok(match self {
+            Reply::Sent(resp) => resp.text().await?,
+            Reply::DryRun(value) => value.to_string(),
+        })
+    }
+
+    pub(crate) async fn bytes(self) -> Result<Vec<u8>> {
+        Ok(match self {
+            Reply::Sent(resp) => resp.bytes().await?.to_vec(),
+            Reply::DryRun(value) => value.to_string().into_bytes(),
+        })
+    }
+}
+
+/// Configures a `Client`; start with `Client::builder()`
+#[derive(Default)]
+pub struct ClientBuilder {
+    host: Option<String>,
+    auth: Option<Auth>,
+    site: Option<String>,
+    /// Accept any certificate
+    insecure: bool,
+    ca_bundle: Option<Vec<u8>>,
+    pinned_certificate: Option<[u8; 32]>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    legacy_paths: bool,
+    dry_run: bool,
+    dry_run_report: Option<DryRunReport>,
+    write_headers: Vec<(String, String)>,
+    mfa_prompt: Option<MfaPrompt>,
+    transport: Option<Arc<dyn Transport>>,
+    timings: Option<Timings>,
+}
+
+impl ClientBuilder {
+    /// Controller address: IP, hostname, or URL with port and path prefix
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.auth = Some(Auth::ApiKey(api_key.into()));
+        self
+    }
+
+    /// Local controller account; call `Client::login` after building
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some(Auth::Password {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Site short name (default: `default`)
+    pub fn site(mut self, site: impl Into<String>) -> Self {
+        self.site = Some(site.into());
+        self
+    }
+
+    /// Check the controller certificate (the default); turn off for a console's self-signed
+    /// one, or pin it with `pin_certificate` instead
+    pub fn verify_tls(mut self, verify: bool) -> Self {
+        self.insecure = !verify;
+        self
+    }
+
+    /// Also trust the CA certificates in this PEM bundle
+    pub fn ca_bundle(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_bundle = Some(pem.into());
+        self
+    }
+
+    /// Trust only the certificate with this SHA-256 fingerprint (see `tls::parse_fingerprint`),
+    /// self-signed or not; other CA and name checks are skipped
+    pub fn pin_certificate(mut self, sha256: [u8; 32]) -> Self {
+        self.pinned_certificate = Some(sha256);
+        self
+    }
+
+    /// Time limit for each request (default: none)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Classic self-hosted controller without the `/proxy/network` prefix
+    pub fn legacy_paths(mut self, legacy: bool) -> Self {
+        self.legacy_paths = legacy;
+        self
+    }
+
+    /// Report every write (method, URL, JSON body) instead of sending it, and answer it as if
+    /// the controller had accepted the body; reads still go out
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Where `dry_run` reports the writes it keeps back; without it, they are logged at info
+    /// level
+    pub fn dry_run_report(mut self, report: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.dry_run_report = Some(Arc::new(report));
+        self
+    }
+
+    /// How to get a two-factor code when a password login asks for one; without it, such a
+    /// login fails
+    pub fn mfa_prompt(
+        mut self,
+        prompt: impl Fn() -> Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.mfa_prompt = Some(Arc::new(prompt));
+        self
+    }
+
+    /// Add a header to every request that changes something (e.g., `X-Change-Ticket`), so
+    /// changes can be matched to change records in proxy logs
+    pub fn write_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.write_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Record the method, path, status, and latency of every request sent (not cache hits)
+    pub fn timings(mut self, timings: Timings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    /// Send requests through `transport` instead of a reqwest client built from the TLS,
+    /// timeout, and user agent options (which it then has to handle itself)
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// The default transport
+    #[cfg(not(target_arch = "wasm32"))]
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let mut http = reqwest::Client::builder()
+            .danger_accept_invalid_certs(self.insecure && self.pinned_certificate.is_none())
+            .cookie_store(true)
+            .user_agent(
+                self.user_agent
+                    .clone()
+                    .unwrap_or_else(|| concat!("unifi/", env!("CARGO_PKG_VERSION")).into()),
+            );
+        if let Some(pem) = &self.ca_bundle {
+            let certs = reqwest::Certificate::from_pem_bundle(pem)
+                .context("Failed to read the CA bundle (expected PEM certificates)")?;
+            for cert in certs {
+                http = http.add_root_certificate(cert);
+            }
+        }
+        if let Some(sha256) = self.pinned_certificate {
+            http = http.use_preconfigured_tls(crate::tls::pinned_config(sha256)?);
+        }
+        if let Some(timeout) = self.timeout {
+            http = http.timeout(timeout);
+        }
+        Ok(http.build()?)
+    }
+
+    /// In the browser, certificates, cookies, and the user agent are up to `fetch`
+    #[cfg(target_arch = "wasm32")]
+    fn http_client(&self) -> Result<reqwest::Client> {
+        Ok(reqwest::Client::new())
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let http = self.http_client()?;
+        let host = self.host.context("No controller host given")?;
+        let auth = self.auth.context("No API key or credentials given")?;
+        let mut write_headers = HeaderMap::new();
+        for (name, value) in &self.write_headers {
+            let name = HeaderName::try_from(name.as_str())
+                .with_context(|| format!("Invalid header name '{name}'"))?;
+            let value = HeaderValue::try_from(value.as_str())
+                .with_context(|| format!("Invalid value for header {name}"))?;
+            write_headers.append(name, value);
+        }
+
+        Ok(Client {
+            transport: self.transport.unwrap_or_else(|| Arc::new(http.clone())),
+            http,
+            base_url: normalize_base_url(&host)?,
+            auth,
+            csrf_token: Arc::new(Mutex::new(None)),
+            site: self.site.unwrap_or_else(|| "default".to_string()),
+            network_root: if self.legacy_paths {
+                ""
+            } else {
+                UNIFI_OS_NETWORK_ROOT
+            },
+            cache: None,
+            dry_run: self.dry_run,
+            dry_run_report: self.dry_run_report,
+            write_headers,
+            mfa_prompt: self.mfa_prompt,
+            timings: self.timings,
+        })
+    }
 }
 
 impl Client {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// Shorthand for `Client::builder().host(host).api_key(api_key).build()`
     pub fn new(host: &str, api_key: &str) -> Result<Self> {
-        let http = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true) // UDM uses self-signed certs
-            .build()?;
+        Self::builder().host(host).api_key(api_key).build()
+    }
+
+    /// Shorthand for `Client::builder().host(host).credentials(username, password).build()`;
+    /// call `login` before use
+    pub fn new_with_password(host: &str, username: &str, password: &str) -> Result<Self> {
+        Self::builder()
+            .host(host)
+            .credentials(username, password)
+            .build()
+    }
+
+    /// Guess whether this is a classic self-hosted controller (no `/proxy/network` prefix).
+    ///
+    /// API keys only exist on UniFi OS, even behind a reverse proxy on port 8443. For session
+    /// auth, port 8443 (the classic default) is taken as a hint; otherwise ask `/status`,
+    /// which only classic controllers answer with `meta.server_version`.
+    pub async fn detect_legacy(&self) -> bool {
+        if matches!(self.auth, Auth::ApiKey(_)) {
+            return false;
+        }
+        if reqwest::Url::parse(&self.base_url).is_ok_and(|u| u.port() == Some(8443)) {
+            return true;
+        }
+        let status = async {
+            self.execute(self.http.get(format!("{}/status", self.base_url)))
+                .await
+                .ok()?
+                .json::<Value>()
+                .await
+                .ok()
+        };
+        status
+            .await
+            .is_some_and(|v| v.pointer("/meta/server_version").is_some())
+    }
 
-        let base_url = if host.starts_with("http") {
-            host.to_string()
+    /// Start a session for password auth (no-op for API keys). When the account has
+    /// two-factor authentication, the code comes from the `mfa_prompt` given to the builder.
+    pub async fn login(&self) -> Result<()> {
+        let Auth::Password { username, password } = &self.auth else {
+            return Ok(());
+        };
+        let legacy = self.network_root.is_empty();
+        let (url, mut body) = if legacy {
+            (
+                self.network_url("api/login"),
+                serde_json::json!({"username": username, "password": password, "remember": true}),
+            )
         } else {
-            format!("https://{}", host)
+            (
+                format!("{}/api/auth/login", self.base_url),
+                serde_json::json!({"username": username, "password": password, "rememberMe": true}),
+            )
         };
 
-        Ok(Self {
-            http,
-            base_url,
-            api_key: api_key.to_string(),
-        })
+        let mut asked = false;
+        let resp = loop {
+            let resp = self
+                .execute(self.http.post(&url).json(&body))
+                .await
+                .map_err(|e| transport_error(e, "log in"))?;
+            if resp.status().is_success() {
+                break resp;
+            }
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            let prompt = self.mfa_prompt.as_ref().filter(|_| !asked);
+            match prompt {
+                Some(prompt) if needs_mfa(status, &text) => {
+                    let field = if legacy { "ubic_2fa_token" } else { "token" };
+                    body[field] = serde_json::json!(prompt()?.trim());
+                    asked = true;
+                }
+                _ => {
+                    let mfa = !asked && needs_mfa(status, &text);
+                    let err = anyhow::Error::from(ApiError {
+                        context: "log in".to_string(),
+                        status,
+                        body: text,
+                    });
+                    return Err(if mfa {
+                        err.context("The account needs a two-factor code and none can be asked for")
+                    } else {
+                        err
+                    });
+                }
+            }
+        };
+        let token = resp
+            .headers()
+            .get("x-csrf-token")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        *self.csrf_token.lock().unwrap() = token;
+        Ok(())
     }
 
-    async fn fetch(&self, url: &str, context: &str) -> Result<reqwest::Response> {
+    /// Attach credentials: the API key header, or the session's CSRF token (cookies are automatic)
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.auth {
+            Auth::ApiKey(key) => req.header("X-API-Key", key),
+            Auth::Password { .. } => match self.csrf_token.lock().unwrap().as_deref() {
+                Some(token) => req.header("X-CSRF-Token", token),
+                None => req,
+            },
+        }
+    }
+
+    /// Talk to a classic (non-UniFi OS) controller, which has no `/proxy/network` prefix
+    pub fn with_legacy_paths(mut self, legacy: bool) -> Self {
+        self.network_root = if legacy { "" } else { UNIFI_OS_NETWORK_ROOT };
+        self
+    }
+
+    /// Target a site other than `default` (the site's short name, as in the controller URL)
+    pub fn with_site(mut self, site: &str) -> Self {
+        self.site = site.to_string();
+        self
+    }
+
+    /// Record requests to `timings` from now on (see `ClientBuilder::timings`), or stop
+    pub fn with_timings(mut self, timings: Option<Timings>) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// Cache GET responses for `ttl`, so repeated reads share one controller request.
+    /// Successful writes drop the cached responses they make stale.
+    pub fn with_cache(mut self, ttl: Duration) -> Self {
+        self.cache = Some(Arc::new(Cache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }));
+        self
+    }
+
+    /// Drop cached responses, e.g. after changing the controller by other means
+    pub fn invalidate(&self, scope: CacheScope) {
+        let Some(cache) = &self.cache else { return };
+        let mut entries = cache.entries.lock().unwrap();
+        match scope {
+            CacheScope::All => entries.clear(),
+            CacheScope::Endpoint(endpoint) => entries.retain(|url, _| !in_scope(url, endpoint)),
+        }
+    }
+
+    /// Upload a file as multipart form data to a site-scoped v1 path (e.g., `upload/portal-logo`)
+    pub(crate) async fn upload(
+        &self,
+        path: &str,
+        file_name: &str,
+        bytes: Vec<u8>,
+        context: &str,
+    ) -> Result<Value> {
+        let url = self.site_url(path);
+        if self.dry_run {
+            self.report_write(
+                &Method::POST,
+                &url,
+                &format!("(multipart file {file_name}, {} bytes)", bytes.len()),
+            );
+            return Ok(serde_json::json!({"filename": file_name}));
+        }
+        let part = reqwest::multipart::Part::bytes(bytes).file_name(file_name.to_string());
+        let form = reqwest::multipart::Form::new().part("file", part);
+
         let resp = self
-            .http
-            .get(url)
-            .header("X-API-Key", &self.api_key)
-            .send()
+            .execute(
+                self.authorize(self.http.post(&url))
+                    .headers(self.write_headers.clone())
+                    .multipart(form),
+            )
+            .await
+            .map_err(|e| transport_error(e, context))?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(ApiError {
+                context: context.to_string(),
+                status,
+                body,
+            }
+            .into());
+        }
+
+        Ok(Self::first_data(resp.json().await?))
+    }
+
+    /// Hand a write `dry_run` keeps back to the `dry_run_report`
+    fn report_write(&self, method: &Method, url: &str, body: &str) {
+        let mut plan = format!("{method} {url}");
+        for (name, value) in &self.write_headers {
+            plan.push_str(&format!("\n{name}: {}", value.to_str().unwrap_or_default()));
+        }
+        if !body.is_empty() {
+            plan.push('\n');
+            plan.push_str(body);
+        }
+        match &self.dry_run_report {
+            Some(report) => report(&plan),
+            None => tracing::info!("Not sent (dry run): {plan}"),
+        }
+    }
+
+    /// Send a request, tracing it: method, URL, and timing at `-v`, headers (credentials
+    /// redacted) at `-vv`
+    async fn execute(&self, req: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let req = req.build()?;
+        let span = tracing::info_span!("request", method = %req.method(), url = %req.url());
+        self.execute_traced(req).instrument(span).await
+    }
+
+    /// `execute` inside its `request` span
+    async fn execute_traced(&self, req: reqwest::Request) -> reqwest::Result<reqwest::Response> {
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            for (name, value) in req.headers() {
+                let value = match name.as_str() {
+                    "x-api-key" | "x-csrf-token" | "cookie" | "authorization" => "[redacted]",
+                    _ => value.to_str().unwrap_or("[binary]"),
+                };
+                tracing::debug!("> {name}: {value}");
+            }
+        }
+        let sent = self.timings.as_ref().map(|_| {
+            let url = req.url();
+            let path = match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            };
+            (req.method().to_string(), path)
+        });
+        let started = Instant::now();
+        let result = self.transport.execute(req).await;
+        if let (Some(timings), Some((method, path))) = (&self.timings, sent) {
+            timings.record(RequestTiming {
+                method,
+                path,
+                status: result.as_ref().ok().map(|r| r.status().as_u16()),
+                elapsed: started.elapsed(),
+            });
+        }
+        let elapsed = started.elapsed().as_millis();
+        match &result {
+            Ok(resp) => {
+                tracing::info!(
+                    status = resp.status().as_u16(),
+                    elapsed_ms = elapsed as u64,
+                    "{} ({elapsed} ms)",
+                    resp.status()
+                );
+                for (name, value) in resp.headers() {
+                    let value = match name.as_str() {
+                        "set-cookie" | "x-csrf-token" => "[redacted]",
+                        _ => value.to_str().unwrap_or("[binary]"),
+                    };
+                    tracing::debug!("< {name}: {value}");
+                }
+            }
+            Err(err) => tracing::warn!(
+                elapsed_ms = elapsed as u64,
+                "Failed after {elapsed} ms: {err}"
+            ),
+        }
+        result
+    }
+
+    /// Send an authenticated request, turning non-success statuses into `ApiError`; under
+    /// `dry_run`, anything but a GET is only reported
+    pub(crate) async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+        context: &str,
+    ) -> Result<Reply> {
+        if self.dry_run && method != Method::GET {
+            let shown = match body {
+                Some(body) => serde_json::to_string_pretty(body)?,
+                None => String::new(),
+            };
+            self.report_write(&method, url, &shown);
+            // Echo the body the way the endpoint would return the stored object
+            let reply = match body {
+                Some(body) if url.contains("/v2/") => body.clone(),
+                Some(body) => serde_json::json!({"meta": {"rc": "ok"}, "data": [body]}),
+                None => serde_json::json!({"meta": {"rc": "ok"}, "data": []}),
+            };
+            return Ok(Reply::DryRun(reply));
+        }
+        let write = method != Method::GET;
+        let resp = self.transmit(method, url, body, context, write).await?;
+        if write && self.cache.is_some() {
+            match stale_endpoints(url) {
+                Some(endpoints) => {
+                    for endpoint in &endpoints {
+                        self.invalidate(CacheScope::Endpoint(endpoint));
+                    }
+                }
+                None => self.invalidate(CacheScope::All),
+            }
+        }
+        Ok(Reply::Sent(resp))
+    }
+
+    /// A POST that only reads (stat queries, log searches), so it goes out even under `dry_run`
+    pub(crate) async fn query(
+        &self,
+        url: &str,
+        body: &Value,
+        context: &str,
+    ) -> Result<reqwest::Response> {
+        self.transmit(Method::POST, url, Some(body), context, false)
             .await
-            .context(format!("Failed to fetch {context}"))?;
+    }
+
+    /// Send a request; `write` adds the configured write headers
+    async fn transmit(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+        context: &str,
+        write: bool,
+    ) -> Result<reqwest::Response> {
+        let request = || {
+            let mut req = self.authorize(self.http.request(method.clone(), url));
+            if write {
+                req = req.headers(self.write_headers.clone());
+            }
+            match body {
+                Some(body) => req.json(body),
+                None => req,
+            }
+        };
+
+        let mut resp = self
+            .execute(request())
+            .await
+            .map_err(|e| transport_error(e, context))?;
+
+        // Sessions expire (e.g., under `unifi serve`); log in again once and retry
+        if resp.status() == StatusCode::UNAUTHORIZED && matches!(self.auth, Auth::Password { .. }) {
+            tracing::info!("Session expired, logging in again and retrying");
+            self.login().await?;
+            resp = self
+                .execute(request())
+                .await
+                .map_err(|e| transport_error(e, context))?;
+        }
 
         if !resp.status().is_success() {
             let status = resp.status();
             let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to get {context} ({status}): {body}");
+            return Err(ApiError {
+                context: context.to_string(),
+                status,
+                body,
+            }
+            .into());
         }
 
         Ok(resp)
     }
 
+    /// GET a URL and parse the JSON body, going through the cache when enabled
+    async fn fetch(&self, url: &str, context: &str) -> Result<Value> {
+        if let Some(cache) = &self.cache {
+            let entries = cache.entries.lock().unwrap();
+            if let Some((at, value)) = entries.get(url)
+                && at.elapsed() < cache.ttl
+            {
+                tracing::debug!(url, age_ms = at.elapsed().as_millis() as u64, "Cache hit");
+                return Ok(value.clone());
+            }
+        }
+
+        let value: Value = self
+            .send(Method::GET, url, None, &format!("get {context}"))
+            .await?
+            .json()
+            .await
+            .context("Failed to parse response")?;
+
+        if let Some(cache) = &self.cache {
+            let mut entries = cache.entries.lock().unwrap();
+            entries.insert(url.to_string(), (Instant::now(), value.clone()));
+        }
+        Ok(value)
+    }
+
     fn extract_data(body: Value) -> Value {
-        body.get("data").cloned().unwrap_or(Value::Array(vec![]))
+        match body {
+            Value::Object(mut map) => map.remove("data").unwrap_or(Value::Array(vec![])),
+            _ => Value::Array(vec![]),
+        }
+    }
+
+    /// Unwrap the single object from a v1 `{"data": [obj]}` write response
+    pub(crate) fn first_data(body: Value) -> Value {
+        body.get("data")
+            .and_then(|d| d.as_array())
+            .and_then(|a| a.first())
+            .cloned()
+            .unwrap_or(body)
+    }
+
+    /// URL of a path under the Network application root (e.g., `/dl/...` download links)
+    pub(crate) fn network_url(&self, path: &str) -> String {
+        format!(
+            "{}{}/{}",
+            self.base_url,
+            self.network_root,
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// URL of a site-scoped v1 API path (`rest/...`, `stat/...`, `cmd/...`)
+    pub(crate) fn site_url(&self, path: &str) -> String {
+        self.network_url(&format!("api/s/{}/{}", self.site, path))
+    }
+
+    /// URL of a site-scoped v2 API path
+    pub(crate) fn v2_url(&self, path: &str) -> String {
+        self.network_url(&format!("v2/api/site/{}/{}", self.site, path))
+    }
+
+    /// Call an arbitrary endpoint. `path` is relative to the Network application root
+    /// (`api/s/{site}/stat/health`), or to the host when it starts with `/`; `{site}` is
+    /// replaced by the current site. Non-JSON responses come back as a string.
+    pub async fn raw(&self, method: Method, path: &str, body: Option<&Value>) -> Result<Value> {
+        let path = path.replace("{site}", &self.site);
+        let url = match path.strip_prefix('/') {
+            Some(absolute) => format!("{}/{absolute}", self.base_url),
+            None => self.network_url(&path),
+        };
+        let context = format!("{method} {path}");
+        let text = self
+            .send(method, &url, body, &context)
+            .await?
+            .text()
+            .await?;
+        Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+
+    /// Sites this account can access (`name` is the short name used in URLs, `desc` the
+    /// display name)
+    pub async fn get_sites(&self) -> Result<Value> {
+        let url = self.network_url("api/self/sites");
+        let body = self.fetch(&url, "sites").await?;
+        Ok(Self::extract_data(body))
+    }
+
+    /// Resolve a site given by short name, display name, or ID to its short name
+    pub async fn resolve_site(&self, site: &str) -> Result<String> {
+        let sites = self.get_sites().await?;
+        let sites = sites.as_array().map(Vec::as_slice).unwrap_or_default();
+        let field = |s: &Value, k: &str| s.get(k).and_then(|v| v.as_str()).map(String::from);
+        sites
+            .iter()
+            .find(|s| field(s, "name").as_deref() == Some(site))
+            .or_else(|| {
+                sites.iter().find(|s| {
+                    field(s, "desc").is_some_and(|d| d.eq_ignore_ascii_case(site))
+                        || field(s, "_id").as_deref() == Some(site)
+                })
+            })
+            .and_then(|s| field(s, "name"))
+            .ok_or_else(|| NotFound(format!("Site '{site}' not found")).into())
+    }
+
+    /// Download a file the controller generated (relative link such as `/dl/...`)
+    pub(crate) async fn download(&self, path: &str, context: &str) -> Result<Vec<u8>> {
+        let url = self.network_url(path);
+        let resp = self.send(Method::GET, &url, None, context).await?;
+        Ok(resp
+            .bytes()
+            .await
+            .context(format!("Failed to {context}"))?
+            .to_vec())
     }
 
     pub(crate) async fn get_rest(&self, endpoint: &str) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/{}",
-            self.base_url, endpoint
-        );
-        let body: Value = self.fetch(&url, endpoint).await?.json().await?;
+        let url = self.site_url(&format!("rest/{endpoint}"));
+        let body = self.fetch(&url, endpoint).await?;
         Ok(Self::extract_data(body))
     }
 
     pub(crate) async fn get_v2(&self, endpoint: &str) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/v2/api/site/default/{}",
-            self.base_url, endpoint
-        );
-        self.fetch(&url, endpoint)
-            .await?
-            .json()
-            .await
-            .context("Failed to parse response")
+        let url = self.v2_url(endpoint);
+        self.fetch(&url, endpoint).await
     }
 
     pub(crate) async fn get_setting(&self, key: &str) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/setting/{}",
-            self.base_url, key
-        );
-        let body: Value = self
-            .fetch(&url, &format!("setting {key}"))
-            .await?
-            .json()
-            .await?;
+        let url = self.site_url(&format!("rest/setting/{key}"));
+        let body = self.fetch(&url, &format!("setting {key}")).await?;
 
         body.get("data")
             .and_then(|d| d.as_array())
             .and_then(|a| a.first().cloned())
-            .ok_or_else(|| anyhow::anyhow!("Setting '{}' not found", key))
+            .ok_or_else(|| NotFound(format!("Setting '{}' not found", key)).into())
     }
 
     pub(crate) async fn get_stat(&self, endpoint: &str) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/stat/{}",
-            self.base_url, endpoint
-        );
-        let body: Value = self.fetch(&url, endpoint).await?.json().await?;
+        let url = self.site_url(&format!("stat/{endpoint}"));
+        let body = self.fetch(&url, endpoint).await?;
         Ok(Self::extract_data(body))
     }
+
+    /// Update fields of a site setting (e.g., `mgmt`, `super_mgmt`) and return the new value
+    pub(crate) async fn update_setting(
+        &self,
+        key: &str,
+        fields: &serde_json::Map<String, Value>,
+    ) -> Result<Value> {
+        let current = self.get_setting(key).await?;
+        let id = current
+            .get("_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Setting '{key}' has no _id"))?;
+        let mut body = current.clone();
+        if let Some(obj) = body.as_object_mut() {
+            obj.extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+
+        let url = self.site_url(&format!("rest/setting/{key}/{id}"));
+        let resp = self
+            .send(
+                Method::PUT,
+                &url,
+                Some(&body),
+                &format!("update setting {key}"),
+            )
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
+    }
+
+    /// POST a query to a v1 `stat/` endpoint (time-ranged reports, event searches)
+    pub(crate) async fn query_stat(&self, endpoint: &str, query: &Value) -> Result<Value> {
+        let url = self.site_url(&format!("stat/{endpoint}"));
+        let body: Value = self
+            .query(&url, query, &format!("query {endpoint}"))
+            .await?
+            .json()
+            .await
+            .context("Failed to parse response")?;
+        Ok(Self::extract_data(body))
+    }
+
+    /// Create an object in a v1 REST collection and return the created object
+    pub(crate) async fn create_rest(&self, endpoint: &str, body: &Value) -> Result<Value> {
+        let url = self.site_url(&format!("rest/{endpoint}"));
+        let resp = self
+            .send(
+                Method::POST,
+                &url,
+                Some(body),
+                &format!("create {endpoint}"),
+            )
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::ResponseFuture;
+
+    /// Answers requests with scripted statuses and bodies, and records what was sent
+    struct FakeTransport {
+        replies: Mutex<Vec<(u16, Value)>>,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Transport for FakeTransport {
+        fn execute(&self, request: reqwest::Request) -> ResponseFuture<'_> {
+            let line = format!("{} {}", request.method(), request.url().path());
+            self.sent.lock().unwrap().push(line);
+            let (status, body) = self.replies.lock().unwrap().remove(0);
+            let resp = http::Response::builder()
+                .status(status)
+                .body(body.to_string())
+                .unwrap();
+            Box::pin(async move { Ok(resp.into()) })
+        }
+    }
+
+    fn client(
+        builder: ClientBuilder,
+        replies: Vec<(u16, Value)>,
+    ) -> (Client, Arc<Mutex<Vec<String>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let transport = FakeTransport {
+            replies: Mutex::new(replies),
+            sent: sent.clone(),
+        };
+        let client = builder
+            .host("https://unifi.test")
+            .transport(transport)
+            .build()
+            .unwrap();
+        (client, sent)
+    }
+
+    fn users() -> Value {
+        serde_json::json!({"meta": {"rc": "ok"}, "data": [{"name": "laptop"}]})
+    }
+
+    #[tokio::test]
+    async fn expired_session_logs_in_again_and_retries() {
+        let (client, sent) = client(
+            Client::builder().credentials("admin", "secret"),
+            vec![
+                (401, Value::Null),
+                (200, serde_json::json!({})),
+                (200, users()),
+            ],
+        );
+        let clients = client.get_rest("user").await.unwrap();
+        assert_eq!(clients, serde_json::json!([{"name": "laptop"}]));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                "GET /proxy/network/api/s/default/rest/user",
+                "POST /api/auth/login",
+                "GET /proxy/network/api/s/default/rest/user",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn api_key_401_is_not_retried() {
+        let (client, sent) = client(Client::builder().api_key("key"), vec![(401, Value::Null)]);
+        let err = client.get_rest("user").await.unwrap_err();
+        let status = err.downcast_ref::<ApiError>().map(|e| e.status);
+        assert_eq!(status, Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dry_run_sends_reads_but_not_writes() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let report = reported.clone();
+        let (client, sent) = client(
+            Client::builder()
+                .api_key("key")
+                .dry_run(true)
+                .dry_run_report(move |plan| report.lock().unwrap().push(plan.to_string())),
+            vec![(200, users())],
+        );
+        client.get_rest("user").await.unwrap();
+        let body = serde_json::json!({"name": "tv"});
+        let url = client.site_url("rest/user");
+        let reply: Value = client
+            .send(Method::POST, &url, Some(&body), "add user")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(reply["data"][0], body);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            ["GET /proxy/network/api/s/default/rest/user"]
+        );
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 1);
+        assert!(reported[0].starts_with(&format!("POST {url}\n")));
+    }
 }
@@ -0,0 +1,187 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::api::{ApiError, Client};
+use crate::duration;
+
+/// Source networks treated as expected when no `--allow` is given: private and loopback ranges
+pub const PRIVATE_NETWORKS: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "127.0.0.0/8",
+    "fc00::/7",
+    "::1/128",
+];
+
+/// One admin sign-in or API access from the controller's logs
+#[derive(Debug, Serialize)]
+pub struct RemoteAccess {
+    pub time: String,
+    pub admin: Option<String>,
+    pub ip: Option<String>,
+    /// `api-key` when the log entry names an API key, else `session`
+    pub via: &'static str,
+    pub message: String,
+    /// `ok` when the source address is in the allowed networks, else `unexpected`
+    pub status: &'static str,
+}
+
+/// An address prefix such as `10.0.0.0/8`
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    addr: IpAddr,
+    len: u8,
+}
+
+/// Parse `a.b.c.d/n`, an IPv6 prefix, or a bare address (exact match)
+pub fn parse_network(s: &str) -> Result<Network> {
+    let (addr, len) = s.split_once('/').unwrap_or((s, ""));
+    let addr: IpAddr = addr
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid address '{s}'"))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let len = match len {
+        "" => max,
+        len => len
+            .parse()
+            .ok()
+            .filter(|l| *l <= max)
+            .with_context(|| format!("Invalid prefix length in '{s}'"))?,
+    };
+    Ok(Network { addr, len })
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Same prefix, even when written with a host address (`192.168.1.1/24`)
+    pub fn same_prefix(&self, other: &Network) -> bool {
+        self.len == other.len && self.contains(other.addr)
+    }
+}
+
+fn str_of(v: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|k| v.pointer(k).and_then(|v| v.as_str()))
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+}
+
+/// Normalize a v2 system-log entry or a v1 `EVT_AD_*` event
+fn access_entry(entry: &Value, allowed: &[Network]) -> RemoteAccess {
+    let time = entry
+        .get("timestamp")
+        .or_else(|| entry.get("time"))
+        .and_then(|v| v.as_i64())
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|t| t.with_timezone(&chrono::Local).to_rfc3339())
+        .unwrap_or_default();
+    let ip = str_of(
+        entry,
+        &[
+            "/parameters/IP/name",
+            "/parameters/ip/name",
+            "/ip",
+            "/src_ip",
+        ],
+    );
+    let message = str_of(entry, &["/message", "/msg", "/key"]).unwrap_or_default();
+    let via = if message.to_lowercase().contains("api key")
+        || entry.pointer("/parameters/API_KEY").is_some()
+    {
+        "api-key"
+    } else {
+        "session"
+    };
+    let expected = ip
+        .as_deref()
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .is_some_and(|ip| allowed.iter().any(|net| net.contains(ip)));
+    RemoteAccess {
+        time,
+        admin: str_of(
+            entry,
+            &["/parameters/ADMIN/name", "/parameters/USER/name", "/admin"],
+        ),
+        ip,
+        via,
+        message,
+        status: if expected { "ok" } else { "unexpected" },
+    }
+}
+
+impl Client {
+    /// Admin sign-ins and API accesses within `since`, from the admin activity log (UniFi OS)
+    /// or the `EVT_AD_*` events of classic controllers
+    pub async fn get_remote_access(
+        &self,
+        since: Duration,
+        allowed: &[Network],
+    ) -> Result<Vec<RemoteAccess>> {
+        let now = duration::now_secs() * 1000;
+        let from = now - since.as_millis() as i64;
+        let body = json!({
+            "timestampFrom": from,
+            "timestampTo": now,
+            "pageNumber": 0,
+            "pageSize": 1000,
+        });
+        let url = self.v2_url("system-log/admin-activity");
+        let entries = match self.query(&url, &body, "get admin activity").await {
+            Ok(resp) => {
+                let log: Value = resp.json().await?;
+                log.get("data").cloned().unwrap_or(log)
+            }
+            Err(err)
+                if err
+                    .downcast_ref::<ApiError>()
+                    .is_some_and(|e| e.status == StatusCode::NOT_FOUND) =>
+            {
+                let query = json!({"within": since.as_secs() / 3600 + 1, "_limit": 3000});
+                let events = self.query_stat("event", &query).await?;
+                Value::Array(
+                    events
+                        .as_array()
+                        .map(Vec::as_slice)
+                        .unwrap_or_default()
+                        .iter()
+                        .filter(|e| {
+                            e.get("key")
+                                .and_then(|k| k.as_str())
+                                .is_some_and(|k| k.starts_with("EVT_AD_"))
+                                && e.get("time").and_then(|t| t.as_i64()) >= Some(from)
+                        })
+                        .cloned()
+                        .collect(),
+                )
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(entries
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|e| access_entry(e, allowed))
+            .collect())
+    }
+}
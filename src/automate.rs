@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use web_time::Instant;
+
+use crate::api::Client;
+use crate::duration;
+
+/// What a rule watches; each match is reported per target (device or client MAC, or `wan`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// A device has not been seen for longer than this
+    DeviceOffline(Duration),
+    /// A wireless client's signal is below this many dBm
+    ClientRssiBelow(i64),
+    /// WAN packet loss is above this percentage
+    WanLossAbove(f64),
+}
+
+/// Remediation, limited to operations that are safe to repeat
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Restart the device
+    Restart,
+    /// Disconnect the client so it roams to a better AP
+    Kick,
+    /// POST the match as JSON to this URL
+    Webhook(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub text: String,
+    pub condition: Condition,
+    pub action: Action,
+}
+
+/// A condition that currently holds for one target
+#[derive(Debug)]
+pub struct Match {
+    pub target: String,
+    pub name: String,
+    pub value: Value,
+}
+
+/// Parse a rule like `device-offline>5m => restart`, `client-rssi<-80 => kick`, or
+/// `wan-loss>5% => webhook:https://example.com/hook`
+pub fn parse_rule(s: &str) -> Result<Rule> {
+    let (condition, action) = s
+        .split_once("=>")
+        .with_context(|| format!("Expected CONDITION => ACTION, got '{s}'"))?;
+    let condition = condition.trim();
+    let condition = if let Some(d) = condition.strip_prefix("device-offline>") {
+        Condition::DeviceOffline(duration::parse(d)?)
+    } else if let Some(n) = condition.strip_prefix("client-rssi<") {
+        Condition::ClientRssiBelow(
+            n.trim()
+                .parse()
+                .with_context(|| format!("Invalid dBm value '{n}'"))?,
+        )
+    } else if let Some(n) = condition.strip_prefix("wan-loss>") {
+        Condition::WanLossAbove(
+            n.trim()
+                .trim_end_matches('%')
+                .parse()
+                .with_context(|| format!("Invalid percentage '{n}'"))?,
+        )
+    } else {
+        bail!(
+            "Unknown condition '{condition}' \
+             (use device-offline>DURATION, client-rssi<DBM, or wan-loss>PERCENT)"
+        );
+    };
+
+    let action = action.trim();
+    let action = match action {
+        "restart" => Action::Restart,
+        "kick" => Action::Kick,
+        _ => match action.strip_prefix("webhook:") {
+            Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                Action::Webhook(url.to_string())
+            }
+            _ => bail!("Unknown action '{action}' (use restart, kick, or webhook:URL)"),
+        },
+    };
+    match (&condition, &action) {
+        (Condition::DeviceOffline(_), Action::Kick) => bail!("kick only applies to clients"),
+        (Condition::ClientRssiBelow(_), Action::Restart) => {
+            bail!("restart only applies to devices")
+        }
+        (Condition::WanLossAbove(_), Action::Restart | Action::Kick) => {
+            bail!("wan-loss rules can only run a webhook")
+        }
+        _ => {}
+    }
+    Ok(Rule {
+        text: s.trim().to_string(),
+        condition,
+        action,
+    })
+}
+
+fn name_of(record: &Value) -> String {
+    ["name", "hostname", "mac"]
+        .iter()
+        .find_map(|k| record.get(*k)?.as_str().filter(|s| !s.is_empty()))
+        .unwrap_or_default()
+        .to_string()
+}
+
+impl Client {
+    /// Targets for which `condition` currently holds
+    pub async fn evaluate(&self, condition: &Condition) -> Result<Vec<Match>> {
+        let now = duration::now_secs();
+        let matches = match condition {
+            Condition::DeviceOffline(limit) => {
+                let devices = self.get_devices().await?;
+                devices
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|d| d.get("state").and_then(|v| v.as_i64()) != Some(1))
+                    .filter_map(|d| {
+                        let offline = now - d.get("last_seen")?.as_i64()?;
+                        let mac = d.get("mac")?.as_str()?;
+                        (offline > limit.as_secs() as i64).then(|| Match {
+                            target: mac.to_string(),
+                            name: name_of(d),
+                            value: json!(duration::humanize(offline)),
+                        })
+                    })
+                    .collect()
+            }
+            Condition::ClientRssiBelow(limit) => {
+                let online = self.get_clients_online().await?;
+                online
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|c| c.get("is_wired").and_then(|v| v.as_bool()) != Some(true))
+                    .filter_map(|c| {
+                        let signal = c.get("signal")?.as_i64()?;
+                        let mac = c.get("mac")?.as_str()?;
+                        (signal < *limit).then(|| Match {
+                            target: mac.to_string(),
+                            name: name_of(c),
+                            value: json!(signal),
+                        })
+                    })
+                    .collect()
+            }
+            Condition::WanLossAbove(limit) => {
+                let loss = self.get_wan_loss().await?;
+                if loss > *limit {
+                    vec![Match {
+                        target: "wan".into(),
+                        name: "WAN".into(),
+                        value: json!(loss),
+                    }]
+                } else {
+                    vec![]
+                }
+            }
+        };
+        Ok(matches)
+    }
+
+    /// WAN packet loss in percent: the complement of the gateway's reported WAN availability,
+    /// or 100 while the internet check fails
+    async fn get_wan_loss(&self) -> Result<f64> {
+        let health = self.get_health().await?;
+        let subsystem = |name: &str| {
+            health.as_array().and_then(|a| {
+                a.iter()
+                    .find(|s| s.get("subsystem").and_then(|v| v.as_str()) == Some(name))
+            })
+        };
+        let www_down = subsystem("www")
+            .and_then(|s| s.get("status"))
+            .and_then(|v| v.as_str())
+            .is_some_and(|s| s != "ok");
+        if www_down {
+            return Ok(100.0);
+        }
+        let availability = subsystem("wan")
+            .and_then(|s| s.pointer("/uptime_stats/WAN/availability"))
+            .and_then(|v| v.as_f64());
+        Ok(availability.map_or(0.0, |a| (100.0 - a).max(0.0)))
+    }
+
+    /// Run a rule's action for one match
+    pub async fn remediate(&self, rule: &Rule, m: &Match) -> Result<()> {
+        match &rule.action {
+            Action::Restart => self.restart_device(&m.target).await,
+            Action::Kick => self.kick_client(&m.target).await,
+            Action::Webhook(url) => {
+                let body = json!({
+                    "rule": rule.text,
+                    "target": m.target,
+                    "name": m.name,
+                    "value": m.value,
+                    "time": chrono::Local::now().to_rfc3339(),
+                });
+                reqwest::Client::new()
+                    .post(url)
+                    .json(&body)
+                    .timeout(Duration::from_secs(10))
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .with_context(|| format!("Webhook {url} failed"))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Remembers when each (rule, target) last fired so a flapping target is not acted on in a loop
+pub struct Cooldown {
+    period: Duration,
+    fired: HashMap<(usize, String), Instant>,
+}
+
+impl Cooldown {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            fired: HashMap::new(),
+        }
+    }
+
+    /// True if the action may run now; records it as fired
+    pub fn ready(&mut self, rule: usize, target: &str) -> bool {
+        let key = (rule, target.to_string());
+        match self.fired.get(&key) {
+            Some(at) if at.elapsed() < self.period => false,
+            _ => {
+                self.fired.insert(key, Instant::now());
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documented_rules() {
+        let rule = parse_rule("device-offline>5m => restart").unwrap();
+        assert_eq!(
+            rule.condition,
+            Condition::DeviceOffline(Duration::from_secs(300))
+        );
+        assert_eq!(rule.action, Action::Restart);
+        assert_eq!(rule.text, "device-offline>5m => restart");
+
+        let rule = parse_rule("client-rssi<-80 => kick").unwrap();
+        assert_eq!(rule.condition, Condition::ClientRssiBelow(-80));
+        assert_eq!(rule.action, Action::Kick);
+
+        let rule = parse_rule(" wan-loss>5% => webhook:https://example.com/hook?a=b ").unwrap();
+        assert_eq!(rule.condition, Condition::WanLossAbove(5.0));
+        assert_eq!(
+            rule.action,
+            Action::Webhook("https://example.com/hook?a=b".into())
+        );
+        assert_eq!(
+            rule.text,
+            "wan-loss>5% => webhook:https://example.com/hook?a=b"
+        );
+    }
+
+    #[test]
+    fn percent_sign_is_optional() {
+        let rule = parse_rule("wan-loss>2.5=>webhook:http://hook.lan").unwrap();
+        assert_eq!(rule.condition, Condition::WanLossAbove(2.5));
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        for bad in [
+            "device-offline>5m restart",
+            "device-offline>soon => restart",
+            "client-rssi<weak => kick",
+            "wan-loss>much% => webhook:https://example.com",
+            "cpu>90 => restart",
+            "device-offline>5m => reboot",
+            "wan-loss>5% => webhook:ftp://example.com",
+            "device-offline>5m => kick",
+            "client-rssi<-80 => restart",
+            "wan-loss>5% => kick",
+        ] {
+            assert!(parse_rule(bad).is_err(), "accepted {bad:?}");
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Synchronous wrapper around `api::Client` for scripts and build scripts (`blocking` feature).
+//!
+//! Each `Client` owns a single-threaded tokio runtime and blocks on it for every call, so it
+//! must not be used from inside another async runtime.
+
+use std::future::Future;
+
+use anyhow::Result;
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::api;
+use crate::overview::Overview;
+use crate::search::Hit;
+
+pub struct Client {
+    inner: api::Client,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Client {
+    /// Wrap an async client built with `api::Client::builder()`; logs in for password auth
+    pub fn new(inner: api::Client) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(inner.login())?;
+        Ok(Self { inner, runtime })
+    }
+
+    /// The async client, for calls without a wrapper here: `c.block_on(c.inner().get_health())`
+    pub fn inner(&self) -> &api::Client {
+        &self.inner
+    }
+
+    /// Run any future on this client's runtime
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn get_sites(&self) -> Result<Value> {
+        self.block_on(self.inner.get_sites())
+    }
+
+    pub fn get_devices(&self) -> Result<Value> {
+        self.block_on(self.inner.get_devices())
+    }
+
+    pub fn get_clients_online(&self) -> Result<Value> {
+        self.block_on(self.inner.get_clients_online())
+    }
+
+    pub fn get_clients_all(&self) -> Result<Value> {
+        self.block_on(self.inner.get_clients_all())
+    }
+
+    pub fn get_networks(&self) -> Result<Value> {
+        self.block_on(self.inner.get_networks())
+    }
+
+    pub fn get_wifi(&self) -> Result<Value> {
+        self.block_on(self.inner.get_wifi())
+    }
+
+    pub fn get_health(&self) -> Result<Value> {
+        self.block_on(self.inner.get_health())
+    }
+
+    pub fn overview(&self) -> Result<Overview> {
+        self.block_on(self.inner.overview())
+    }
+
+    pub fn search(&self, term: &str) -> Result<Vec<Hit>> {
+        self.block_on(self.inner.search(term))
+    }
+
+    /// See `api::Client::raw`
+    pub fn raw(&self, method: Method, path: &str, body: Option<&Value>) -> Result<Value> {
+        self.block_on(self.inner.raw(method, path, body))
+    }
+}
@@ -1,7 +1,134 @@
-use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::Method;
 use serde_json::Value;
 
-use crate::api::Client;
+use crate::api::{Client, NotFound};
+use crate::duration;
+use crate::output;
+
+/// Clients per `stat/alluser` request when listing offline clients
+const OFFLINE_PAGE_SIZE: usize = 500;
+
+/// Normalize a MAC address to lowercase, colon-separated form
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+/// Columns of `clients export` when `--columns` is not given
+pub const EXPORT_COLUMNS: &[&str] = &[
+    "name",
+    "mac",
+    "ip",
+    "network",
+    "first_seen",
+    "last_seen",
+    "total_rx",
+    "total_tx",
+];
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Comma-separated, with a header line
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+/// Value of an export column; well-known names map to controller fields, anything else is
+/// looked up as a (dotted) field
+fn export_field(client: &Value, column: &str) -> Value {
+    let first = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| {
+                client
+                    .get(*k)
+                    .filter(|v| !v.is_null() && v.as_str() != Some(""))
+            })
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    let time = |key: &str| {
+        client
+            .get(key)
+            .and_then(|v| v.as_i64())
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .map(|t| Value::String(t.to_rfc3339()))
+            .unwrap_or(Value::Null)
+    };
+    match column {
+        "name" => first(&["name", "hostname", "mac"]),
+        "ip" => first(&["last_ip", "ip", "fixed_ip"]),
+        "network" => first(&["last_connection_network_name", "network"]),
+        "first_seen" | "last_seen" => time(column),
+        "total_rx" => first(&["rx_bytes"]),
+        "total_tx" => first(&["tx_bytes"]),
+        other => other
+            .split('.')
+            .try_fold(client, |v, key| v.get(key))
+            .cloned()
+            .unwrap_or(Value::Null),
+    }
+}
+
+/// CSV header line for an export
+pub fn export_header(columns: &[String]) -> String {
+    let fields: Vec<String> = columns.iter().map(|c| output::csv_field(c)).collect();
+    fields.join(",")
+}
+
+/// One export line (without the newline) for a client record
+pub fn export_line(client: &Value, columns: &[String], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => {
+            let fields: Vec<String> = columns
+                .iter()
+                .map(|col| match export_field(client, col) {
+                    Value::Null => String::new(),
+                    Value::String(s) => output::csv_field(&s),
+                    v => output::csv_field(&v.to_string()),
+                })
+                .collect();
+            fields.join(",")
+        }
+        ExportFormat::Jsonl => {
+            let obj = columns
+                .iter()
+                .map(|col| (col.clone(), export_field(client, col)))
+                .collect();
+            Value::Object(obj).to_string()
+        }
+    }
+}
+
+fn last_seen(client: &Value) -> Option<i64> {
+    client.get("last_seen").and_then(|v| v.as_i64())
+}
+
+/// Add `last_seen_ago`, `last_ip` and `last_uplink` to a known-client record
+fn enrich_offline(mut client: Value, now: i64) -> Value {
+    let str_field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| client.get(*k).and_then(|v| v.as_str()))
+            .filter(|s| !s.is_empty())
+            .map(|s| Value::String(s.to_string()))
+            .unwrap_or(Value::Null)
+    };
+    let last_ip = str_field(&["last_ip", "ip", "fixed_ip"]);
+    let last_uplink = str_field(&["last_uplink_name", "last_uplink_mac", "last_ap_mac"]);
+    let last_seen_ago = last_seen(&client)
+        .map(|seen| Value::String(format!("{} ago", duration::humanize(now - seen))))
+        .unwrap_or(Value::Null);
+
+    if let Some(obj) = client.as_object_mut() {
+        obj.insert("last_seen_ago".into(), last_seen_ago);
+        obj.insert("last_ip".into(), last_ip);
+        obj.insert("last_uplink".into(), last_uplink);
+    }
+    client
+}
 
 impl Client {
     /// Get online clients
@@ -9,62 +136,162 @@ impl Client {
         self.get_stat("sta").await
     }
 
+    /// Online wireless clients on an SSID and/or an AP (by name or MAC); both match
+    /// case-insensitively, and the AP name is added to each client as `ap_name`
+    pub async fn get_clients_on(&self, ssid: Option<&str>, ap: Option<&str>) -> Result<Vec<Value>> {
+        let (online, ap_names) = tokio::try_join!(self.get_clients_online(), self.get_ap_names())?;
+        let ap_mac = match ap {
+            Some(ap) => Some(
+                ap_names
+                    .iter()
+                    .find(|(mac, name)| name.eq_ignore_ascii_case(ap) || **mac == normalize_mac(ap))
+                    .map(|(mac, _)| mac.clone())
+                    .ok_or_else(|| NotFound(format!("No AP named '{ap}'")))?,
+            ),
+            None => None,
+        };
+        let Value::Array(online) = online else {
+            return Ok(vec![]);
+        };
+        Ok(online
+            .into_iter()
+            .filter(|c| {
+                let field = |k: &str| c.get(k).and_then(|v| v.as_str());
+                field("ap_mac").is_some()
+                    && ssid.is_none_or(|ssid| {
+                        field("essid").is_some_and(|e| e.eq_ignore_ascii_case(ssid))
+                    })
+                    && ap_mac
+                        .as_deref()
+                        .is_none_or(|ap| field("ap_mac") == Some(ap))
+            })
+            .map(|mut c| {
+                let name = c
+                    .get("ap_mac")
+                    .and_then(|v| v.as_str())
+                    .and_then(|mac| ap_names.get(mac))
+                    .cloned();
+                if let (Some(name), Some(obj)) = (name, c.as_object_mut()) {
+                    obj.insert("ap_name".into(), Value::String(name));
+                }
+                c
+            })
+            .collect())
+    }
+
     /// Get all known clients
     pub async fn get_clients_all(&self) -> Result<Value> {
         self.get_rest("user").await
     }
 
-    /// Get offline clients (all known minus online)
-    pub async fn get_clients_offline(&self) -> Result<Value> {
-        let all = self.get_clients_all().await?;
+    /// Get offline clients (all known minus online), enriched with last-seen details.
+    ///
+    /// When `not_seen_for` is given, only clients not seen for at least that long are returned.
+    /// The client history is paged through `stat/alluser` and filtered page by page, so only
+    /// the offline clients are held in memory, never the whole history.
+    pub async fn get_clients_offline(&self, not_seen_for: Option<Duration>) -> Result<Value> {
         let online = self.get_clients_online().await?;
-
-        let online_macs: std::collections::HashSet<String> = online
+        let online_macs: HashSet<&str> = online
             .as_array()
             .map(|arr| {
                 arr.iter()
-                    .filter_map(|c| c.get("mac").and_then(|m| m.as_str()).map(|s| s.to_string()))
+                    .filter_map(|c| c.get("mac").and_then(|m| m.as_str()))
                     .collect()
             })
             .unwrap_or_default();
 
-        let offline: Vec<Value> = all
-            .as_array()
-            .map(|arr| {
-                arr.iter()
+        let now = duration::now_secs();
+        let cutoff = not_seen_for.map(|d| now - d.as_secs() as i64);
+
+        let mut offline = Vec::new();
+        self.export_clients(OFFLINE_PAGE_SIZE, |page| {
+            offline.extend(
+                page.iter()
                     .filter(|c| {
                         c.get("mac")
                             .and_then(|m| m.as_str())
-                            .map(|mac| !online_macs.contains(mac))
-                            .unwrap_or(true)
+                            .is_none_or(|mac| !online_macs.contains(mac))
                     })
-                    .cloned()
-                    .collect()
-            })
-            .unwrap_or_default();
-
+                    .filter(|c| match cutoff {
+                        Some(cutoff) => last_seen(c).is_none_or(|seen| seen <= cutoff),
+                        None => true,
+                    })
+                    .map(|c| enrich_offline(c.clone(), now)),
+            );
+            Ok(())
+        })
+        .await?;
         Ok(Value::Array(offline))
     }
 
+    /// Page through every client the controller has ever seen, with lifetime traffic totals,
+    /// handing each page to `on_page` as it arrives. Returns the number of clients.
+    pub async fn export_clients(
+        &self,
+        page_size: usize,
+        mut on_page: impl FnMut(&[Value]) -> Result<()>,
+    ) -> Result<usize> {
+        let mut start = 0;
+        let mut previous_first: Option<Value> = None;
+        loop {
+            let query = serde_json::json!({
+                "type": "all",
+                "conn": "all",
+                // Look-back in hours; large enough to cover the whole history
+                "within": 24 * 365 * 100,
+                "_start": start,
+                "_limit": page_size,
+            });
+            let page = self.query_stat("alluser", &query).await?;
+            let page = page.as_array().map(Vec::as_slice).unwrap_or_default();
+            // Controllers that ignore `_start` return the same page again
+            let first = page.first().and_then(|c| c.get("mac")).cloned();
+            if page.is_empty() || (previous_first.is_some() && first == previous_first) {
+                break;
+            }
+            on_page(page)?;
+            start += page.len();
+            // Also covers controllers that ignore `_limit` and return everything at once
+            if page.len() != page_size {
+                break;
+            }
+            previous_first = first;
+        }
+        Ok(start)
+    }
+
     /// Kick a client by MAC address (forces reconnect)
     pub async fn kick_client(&self, mac: &str) -> Result<()> {
-        let url = format!("{}/proxy/network/api/s/default/cmd/stamgr", self.base_url);
-
-        let resp = self
-            .http
-            .post(&url)
-            .header("X-API-Key", &self.api_key)
-            .json(&serde_json::json!({"cmd": "kick-sta", "mac": mac}))
-            .send()
+        self.station_command("kick-sta", mac, "kick client").await
+    }
+
+    /// Block a client by MAC address (disconnects it and prevents reconnecting)
+    pub async fn block_client(&self, mac: &str) -> Result<()> {
+        self.station_command("block-sta", mac, "block client").await
+    }
+
+    /// Unblock a previously blocked client
+    pub async fn unblock_client(&self, mac: &str) -> Result<()> {
+        self.station_command("unblock-sta", mac, "unblock client")
             .await
-            .context("Failed to kick client")?;
+    }
 
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to kick client ({}): {}", status, body);
+    /// Forget clients (removes their history and settings from the controller)
+    pub async fn forget_clients(&self, macs: &[String]) -> Result<()> {
+        let url = self.site_url("cmd/stamgr");
+        // Large forget requests time out on the controller, so send them in batches
+        for batch in macs.chunks(100) {
+            let body = serde_json::json!({"cmd": "forget-sta", "macs": batch});
+            self.send(Method::POST, &url, Some(&body), "forget clients")
+                .await?;
         }
+        Ok(())
+    }
 
+    async fn station_command(&self, cmd: &str, mac: &str, context: &str) -> Result<()> {
+        let url = self.site_url("cmd/stamgr");
+        let body = serde_json::json!({"cmd": cmd, "mac": mac.to_lowercase()});
+        self.send(Method::POST, &url, Some(&body), context).await?;
         Ok(())
     }
 }
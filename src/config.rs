@@ -1,37 +1,607 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::output::Format;
+use crate::secret;
+use crate::style::ColorChoice;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Config {
     pub host: Option<String>,
     pub api_key: Option<String>,
+    /// Local account for session (cookie) auth, for controllers without API keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Classic self-hosted controller (no `/proxy/network` prefix); detected when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy: Option<bool>,
+    /// API keys restricted to one site, by site name as given to `--site`; used instead of
+    /// `api_key` for that site
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sites: BTreeMap<String, String>,
+    /// How the controller certificate is checked
+    #[serde(default, skip_serializing_if = "Tls::is_empty")]
+    pub tls: Tls,
+    /// Defaults for global flags; flags given on the command line win
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
+    /// Commands run by `unifi hooks run` when matching controller events arrive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+    /// Additional named controllers, addressable as `profile:site`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Friendly names for client and device MACs (or IDs), accepted wherever one is expected
+    #[serde(default, rename = "alias", skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Headers added to every request that changes something (e.g., `X-Change-Ticket`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub write_headers: BTreeMap<String, String>,
+    /// Table columns per command, keyed by the subcommand path joined with `_` (e.g.,
+    /// `clients_online`); `--fields` still wins
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub columns: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Defaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Format>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorChoice>,
+    /// Page long output on a terminal (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pager: Option<bool>,
+    /// IANA timezone for displayed times (e.g., `Europe/Paris`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Site used when no other is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// Seconds before a controller request is given up, 0 for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl Defaults {
+    fn is_empty(&self) -> bool {
+        self.output.is_none()
+            && self.color.is_none()
+            && self.pager.is_none()
+            && self.timezone.is_none()
+            && self.site.is_none()
+            && self.timeout.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Tls {
+    /// Accept any certificate, e.g. a console's self-signed one (default false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insecure: Option<bool>,
+    /// PEM file with the CA certificate(s) that issued the controller's, trusted besides the
+    /// system roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    /// SHA-256 fingerprint of the controller certificate (as `unifi doctor` shows it); only
+    /// that certificate is accepted, self-signed or not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+impl Tls {
+    fn is_empty(&self) -> bool {
+        self.insecure.is_none() && self.ca_file.is_none() && self.fingerprint.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hook {
+    /// Event key (e.g., `EVT_WU_Disconnected`); a trailing `*` matches a prefix
+    pub event: String,
+    /// Shell command; receives the event JSON on stdin
+    pub run: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legacy: Option<bool>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sites: BTreeMap<String, String>,
+    /// Site used with this profile when `--site` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    /// Output format with this profile when `--output` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Format>,
+    #[serde(default, skip_serializing_if = "Tls::is_empty")]
+    pub tls: Tls,
+}
+
+impl Config {
+    /// The connection settings of a named entry of `profiles`, or the top-level ones
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            None => Ok(Profile {
+                host: self.host.clone(),
+                api_key: self.api_key.clone(),
+                username: self.username.clone(),
+                password: self.password.clone(),
+                legacy: self.legacy,
+                sites: self.sites.clone(),
+                site: None,
+                output: None,
+                tls: self.tls.clone(),
+            }),
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Profile '{name}' not found in config")),
+        }
+    }
+
+    /// Store connection settings as a named profile, or as the top-level ones
+    pub fn set_profile(&mut self, name: Option<&str>, profile: Profile) {
+        match name {
+            None => {
+                self.host = profile.host;
+                self.api_key = profile.api_key;
+                self.username = profile.username;
+                self.password = profile.password;
+                self.legacy = profile.legacy;
+                self.sites = profile.sites;
+                self.tls = profile.tls;
+            }
+            Some(name) => {
+                self.profiles.insert(name.to_string(), profile);
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// The API key for `site`: its `sites` entry (case-insensitive), else `api_key`
+    pub fn api_key_for(&self, site: Option<&str>) -> Option<String> {
+        site.and_then(|site| {
+            self.sites
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(site))
+                .map(|(_, key)| key.clone())
+        })
+        .or_else(|| self.api_key.clone())
+    }
+
+    /// Whether any credential is stored encrypted (see `secret::encrypt`)
+    pub fn is_encrypted(&self) -> bool {
+        self.secrets().any(|s| secret::is_encrypted(s))
+    }
+
+    /// Encrypt the API keys and password that are still in plaintext
+    pub fn encrypt(&mut self, passphrase: &str) -> Result<()> {
+        for value in self.secrets_mut() {
+            if !secret::is_encrypted(value) {
+                *value = secret::encrypt(value, passphrase)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decrypt the API keys and password that are encrypted
+    pub fn decrypt(&mut self, passphrase: &str) -> Result<()> {
+        for value in self.secrets_mut() {
+            if secret::is_encrypted(value) {
+                *value = secret::decrypt(value, passphrase)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn secrets(&self) -> impl Iterator<Item = &String> {
+        self.api_key
+            .iter()
+            .chain(&self.password)
+            .chain(self.sites.values())
+    }
+
+    fn secrets_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.api_key
+            .iter_mut()
+            .chain(&mut self.password)
+            .chain(self.sites.values_mut())
+    }
+}
+
+/// Where to connect, after merging (highest first) command-line flags, `UNIFI_*` environment
+/// variables, the selected profile, and `defaults`
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub profile: Profile,
+    pub site: Option<String>,
+}
+
+impl Settings {
+    /// Use `--host` and `--api-key` for this run; an API key also replaces per-site keys
+    pub fn with_overrides(mut self, host: Option<&str>, api_key: Option<&str>) -> Self {
+        if let Some(host) = host {
+            self.profile.host = Some(host.to_string());
+        }
+        if let Some(key) = api_key {
+            self.profile.api_key = Some(key.to_string());
+            self.profile.sites.clear();
+        }
+        self
+    }
+}
+
+/// A non-empty environment variable
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
 }
 
-fn config_dir() -> PathBuf {
-    dirs::config_dir()
+/// `--profile`, else `UNIFI_PROFILE`
+pub fn profile_name(flag: Option<&str>) -> Option<String> {
+    flag.map(String::from).or_else(|| env("UNIFI_PROFILE"))
+}
+
+/// `UNIFI_PASSPHRASE`, for encrypted credentials without a prompt
+pub fn passphrase_env() -> Option<String> {
+    env("UNIFI_PASSPHRASE")
+}
+
+impl Config {
+    /// Merge flags (`--profile`, `--site`), `UNIFI_PROFILE`, `UNIFI_HOST`, `UNIFI_API_KEY`,
+    /// and `UNIFI_SITE` over this config. `UNIFI_API_KEY` replaces per-site keys too.
+    pub fn settings(&self, profile: Option<&str>, site: Option<&str>) -> Result<Settings> {
+        let profile_name = profile_name(profile);
+        let mut profile = self.profile(profile_name.as_deref())?;
+        if let Some(host) = env("UNIFI_HOST") {
+            profile.host = Some(host);
+        }
+        if let Some(key) = env("UNIFI_API_KEY") {
+            profile.api_key = Some(key);
+            profile.sites.clear();
+        }
+        let site = site
+            .map(String::from)
+            .or_else(|| env("UNIFI_SITE"))
+            .or_else(|| profile.site.clone())
+            .or_else(|| self.defaults.site.clone());
+        Ok(Settings { profile, site })
+    }
+
+    /// The output format when `--output` is not given: the selected profile's (`--profile`,
+    /// else `UNIFI_PROFILE`), else `defaults.output`
+    pub fn output(&self, profile: Option<&str>) -> Option<Format> {
+        profile_name(profile)
+            .and_then(|name| self.profiles.get(&name))
+            .and_then(|p| p.output)
+            .or(self.defaults.output)
+    }
+}
+
+/// Per-request limit when neither `--timeout` nor `defaults.timeout` is given
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// One effective setting and where it came from, for `unifi config show`
+#[derive(Debug, Serialize)]
+pub struct Origin {
+    pub setting: &'static str,
+    pub value: Value,
+    pub source: String,
+}
+
+fn origin(setting: &'static str, value: impl Into<Value>, source: impl Into<String>) -> Origin {
+    Origin {
+        setting,
+        value: value.into(),
+        source: source.into(),
+    }
+}
+
+/// The first of `candidates` that is set, as `(value, source)`
+fn pick<T>(candidates: impl IntoIterator<Item = (Option<T>, String)>) -> Option<(T, String)> {
+    candidates
+        .into_iter()
+        .find_map(|(value, source)| Some((value?, source)))
+}
+
+/// A setting with a flag, a `defaults` entry, and a built-in value, highest first
+fn layered<T: Serialize>(
+    setting: &'static str,
+    flag: (Option<T>, String),
+    config: (Option<T>, String),
+    builtin: T,
+) -> Origin {
+    let (value, source) = pick([flag, config]).unwrap_or((builtin, "built-in".to_string()));
+    origin(
+        setting,
+        serde_json::to_value(value).unwrap_or_default(),
+        source,
+    )
+}
+
+impl Config {
+    /// The settings a command would run with and where each comes from, merging (highest
+    /// first) the global flags in `flags`/`host`/`api_key`/`flags_insecure`, `UNIFI_*`
+    /// environment variables, the selected profile, and `defaults`, this config having been
+    /// read from `file`. Secrets are masked.
+    pub fn explain(
+        &self,
+        file: &Path,
+        flags: &Defaults,
+        profile: Option<&str>,
+        host: Option<&str>,
+        api_key: Option<&str>,
+        flags_insecure: bool,
+    ) -> Result<Vec<Origin>> {
+        let name = profile_name(profile);
+        let p = self.profile(name.as_deref())?;
+        let in_profile = match &name {
+            Some(name) => format!("config [profiles.{name}]"),
+            None => "config".to_string(),
+        };
+        let flag = |f: &str| format!("flag --{f}");
+        let var = |v: &str| format!("env {v}");
+        let defaults = |key: &str| format!("config [defaults] {key}");
+        let unset = || (Value::Null, String::new());
+        let text = |(value, source): (String, String)| (Value::String(value), source);
+
+        let mut out = vec![origin(
+            "file",
+            file.display().to_string(),
+            if file.exists() { "found" } else { "missing" },
+        )];
+        let (value, source) = match (profile, &name) {
+            (Some(flag_value), _) => (Value::from(flag_value), flag("profile")),
+            (None, Some(name)) => (Value::from(name.as_str()), var("UNIFI_PROFILE")),
+            (None, None) => (Value::Null, "top-level settings".to_string()),
+        };
+        out.push(origin("profile", value, source));
+
+        let (value, source) = pick([
+            (host.map(String::from), flag("host")),
+            (env("UNIFI_HOST"), var("UNIFI_HOST")),
+            (p.host.clone(), in_profile.clone()),
+        ])
+        .map_or_else(unset, text);
+        out.push(origin("host", value, source));
+
+        let site = pick([
+            (flags.site.clone(), flag("site")),
+            (env("UNIFI_SITE"), var("UNIFI_SITE")),
+            (p.site.clone(), in_profile.clone()),
+            (self.defaults.site.clone(), defaults("site")),
+        ]);
+        let site_key = site.as_ref().and_then(|(site, _)| {
+            p.sites
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(site))
+                .map(|(name, key)| (key.clone(), format!("{in_profile} sites.{name}")))
+        });
+        let key = match (api_key, env("UNIFI_API_KEY"), site_key) {
+            (Some(key), _, _) => Some((key.to_string(), flag("api-key"))),
+            (None, Some(key), _) => Some((key, var("UNIFI_API_KEY"))),
+            (None, None, Some(site_key)) => Some(site_key),
+            (None, None, None) => p.api_key.clone().map(|key| (key, in_profile.clone())),
+        };
+        let masked = |(value, source): (String, String)| (secret::mask(&value), source);
+        let (value, source) = key.map(masked).map_or_else(unset, text);
+        out.push(origin("api_key", value, source));
+        let (value, source) = p
+            .username
+            .clone()
+            .map(|user| (user, in_profile.clone()))
+            .map_or_else(unset, text);
+        out.push(origin("username", value, source));
+        let (value, source) = p
+            .password
+            .clone()
+            .map(|pass| (pass, in_profile.clone()))
+            .map(masked)
+            .map_or_else(unset, text);
+        out.push(origin("password", value, source));
+        out.push(match p.legacy {
+            Some(legacy) => origin("legacy", legacy, in_profile.clone()),
+            None => origin("legacy", Value::Null, "detected on connect"),
+        });
+        let (value, source) =
+            site.map_or_else(|| (Value::from("default"), "built-in".to_string()), text);
+        out.push(origin("site", value, source));
+        let (value, source) = pick([
+            (flags_insecure.then_some(true), flag("insecure")),
+            (p.tls.insecure, format!("{in_profile} [tls]")),
+        ])
+        .unwrap_or((false, "built-in".to_string()));
+        out.push(origin("tls.insecure", value, source));
+        let (value, source) = p
+            .tls
+            .ca_file
+            .as_ref()
+            .map(|path| (path.display().to_string(), format!("{in_profile} [tls]")))
+            .map_or_else(unset, text);
+        out.push(origin("tls.ca_file", value, source));
+        let (value, source) = p
+            .tls
+            .fingerprint
+            .clone()
+            .map(|pin| (pin, format!("{in_profile} [tls]")))
+            .map_or_else(unset, text);
+        out.push(origin("tls.fingerprint", value, source));
+
+        let (value, source) = pick([
+            (flags.output, flag("output")),
+            (p.output, in_profile.clone()),
+            (self.defaults.output, defaults("output")),
+        ])
+        .unwrap_or((Format::default(), "built-in".to_string()));
+        out.push(origin(
+            "output",
+            serde_json::to_value(value).unwrap_or_default(),
+            source,
+        ));
+        out.push(layered(
+            "color",
+            (flags.color, flag("color/--no-color")),
+            (self.defaults.color, defaults("color")),
+            ColorChoice::default(),
+        ));
+        out.push(layered(
+            "pager",
+            (flags.pager, flag("no-pager")),
+            (self.defaults.pager, defaults("pager")),
+            true,
+        ));
+        // Startup copies `defaults.timezone` into an unset `TZ`, so a matching `TZ` is the config's
+        let (value, source) = match (&self.defaults.timezone, env("TZ")) {
+            (Some(zone), Some(tz)) if tz != *zone => (tz, var("TZ")),
+            (Some(zone), _) => (zone.clone(), defaults("timezone")),
+            (None, Some(tz)) => (tz, var("TZ")),
+            (None, None) => ("local".to_string(), "built-in".to_string()),
+        };
+        out.push(origin("timezone", value, source));
+        out.push(layered(
+            "timeout",
+            (flags.timeout, flag("timeout")),
+            (self.defaults.timeout, defaults("timeout")),
+            DEFAULT_TIMEOUT_SECS,
+        ));
+        Ok(out)
+    }
+}
+
+/// `unifi` under `XDG_CONFIG_HOME` when that is set (on every platform), else under the
+/// platform's config directory
+pub fn config_dir() -> PathBuf {
+    // The XDG spec says to ignore relative paths
+    env("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(dirs::config_dir)
         .unwrap_or_else(|| PathBuf::from("."))
         .join("unifi")
 }
 
-fn config_path() -> PathBuf {
+/// The config file: `--config`, else `UNIFI_CONFIG`, else `config.toml` in `config_dir()`
+pub fn config_path(flag: Option<&Path>) -> PathBuf {
+    flag.map(PathBuf::from)
+        .or_else(|| env("UNIFI_CONFIG").map(PathBuf::from))
+        .unwrap_or_else(default_config_path)
+}
+
+fn default_config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Where the config lived before it moved to TOML
+fn legacy_config_path() -> PathBuf {
     config_dir().join("config.json")
 }
 
-pub fn load_config() -> Result<Config> {
-    let path = config_path();
+/// Read the config at `path` (see `config_path`); a missing file is an empty config
+pub fn load_config(path: &Path) -> Result<Config> {
     if !path.exists() {
         return Ok(Config::default());
     }
-    let content = fs::read_to_string(&path)?;
-    Ok(serde_json::from_str(&content)?)
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Convert a `config.json` left by an older version to `config.toml` at `path`, once, and
+/// say so; the JSON file is kept as `config.json.bak`. Only the default location can have an
+/// older `config.json` beside it.
+pub fn migrate_json_config(path: &Path) -> Result<Option<String>> {
+    let json_path = legacy_config_path();
+    if path.exists() || path != default_config_path() || !json_path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&json_path)?;
+    let config: Config = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", json_path.display()))?;
+    save_config(&config, path)?;
+    fs::rename(&json_path, json_path.with_extension("json.bak"))?;
+    Ok(Some(format!(
+        "Migrated {} to {} (the old file is kept as config.json.bak)",
+        json_path.display(),
+        path.display()
+    )))
 }
 
-pub fn save_config(config: &Config) -> Result<()> {
-    let dir = config_dir();
-    fs::create_dir_all(&dir)?;
-    let path = config_path();
-    fs::write(&path, serde_json::to_string_pretty(config)?)?;
+/// Write the config as TOML to `path`. Comments and layout in an existing file are kept for
+/// the entries that are still there.
+pub fn save_config(config: &Config, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let new: DocumentMut = toml::to_string_pretty(config)?.parse()?;
+    let mut doc = match fs::read_to_string(path) {
+        Ok(content) => content.parse().unwrap_or_default(),
+        Err(_) => DocumentMut::new(),
+    };
+    merge_table(doc.as_table_mut(), new.as_table());
+    fs::write(path, doc.to_string())?;
     Ok(())
 }
+
+/// Make `old` hold the same entries as `new`, keeping the comments and formatting of `old`
+/// where an entry survives
+fn merge_table(old: &mut Table, new: &Table) {
+    let gone: Vec<String> = old
+        .iter()
+        .filter(|(key, _)| !new.contains_key(key))
+        .map(|(key, _)| key.to_string())
+        .collect();
+    for key in gone {
+        old.remove(&key);
+    }
+    for (key, item) in new.iter() {
+        match old.get_mut(key) {
+            Some(existing) => merge_item(existing, item),
+            None => {
+                old.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut Item, new: &Item) {
+    match (old, new) {
+        (Item::Table(old), Item::Table(new)) => merge_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => {
+            let mut merged = ArrayOfTables::new();
+            for (i, table) in new.iter().enumerate() {
+                let mut table = table.clone();
+                if let Some(existing) = old.get(i) {
+                    let mut existing = existing.clone();
+                    merge_table(&mut existing, &table);
+                    table = existing;
+                }
+                merged.push(table);
+            }
+            *old = merged;
+        }
+        (Item::Value(old), Item::Value(new)) => {
+            let decor = old.decor().clone();
+            old.decor_mut().clear();
+            if old.to_string().trim() != new.to_string().trim() {
+                *old = new.clone();
+            }
+            *old.decor_mut() = decor;
+        }
+        (old, new) => *old = new.clone(),
+    }
+}
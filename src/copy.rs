@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::Client;
+
+/// Copyable object types, declared in dependency order (referenced types first)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Resource {
+    UserGroups,
+    Networks,
+    FirewallGroups,
+    Wlans,
+    FirewallRules,
+    PortForwards,
+}
+
+impl Resource {
+    fn endpoint(self) -> &'static str {
+        match self {
+            Resource::UserGroups => "usergroup",
+            Resource::Networks => "networkconf",
+            Resource::FirewallGroups => "firewallgroup",
+            Resource::Wlans => "wlanconf",
+            Resource::FirewallRules => "firewallrule",
+            Resource::PortForwards => "portforward",
+        }
+    }
+
+    /// Objects that only make sense on their own controller (WAN uplinks)
+    fn skip(self, obj: &Value) -> bool {
+        self == Resource::Networks && obj.get("purpose").and_then(|p| p.as_str()) == Some("wan")
+    }
+}
+
+/// ID-bearing fields and the collection their IDs point into
+const REFERENCES: &[(&str, &str)] = &[
+    ("usergroup_id", "usergroup"),
+    ("networkconf_id", "networkconf"),
+    ("src_networkconf_id", "networkconf"),
+    ("dst_networkconf_id", "networkconf"),
+    ("src_firewallgroup_ids", "firewallgroup"),
+    ("dst_firewallgroup_ids", "firewallgroup"),
+    ("wlangroup_id", "wlangroup"),
+    ("ap_group_ids", "apgroups"),
+];
+
+/// Controller-assigned fields that must not be sent when creating the copy
+const STRIP: &[&str] = &[
+    "_id",
+    "site_id",
+    "external_id",
+    "attr_hidden_id",
+    "attr_no_delete",
+    "attr_no_edit",
+];
+
+/// `profile:site` (or just `site` for the default connection)
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub profile: Option<String>,
+    pub site: String,
+}
+
+pub fn parse_location(s: &str) -> Result<Location> {
+    let (profile, site) = match s.split_once(':') {
+        Some((profile, site)) => (Some(profile.to_string()).filter(|p| !p.is_empty()), site),
+        None => (None, s),
+    };
+    anyhow::ensure!(!site.is_empty(), "Expected [PROFILE:]SITE, got '{s}'");
+    Ok(Location {
+        profile,
+        site: site.to_string(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Created,
+    Exists,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CopyResult {
+    pub resource: &'static str,
+    pub name: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn name_of(obj: &Value) -> Option<&str> {
+    obj.get("name").and_then(|n| n.as_str())
+}
+
+fn id_of(obj: &Value) -> Option<&str> {
+    obj.get("_id").and_then(|n| n.as_str())
+}
+
+/// Fetched collections on both sides, so each is requested at most once per run
+struct Collections<'a> {
+    src: &'a Client,
+    dst: &'a Client,
+    src_cache: HashMap<&'static str, Vec<Value>>,
+    dst_cache: HashMap<&'static str, Vec<Value>>,
+}
+
+impl<'a> Collections<'a> {
+    async fn load(client: &Client, endpoint: &str) -> Result<Vec<Value>> {
+        // AP groups only exist in the v2 API
+        let value = if endpoint == "apgroups" {
+            client.get_v2(endpoint).await?
+        } else {
+            client.get_rest(endpoint).await?
+        };
+        Ok(value.as_array().cloned().unwrap_or_default())
+    }
+
+    async fn src(&mut self, endpoint: &'static str) -> Result<&Vec<Value>> {
+        if !self.src_cache.contains_key(endpoint) {
+            let objs = Self::load(self.src, endpoint).await?;
+            self.src_cache.insert(endpoint, objs);
+        }
+        Ok(&self.src_cache[endpoint])
+    }
+
+    async fn dst(&mut self, endpoint: &'static str) -> Result<&mut Vec<Value>> {
+        if !self.dst_cache.contains_key(endpoint) {
+            let objs = Self::load(self.dst, endpoint).await?;
+            self.dst_cache.insert(endpoint, objs);
+        }
+        Ok(self.dst_cache.get_mut(endpoint).unwrap())
+    }
+
+    /// Translate a source-side ID into the ID of the same-named object on the target
+    async fn remap(
+        &mut self,
+        endpoint: &'static str,
+        id: &str,
+        renamed: &[&str],
+        prefix: &str,
+    ) -> Result<String> {
+        let name = self
+            .src(endpoint)
+            .await?
+            .iter()
+            .find(|o| id_of(o) == Some(id))
+            .and_then(name_of)
+            .map(|n| n.to_string())
+            .with_context(|| format!("{endpoint} {id} not found on source"))?;
+
+        let mut candidates = vec![name.clone()];
+        if renamed.contains(&endpoint) {
+            candidates.insert(0, format!("{prefix}{name}"));
+        }
+        let targets = self.dst(endpoint).await?;
+        candidates
+            .iter()
+            .find_map(|n| {
+                targets
+                    .iter()
+                    .find(|o| name_of(o) == Some(n))
+                    .and_then(id_of)
+            })
+            .map(|id| id.to_string())
+            .with_context(|| format!("{endpoint} '{name}' does not exist on target"))
+    }
+}
+
+/// Copy objects from one controller/site to another, matching references by name.
+///
+/// Objects whose (prefixed) name already exists on the target are left untouched.
+pub async fn copy(
+    src: &Client,
+    dst: &Client,
+    resources: &[Resource],
+    rename_prefix: Option<&str>,
+) -> Result<Vec<CopyResult>> {
+    let mut resources = resources.to_vec();
+    resources.sort();
+    resources.dedup();
+
+    let prefix = rename_prefix.unwrap_or_default();
+    let renamed: Vec<&str> = if prefix.is_empty() {
+        vec![]
+    } else {
+        resources.iter().map(|r| r.endpoint()).collect()
+    };
+
+    let mut collections = Collections {
+        src,
+        dst,
+        src_cache: HashMap::new(),
+        dst_cache: HashMap::new(),
+    };
+    let mut results = Vec::new();
+
+    for resource in resources {
+        let endpoint = resource.endpoint();
+        let objects = collections.src(endpoint).await?.clone();
+
+        for obj in objects {
+            let Some(name) = name_of(&obj) else { continue };
+            if resource.skip(&obj)
+                || obj.get("attr_no_delete").and_then(|v| v.as_bool()) == Some(true)
+            {
+                continue;
+            }
+            let new_name = format!("{prefix}{name}");
+            let mut result = CopyResult {
+                resource: endpoint,
+                name: new_name.clone(),
+                status: Status::Exists,
+                id: None,
+                error: None,
+            };
+
+            let existing = collections
+                .dst(endpoint)
+                .await?
+                .iter()
+                .find(|o| name_of(o) == Some(&new_name))
+                .and_then(id_of)
+                .map(|id| id.to_string());
+            if let Some(id) = existing {
+                result.id = Some(id);
+                results.push(result);
+                continue;
+            }
+
+            match prepare(&mut collections, obj, &new_name, &renamed, prefix).await {
+                Ok(body) => match dst.create_rest(endpoint, &body).await {
+                    Ok(created) => {
+                        result.status = Status::Created;
+                        result.id = id_of(&created).map(|id| id.to_string());
+                        collections.dst(endpoint).await?.push(created);
+                    }
+                    Err(err) => {
+                        result.status = Status::Failed;
+                        result.error = Some(format!("{err:#}"));
+                    }
+                },
+                Err(err) => {
+                    result.status = Status::Failed;
+                    result.error = Some(format!("{err:#}"));
+                }
+            }
+            results.push(result);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Strip controller-assigned fields, rename, and remap references for the target
+async fn prepare(
+    collections: &mut Collections<'_>,
+    obj: Value,
+    new_name: &str,
+    renamed: &[&str],
+    prefix: &str,
+) -> Result<Value> {
+    let Value::Object(mut body) = obj else {
+        anyhow::bail!("Unexpected non-object record");
+    };
+    for key in STRIP {
+        body.remove(*key);
+    }
+    body.insert("name".into(), Value::String(new_name.to_string()));
+
+    for (field, endpoint) in REFERENCES {
+        let Some(value) = body.get(*field).cloned() else {
+            continue;
+        };
+        let remapped = match value {
+            Value::String(id) if !id.is_empty() => {
+                Value::String(collections.remap(endpoint, &id, renamed, prefix).await?)
+            }
+            Value::Array(ids) => {
+                let mut out = Vec::with_capacity(ids.len());
+                for id in ids.iter().filter_map(|v| v.as_str()) {
+                    out.push(Value::String(
+                        collections.remap(endpoint, id, renamed, prefix).await?,
+                    ));
+                }
+                Value::Array(out)
+            }
+            other => other,
+        };
+        body.insert(field.to_string(), remapped);
+    }
+
+    Ok(Value::Object(body))
+}
@@ -1,11 +1,215 @@
-use anyhow::Result;
-use serde_json::Value;
+use std::collections::BTreeMap;
 
-use crate::api::Client;
+use anyhow::{Result, bail};
+use reqwest::Method;
+use serde_json::{Map, Value, json};
+
+use crate::api::{Client, NotFound};
+use crate::clients::normalize_mac;
+
+/// Writable per-device settings that `devices export-config` saves and `apply-config` restores
+pub const CONFIG_FIELDS: &[&str] = &[
+    "name",
+    "port_overrides",
+    "radio_table",
+    "config_network",
+    "ethernet_overrides",
+    "led_override",
+    "led_override_color",
+    "led_override_color_brightness",
+    "outdoor_mode_override",
+    "mgmt_network_id",
+    "snmp_contact",
+    "snmp_location",
+    "stp_priority",
+    "stp_version",
+    "jumboframe_enabled",
+    "flowctrl_enabled",
+    "dot1x_portctrl_enabled",
+    "dot1x_fallback_networkconf_id",
+    "switch_vlan_enabled",
+    "lcm_brightness_override",
+    "lcm_night_mode_enabled",
+    "mesh_sta_vap_enabled",
+];
+
+fn text<'a>(device: &'a Value, key: &str) -> &'a str {
+    device.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+/// The writable subset of a device record, with the device it came from:
+/// `{"device": {name, mac, model}, "config": {...}}`
+pub fn export_config(device: &Value) -> Value {
+    let config: Map<String, Value> = CONFIG_FIELDS
+        .iter()
+        .filter_map(|k| Some((k.to_string(), device.get(*k)?.clone())))
+        .collect();
+    json!({
+        "device": {
+            "name": device.get("name"),
+            "mac": device.get("mac"),
+            "model": device.get("model"),
+        },
+        "config": config,
+    })
+}
 
 impl Client {
     /// Get UniFi devices (APs, switches, gateways)
     pub async fn get_devices(&self) -> Result<Value> {
         self.get_stat("device").await
     }
+
+    /// Find a device by name, MAC, or IP address
+    pub async fn find_device(&self, query: &str) -> Result<Value> {
+        let devices = self.get_devices().await?;
+        let mac = normalize_mac(query);
+        devices
+            .as_array()
+            .and_then(|a| {
+                a.iter().find(|d| {
+                    let field = |k: &str| d.get(k).and_then(|v| v.as_str());
+                    field("name") == Some(query)
+                        || field("mac") == Some(mac.as_str())
+                        || field("ip") == Some(query)
+                })
+            })
+            .cloned()
+            .ok_or_else(|| NotFound(format!("Device '{query}' not found")).into())
+    }
+
+    /// Write a saved `export_config` document to `device`. Only `CONFIG_FIELDS` are sent; a
+    /// different model is refused unless `force`, since port and radio layouts differ.
+    pub async fn apply_device_config(
+        &self,
+        device: &Value,
+        exported: &Value,
+        force: bool,
+    ) -> Result<Value> {
+        let Some(config) = exported.get("config").and_then(|v| v.as_object()) else {
+            bail!("Not a device config export (no \"config\" object)");
+        };
+        let saved_model = exported
+            .pointer("/device/model")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let model = text(device, "model");
+        if !force && !saved_model.is_empty() && saved_model != model {
+            bail!(
+                "Config was exported from a {saved_model}, this device is a {model}; \
+                 pass --force to apply it anyway"
+            );
+        }
+        let body: Map<String, Value> = config
+            .iter()
+            .filter(|(k, _)| CONFIG_FIELDS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let id = text(device, "_id");
+        if id.is_empty() {
+            bail!("Device record has no _id");
+        }
+        let url = self.site_url(&format!("rest/device/{id}"));
+        let resp = self
+            .send(
+                Method::PUT,
+                &url,
+                Some(&Value::Object(body)),
+                &format!("apply config to {}", text(device, "mac")),
+            )
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
+    }
+
+    /// Restart a device by MAC address
+    pub async fn restart_device(&self, mac: &str) -> Result<()> {
+        self.devmgr("restart", &normalize_mac(mac)).await?;
+        Ok(())
+    }
+
+    /// MACs of wired clients currently seen on each port of a switch
+    pub async fn get_switch_port_clients(
+        &self,
+        switch_mac: &str,
+    ) -> Result<BTreeMap<u64, Vec<String>>> {
+        let online = self.get_clients_online().await?;
+        let mut ports: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+        for client in online.as_array().map(Vec::as_slice).unwrap_or_default() {
+            if client.get("sw_mac").and_then(|m| m.as_str()) != Some(switch_mac) {
+                continue;
+            }
+            if let (Some(port), Some(mac)) = (
+                client.get("sw_port").and_then(|p| p.as_u64()),
+                client.get("mac").and_then(|m| m.as_str()),
+            ) {
+                ports.entry(port).or_default().push(mac.to_string());
+            }
+        }
+        Ok(ports)
+    }
+}
+
+/// `PORT=MAC` pair for `switch port-watch --expect`
+#[derive(Debug, Clone)]
+pub struct PortExpectation {
+    pub port: u64,
+    pub mac: String,
+}
+
+pub fn parse_port_expectation(s: &str) -> Result<PortExpectation> {
+    let (port, mac) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected PORT=MAC, got '{s}'"))?;
+    Ok(PortExpectation {
+        port: port
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid port number '{port}'"))?,
+        mac: normalize_mac(mac),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortStatus {
+    /// The expected device is on the port
+    Ok,
+    /// Nothing is connected on the port
+    Missing,
+    /// Other devices are on the port instead of the expected one
+    Mismatch,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PortCheck {
+    pub port: u64,
+    pub expected: String,
+    pub connected: Vec<String>,
+    pub status: PortStatus,
+}
+
+/// Compare the clients seen on each port against the expectations
+pub fn check_ports(
+    ports: &BTreeMap<u64, Vec<String>>,
+    expectations: &[PortExpectation],
+) -> Vec<PortCheck> {
+    expectations
+        .iter()
+        .map(|e| {
+            let connected = ports.get(&e.port).cloned().unwrap_or_default();
+            let status = if connected.iter().any(|m| normalize_mac(m) == e.mac) {
+                PortStatus::Ok
+            } else if connected.is_empty() {
+                PortStatus::Missing
+            } else {
+                PortStatus::Mismatch
+            };
+            PortCheck {
+                port: e.port,
+                expected: e.mac.clone(),
+                connected,
+                status,
+            }
+        })
+        .collect()
 }
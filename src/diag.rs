@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{Value, json};
+use web_time::Instant;
+
+use crate::api::Client;
+use crate::duration;
+
+/// One thing that happened, for `diag timeline`
+#[derive(Debug, Serialize)]
+pub struct TimelineEntry {
+    /// Local time
+    pub time: String,
+    /// Relative to the moment asked about, e.g. `-2m` or `+30s`
+    pub offset: String,
+    /// `event`, `alarm`, `wan`, or `device`
+    pub source: &'static str,
+    /// Event or alarm key, or what a sample or device change shows
+    pub kind: String,
+    pub message: String,
+    #[serde(skip)]
+    unix: i64,
+}
+
+fn str_of(record: &Value, key: &str) -> String {
+    record
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// `time` in milliseconds (events, alarms, reports) as Unix seconds
+fn millis(record: &Value) -> Option<i64> {
+    record
+        .get("time")
+        .and_then(|v| v.as_i64())
+        .map(|t| t / 1000)
+}
+
+fn entry(
+    unix: i64,
+    around: i64,
+    source: &'static str,
+    kind: String,
+    message: String,
+) -> TimelineEntry {
+    let time = chrono::DateTime::from_timestamp(unix, 0)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let delta = unix - around;
+    let sign = if delta < 0 { '-' } else { '+' };
+    TimelineEntry {
+        time,
+        offset: format!("{sign}{}", duration::humanize(delta.abs())),
+        source,
+        kind,
+        message,
+        unix,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThroughputResult {
+    pub device: String,
+    pub mac: String,
+    /// How the device reaches the gateway: `wire` or `wireless`
+    pub uplink: Option<String>,
+    pub download_mbps: Option<f64>,
+    pub upload_mbps: Option<f64>,
+    pub latency_ms: Option<f64>,
+    pub rundate: Option<i64>,
+}
+
+impl Client {
+    pub(crate) async fn devmgr(&self, cmd: &str, mac: &str) -> Result<Value> {
+        let url = self.site_url("cmd/devmgr");
+        let body = json!({"cmd": cmd, "mac": mac});
+        let resp = self
+            .send(
+                Method::POST,
+                &url,
+                Some(&body),
+                &format!("run {cmd} on {mac}"),
+            )
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
+    }
+
+    /// Run the device's built-in throughput test towards the gateway and wait for the result
+    pub async fn run_throughput_test(
+        &self,
+        device: &str,
+        timeout: Duration,
+    ) -> Result<ThroughputResult> {
+        let device = self.find_device(device).await?;
+        let field = |k: &str| device.get(k).and_then(|v| v.as_str()).map(String::from);
+        let mac = field("mac").unwrap_or_default();
+        let started = duration::now_secs();
+
+        self.devmgr("speedtest", &mac).await?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let status = self.devmgr("speedtest-status", &mac).await?;
+            let rundate = status.get("rundate").and_then(|v| v.as_i64());
+            let finished = rundate.is_some_and(|t| t >= started)
+                && status.get("xput_upload").is_some_and(|v| !v.is_null());
+            if finished {
+                let num = |k: &str| status.get(k).and_then(|v| v.as_f64());
+                return Ok(ThroughputResult {
+                    device: field("name").unwrap_or_else(|| mac.clone()),
+                    uplink: device
+                        .get("uplink")
+                        .and_then(|u| u.get("type"))
+                        .and_then(|v| v.as_str())
+                        .map(String::from),
+                    mac,
+                    download_mbps: num("xput_download"),
+                    upload_mbps: num("xput_upload"),
+                    latency_ms: num("latency"),
+                    rundate,
+                });
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "Throughput test on {mac} did not finish within {}s \
+                     (the device may not support it)",
+                    timeout.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Events, alarms, WAN samples (5-minute gateway reports), and device boots and last
+    /// contacts from `window` before `around` (Unix seconds) to `window` after, oldest first
+    pub async fn get_timeline(&self, around: i64, window: Duration) -> Result<Vec<TimelineEntry>> {
+        let span = window.as_secs() as i64;
+        let (start, end) = (around - span, around + span);
+        let range = json!({"start": start * 1000, "end": end * 1000});
+        let mut events_query = range.clone();
+        events_query["_limit"] = json!(3000);
+        let mut wan_query = range.clone();
+        wan_query["attrs"] = json!([
+            "time",
+            "wan-rx_bytes",
+            "wan-tx_bytes",
+            "latency_avg",
+            "wan-rx_dropped",
+            "wan-tx_dropped"
+        ]);
+        let (events, alarms, wan, devices) = tokio::try_join!(
+            self.query_stat("event", &events_query),
+            self.get_stat("alarm"),
+            self.query_stat("report/5minutes.gw", &wan_query),
+            self.get_devices()
+        )?;
+        let records = |v: &Value| v.as_array().cloned().unwrap_or_default();
+        let in_window = |t: i64| (start..=end).contains(&t);
+        let mut entries = Vec::new();
+
+        for e in records(&events) {
+            let Some(t) = millis(&e).filter(|t| in_window(*t)) else {
+                continue;
+            };
+            entries.push(entry(
+                t,
+                around,
+                "event",
+                str_of(&e, "key"),
+                str_of(&e, "msg"),
+            ));
+        }
+        for a in records(&alarms) {
+            let Some(t) = millis(&a).filter(|t| in_window(*t)) else {
+                continue;
+            };
+            entries.push(entry(
+                t,
+                around,
+                "alarm",
+                str_of(&a, "key"),
+                str_of(&a, "msg"),
+            ));
+        }
+        for sample in records(&wan) {
+            let Some(t) = millis(&sample).filter(|t| in_window(*t)) else {
+                continue;
+            };
+            let num = |k: &str| sample.get(k).and_then(|v| v.as_f64());
+            let mut parts = Vec::new();
+            if let Some(rx) = num("wan-rx_bytes") {
+                parts.push(format!("rx {:.1} MB", rx / 1e6));
+            }
+            if let Some(tx) = num("wan-tx_bytes") {
+                parts.push(format!("tx {:.1} MB", tx / 1e6));
+            }
+            if let Some(latency) = num("latency_avg") {
+                parts.push(format!("latency {latency:.0} ms"));
+            }
+            let dropped =
+                num("wan-rx_dropped").unwrap_or(0.0) + num("wan-tx_dropped").unwrap_or(0.0);
+            if dropped > 0.0 {
+                parts.push(format!("{dropped:.0} dropped"));
+            }
+            entries.push(entry(
+                t,
+                around,
+                "wan",
+                "5-minute sample".into(),
+                parts.join(", "),
+            ));
+        }
+        let now = duration::now_secs();
+        for d in records(&devices) {
+            let name = [str_of(&d, "name"), str_of(&d, "mac")]
+                .into_iter()
+                .find(|s| !s.is_empty())
+                .unwrap_or_default();
+            let online = d.get("state").and_then(|v| v.as_i64()) == Some(1);
+            let uptime = d.get("uptime").and_then(|v| v.as_i64());
+            if let Some(booted) = uptime.filter(|_| online).map(|u| now - u)
+                && in_window(booted)
+            {
+                let message = format!("{name} started (up {})", duration::humanize(now - booted));
+                entries.push(entry(booted, around, "device", "boot".into(), message));
+            }
+            let last_seen = d.get("last_seen").and_then(|v| v.as_i64());
+            if let Some(seen) = last_seen.filter(|_| !online)
+                && in_window(seen)
+            {
+                let message = format!("{name} last contact before going offline");
+                entries.push(entry(seen, around, "device", "offline".into(), message));
+            }
+        }
+
+        entries.sort_by_key(|e| e.unix);
+        Ok(entries)
+    }
+}
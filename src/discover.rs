@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+/// UDP port UniFi devices answer discovery probes on
+pub const PORT: u16 = 10001;
+
+/// Version 1 discovery request: version, command, and an empty payload
+const PROBE: [u8; 4] = [1, 0, 0, 0];
+
+/// Model codes of consoles that run the Network application (UDM, UDR, Cloud Gateway, Cloud
+/// Key, ...); the other devices that answer are adopted by one of them
+const CONSOLE_PREFIXES: &[&str] = &["UDM", "UDR", "UDW", "UCG", "UXG", "UCK", "UX"];
+
+// Fields of a reply (type, 2-byte length, value)
+const HWADDR: u8 = 0x01;
+const IPINFO: u8 = 0x02;
+const FIRMWARE: u8 = 0x03;
+const HOSTNAME: u8 = 0x0b;
+const PLATFORM: u8 = 0x0c;
+const MODEL: u8 = 0x15;
+
+/// A device that answered a discovery probe
+#[derive(Debug, Serialize)]
+pub struct Found {
+    pub ip: String,
+    pub mac: String,
+    pub name: String,
+    pub model: String,
+    pub version: String,
+    /// A console to point `host` at, rather than a device it manages
+    pub console: bool,
+}
+
+/// Send a discovery probe to `address` (the broadcast address finds every device on the LAN)
+/// and collect the answers that arrive within `wait`, consoles first
+pub async fn discover(address: IpAddr, wait: Duration) -> Result<Vec<Found>> {
+    let bind = match address {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(bind, 0))
+        .await
+        .context("Failed to open a UDP socket")?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&PROBE, SocketAddr::new(address, PORT))
+        .await
+        .with_context(|| format!("Failed to send the discovery probe to {address}:{PORT}"))?;
+
+    // A device may answer more than once (e.g., to a retried probe)
+    let mut found = BTreeMap::new();
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut buf = [0u8; 1500];
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        if let Some(device) = parse(&buf[..len], from.ip()) {
+            found.entry(device.ip.clone()).or_insert(device);
+        }
+    }
+    let mut found: Vec<Found> = found.into_values().collect();
+    found.sort_by_key(|d| !d.console);
+    Ok(found)
+}
+
+/// Decode a version 1 reply that came from `from`
+fn parse(reply: &[u8], from: IpAddr) -> Option<Found> {
+    if reply.len() < 4 || reply[0] != 1 || reply[1] != 0 {
+        return None;
+    }
+    let end = (4 + u16::from_be_bytes([reply[2], reply[3]]) as usize).min(reply.len());
+    let mac = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_string();
+    let mut device = Found {
+        ip: from.to_string(),
+        mac: String::new(),
+        name: String::new(),
+        model: String::new(),
+        version: String::new(),
+        console: false,
+    };
+    let mut platform = String::new();
+    let mut at = 4;
+    while at + 3 <= end {
+        let kind = reply[at];
+        let len = u16::from_be_bytes([reply[at + 1], reply[at + 2]]) as usize;
+        let Some(value) = reply.get(at + 3..at + 3 + len) else {
+            break;
+        };
+        match kind {
+            HWADDR if len == 6 => device.mac = mac(value),
+            // MAC + IPv4 per interface; older firmware sends no HWADDR
+            IPINFO if len == 10 && device.mac.is_empty() => device.mac = mac(&value[..6]),
+            FIRMWARE => device.version = text(value),
+            HOSTNAME => device.name = text(value),
+            PLATFORM => platform = text(value),
+            MODEL => device.model = text(value),
+            _ => {}
+        }
+        at += 3 + len;
+    }
+    if device.model.is_empty() {
+        device.model = platform;
+    }
+    let model = device.model.to_uppercase();
+    device.console = CONSOLE_PREFIXES
+        .iter()
+        .any(|prefix| model.starts_with(prefix));
+    Some(device)
+}
@@ -0,0 +1,283 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::api::{self, Client};
+use crate::config::Profile;
+use crate::tls;
+
+/// Clock differences above these are reported as a warning and an error
+const SKEW_WARN_SECS: i64 = 60;
+const SKEW_ERROR_SECS: i64 = 300;
+/// Certificates expiring sooner than this are reported as a warning
+const EXPIRY_WARN_DAYS: i64 = 30;
+
+/// One line of `unifi doctor`
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub check: &'static str,
+    /// `ok`, `warning`, or `error`
+    pub status: &'static str,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(check: &'static str, status: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn error(check: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(check, "error", detail)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == "error"
+    }
+}
+
+/// Host and credentials are configured
+pub fn config_check(profile: &Profile) -> Check {
+    match (&profile.host, &profile.api_key, &profile.username) {
+        (None, _, _) => Check::error("config", "No host configured; run 'unifi config'"),
+        (Some(_), None, None) => Check::error("config", "No API key or username configured"),
+        (Some(host), Some(_), _) => Check::new("config", "ok", format!("{host}, API key")),
+        (Some(host), None, Some(user)) => {
+            Check::new("config", "ok", format!("{host}, local account {user}"))
+        }
+    }
+}
+
+fn format_time(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// The controller certificate's fingerprint (against `pinned`, if set), its validity window,
+/// and the clock difference to the controller, from one unauthenticated request
+pub async fn tls_checks(host: &str, pinned: Option<&str>) -> Vec<Check> {
+    let base_url = match api::normalize_base_url(host) {
+        Ok(url) => url,
+        Err(err) => return vec![Check::error("connect", format!("{err:#}"))],
+    };
+    let resp = async {
+        reqwest::Client::builder()
+            .danger_accept_invalid_certs(true) // Inspect the certificate instead of rejecting it
+            .tls_info(true)
+            .timeout(Duration::from_secs(10))
+            .build()?
+            .get(&base_url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to {base_url}"))
+    };
+    let resp = match resp.await {
+        Ok(resp) => resp,
+        Err(err) => return vec![Check::error("connect", format!("{err:#}"))],
+    };
+    let now = Utc::now();
+    let mut checks = vec![Check::new("connect", "ok", base_url.clone())];
+
+    let cert = resp
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate());
+    if let Some(der) = cert {
+        let fingerprint = tls::fingerprint(der);
+        checks.push(match pinned.map(tls::parse_fingerprint) {
+            Some(Ok(pin)) if !tls::matches(der, &pin) => Check::error(
+                "fingerprint",
+                format!("SHA-256 {fingerprint} does not match the pinned one"),
+            ),
+            Some(Err(err)) => Check::error("fingerprint", format!("{err:#}")),
+            _ => Check::new("fingerprint", "ok", format!("SHA-256 {fingerprint}")),
+        });
+        checks.push(match x509_parser::parse_x509_certificate(der) {
+            Ok((_, cert)) => {
+                let validity = cert.validity();
+                let from = DateTime::from_timestamp(validity.not_before.timestamp(), 0)
+                    .unwrap_or_default();
+                let until =
+                    DateTime::from_timestamp(validity.not_after.timestamp(), 0).unwrap_or_default();
+                let window = format!(
+                    "{}, valid {} to {}",
+                    cert.subject(),
+                    format_time(from),
+                    format_time(until)
+                );
+                if now < from {
+                    Check::error(
+                        "certificate",
+                        format!("{window}: not yet valid, check this machine's clock"),
+                    )
+                } else if now > until {
+                    Check::error(
+                        "certificate",
+                        format!("{window}: expired (or this machine's clock is ahead)"),
+                    )
+                } else if (until - now).num_days() < EXPIRY_WARN_DAYS {
+                    Check::new(
+                        "certificate",
+                        "warning",
+                        format!("{window}: expires in {} days", (until - now).num_days()),
+                    )
+                } else {
+                    Check::new("certificate", "ok", window)
+                }
+            }
+            Err(err) => Check::new(
+                "certificate",
+                "warning",
+                format!("Could not parse certificate: {err}"),
+            ),
+        });
+    }
+
+    let server_time = resp
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|d| d.to_str().ok())
+        .and_then(|d| DateTime::parse_from_rfc2822(d).ok());
+    if let Some(server_time) = server_time {
+        let skew = (now - server_time.with_timezone(&Utc)).num_seconds();
+        let detail = format!("this machine is {skew:+}s relative to the controller");
+        checks.push(match skew.abs() {
+            s if s > SKEW_ERROR_SECS => Check::error("clock", detail),
+            s if s > SKEW_WARN_SECS => Check::new("clock", "warning", detail),
+            _ => Check::new("clock", "ok", detail),
+        });
+    }
+    checks
+}
+
+/// Credentials work and the Network application answers
+pub async fn api_check(client: &Client) -> Check {
+    match client.get_sites().await {
+        Ok(sites) => Check::new(
+            "api",
+            "ok",
+            format!(
+                "{} site(s) accessible",
+                sites.as_array().map_or(0, Vec::len)
+            ),
+        ),
+        Err(err) => Check::error("api", format!("{err:#}")),
+    }
+}
+
+/// What to do about a failed login or first request, by the kind of failure
+fn auth_hint(err: &anyhow::Error) -> &'static str {
+    if let Some(e) = err.downcast_ref::<api::ApiError>() {
+        return match e.status.as_u16() {
+            401 => {
+                "The controller rejected the credentials. Create a new API key (UniFi OS: \
+                 Settings > Control Plane > Integrations) and run 'unifi config --api-key KEY'"
+            }
+            403 => {
+                "The credentials are valid but may not use the Network application; grant the \
+                 key or account access to it"
+            }
+            404 => {
+                "No Network application at this address. Check the host, port, and path, or run \
+                 'unifi config --legacy true' for a classic controller"
+            }
+            429 => "Too many attempts; wait a few minutes before trying again",
+            _ => "The controller answered with an error; 'unifi -v config test' shows the request",
+        };
+    }
+    if err.is::<api::TlsError>() {
+        return "'unifi doctor' shows the certificate and the clock difference";
+    }
+    if err.chain().any(|e| e.is::<reqwest::Error>()) {
+        return "The controller did not answer; check the host and that this machine can reach it";
+    }
+    "Check the host and credentials with 'unifi config'"
+}
+
+/// A failed `auth` check: the short reason (not the response body) and what to do about it
+pub fn auth_failure(err: &anyhow::Error) -> Check {
+    let reason = match err.downcast_ref::<api::ApiError>() {
+        Some(e) => format!("Failed to {} ({})", e.context, e.status),
+        None => format!("{err:#}"),
+    };
+    Check::error("auth", format!("{reason}. {}", auth_hint(err)))
+}
+
+/// Authentication, the controller model and versions, the visible sites, and the role on
+/// `site` (by name, description, or ID; `default` when unset)
+pub async fn connection_checks(client: &Client, site: Option<&str>) -> Vec<Check> {
+    let sites = match client.get_sites().await {
+        Ok(sites) => sites,
+        Err(err) => return vec![auth_failure(&err)],
+    };
+    let sites = sites.as_array().cloned().unwrap_or_default();
+    let mut checks = vec![Check::new("auth", "ok", "Credentials accepted")];
+
+    checks.push(match client.get_sysinfo().await {
+        Ok(info) => {
+            let field = |key: &str| info.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+            let mut parts: Vec<String> = ["ubnt_device_type", "hostname"]
+                .iter()
+                .map(|key| field(key).to_string())
+                .filter(|v| !v.is_empty())
+                .collect();
+            if !field("udm_version").is_empty() {
+                parts.push(format!("UniFi OS {}", field("udm_version")));
+            }
+            if !field("version").is_empty() {
+                parts.push(format!("Network {}", field("version")));
+            }
+            match parts.is_empty() {
+                true => Check::new("controller", "warning", "No model or version reported"),
+                false => Check::new("controller", "ok", parts.join(", ")),
+            }
+        }
+        Err(err) => Check::new("controller", "warning", format!("{err:#}")),
+    });
+
+    let names: Vec<&str> = sites
+        .iter()
+        .filter_map(|s| s.get("name").and_then(|v| v.as_str()))
+        .collect();
+    checks.push(match names.is_empty() {
+        true => Check::error("sites", "The credentials see no sites"),
+        false => Check::new(
+            "sites",
+            "ok",
+            format!("{} site(s): {}", names.len(), names.join(", ")),
+        ),
+    });
+
+    let wanted = site.unwrap_or("default");
+    let current = sites.iter().find(|s| {
+        ["name", "desc", "_id"].iter().any(|k| {
+            s.get(*k)
+                .and_then(|v| v.as_str())
+                .is_some_and(|v| v.eq_ignore_ascii_case(wanted))
+        })
+    });
+    checks.push(match current {
+        None => Check::error(
+            "permission",
+            format!("No access to site '{wanted}'; use --site with one of the sites above"),
+        ),
+        Some(s) => match s.get("role").and_then(|v| v.as_str()) {
+            Some("admin") => Check::new("permission", "ok", format!("admin on {wanted}")),
+            Some(role) => Check::new(
+                "permission",
+                "warning",
+                format!("{role} on {wanted}: commands that change settings will be refused"),
+            ),
+            None => Check::new(
+                "permission",
+                "warning",
+                format!("The controller does not report the role on {wanted}"),
+            ),
+        },
+    });
+    checks
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use web_time::{SystemTime, UNIX_EPOCH};
+
+/// Parse a short duration like `90s`, `5m`, `24h`, `30d`, or `2w`
+pub fn parse(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: u64 = num
+        .parse()
+        .with_context(|| format!("Invalid duration '{s}' (expected e.g. 30s, 5m, 24h, 30d)"))?;
+    let unit_secs: u64 = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => anyhow::bail!("Invalid duration unit '{unit}' in '{s}' (use s, m, h, d, w)"),
+    };
+    // Callers do arithmetic on the seconds as i64
+    let secs = n
+        .checked_mul(unit_secs)
+        .filter(|secs| i64::try_from(*secs).is_ok())
+        .with_context(|| format!("Duration '{s}' is too large"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Current Unix time in seconds
+pub fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Render a number of seconds as a compact "3d 4h" style string
+pub fn humanize(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (d, h, m) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+    match (d, h, m) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, m) => format!("{m}m"),
+        (0, h, m) => format!("{h}h {m}m"),
+        (d, h, _) => format!("{d}d {h}h"),
+    }
+}
+
+/// Parse a point in time as Unix seconds: `now`, RFC 3339, or local `YYYY-MM-DD HH:MM[:SS]`
+/// (a `T` separator works too)
+pub fn parse_time(s: &str) -> Result<i64> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("now") {
+        return Ok(now_secs());
+    }
+    if let Ok(t) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(t.timestamp());
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|f| chrono::NaiveDateTime::parse_from_str(s, f).ok())
+    .with_context(|| format!("Invalid time '{s}' (expected e.g. \"2024-06-02 14:35\")"))?;
+    naive
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .map(|t| t.timestamp())
+        .with_context(|| format!("'{s}' does not exist in the local timezone"))
+}
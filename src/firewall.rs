@@ -1,7 +1,636 @@
-use anyhow::{Context, Result};
-use serde_json::Value;
+use std::net::IpAddr;
 
-use crate::api::Client;
+use anyhow::{Result, bail};
+use reqwest::Method;
+use serde_json::{Map, Value};
+
+use crate::api::{Client, NotFound};
+
+/// ICMP type names accepted in `icmp_typename`: (name, type, code, description)
+pub const ICMP_TYPES: &[(&str, u8, Option<u8>, &str)] = &[
+    ("echo-reply", 0, None, "Ping reply"),
+    (
+        "destination-unreachable",
+        3,
+        None,
+        "Any destination unreachable",
+    ),
+    ("network-unreachable", 3, Some(0), "Network unreachable"),
+    ("host-unreachable", 3, Some(1), "Host unreachable"),
+    ("protocol-unreachable", 3, Some(2), "Protocol unreachable"),
+    ("port-unreachable", 3, Some(3), "Port unreachable"),
+    (
+        "fragmentation-needed",
+        3,
+        Some(4),
+        "Fragmentation needed (path MTU discovery)",
+    ),
+    ("source-route-failed", 3, Some(5), "Source route failed"),
+    ("network-unknown", 3, Some(6), "Destination network unknown"),
+    ("host-unknown", 3, Some(7), "Destination host unknown"),
+    (
+        "network-prohibited",
+        3,
+        Some(9),
+        "Network administratively prohibited",
+    ),
+    (
+        "host-prohibited",
+        3,
+        Some(10),
+        "Host administratively prohibited",
+    ),
+    (
+        "TOS-network-unreachable",
+        3,
+        Some(11),
+        "Network unreachable for TOS",
+    ),
+    (
+        "TOS-host-unreachable",
+        3,
+        Some(12),
+        "Host unreachable for TOS",
+    ),
+    (
+        "communication-prohibited",
+        3,
+        Some(13),
+        "Communication administratively prohibited",
+    ),
+    (
+        "host-precedence-violation",
+        3,
+        Some(14),
+        "Host precedence violation",
+    ),
+    (
+        "precedence-cutoff",
+        3,
+        Some(15),
+        "Precedence cutoff in effect",
+    ),
+    ("source-quench", 4, None, "Source quench (deprecated)"),
+    ("redirect", 5, None, "Any redirect"),
+    ("network-redirect", 5, Some(0), "Redirect for network"),
+    ("host-redirect", 5, Some(1), "Redirect for host"),
+    (
+        "TOS-network-redirect",
+        5,
+        Some(2),
+        "Redirect for TOS and network",
+    ),
+    ("TOS-host-redirect", 5, Some(3), "Redirect for TOS and host"),
+    ("echo-request", 8, None, "Ping request"),
+    ("router-advertisement", 9, None, "Router advertisement"),
+    ("router-solicitation", 10, None, "Router solicitation"),
+    ("time-exceeded", 11, None, "Any time exceeded (traceroute)"),
+    (
+        "ttl-zero-during-transit",
+        11,
+        Some(0),
+        "TTL expired in transit",
+    ),
+    (
+        "ttl-zero-during-reassembly",
+        11,
+        Some(1),
+        "Fragment reassembly time exceeded",
+    ),
+    ("parameter-problem", 12, None, "Any parameter problem"),
+    ("ip-header-bad", 12, Some(0), "Bad IP header"),
+    (
+        "required-option-missing",
+        12,
+        Some(1),
+        "Required option missing",
+    ),
+    ("timestamp-request", 13, None, "Timestamp request"),
+    ("timestamp-reply", 14, None, "Timestamp reply"),
+    ("address-mask-request", 17, None, "Address mask request"),
+    ("address-mask-reply", 18, None, "Address mask reply"),
+];
+
+/// ICMPv6 type names accepted in `icmpv6_typename`
+pub const ICMPV6_TYPES: &[(&str, u8, Option<u8>, &str)] = &[
+    (
+        "destination-unreachable",
+        1,
+        None,
+        "Any destination unreachable",
+    ),
+    ("no-route", 1, Some(0), "No route to destination"),
+    (
+        "communication-prohibited",
+        1,
+        Some(1),
+        "Communication administratively prohibited",
+    ),
+    ("beyond-scope", 1, Some(2), "Beyond scope of source address"),
+    ("address-unreachable", 1, Some(3), "Address unreachable"),
+    ("port-unreachable", 1, Some(4), "Port unreachable"),
+    (
+        "failed-policy",
+        1,
+        Some(5),
+        "Source address failed ingress/egress policy",
+    ),
+    ("reject-route", 1, Some(6), "Reject route to destination"),
+    (
+        "packet-too-big",
+        2,
+        None,
+        "Packet too big (path MTU discovery)",
+    ),
+    ("time-exceeded", 3, None, "Any time exceeded (traceroute)"),
+    (
+        "ttl-zero-during-transit",
+        3,
+        Some(0),
+        "Hop limit exceeded in transit",
+    ),
+    (
+        "ttl-zero-during-reassembly",
+        3,
+        Some(1),
+        "Fragment reassembly time exceeded",
+    ),
+    ("parameter-problem", 4, None, "Any parameter problem"),
+    ("bad-header", 4, Some(0), "Erroneous header field"),
+    (
+        "unknown-header-type",
+        4,
+        Some(1),
+        "Unrecognized next header type",
+    ),
+    ("unknown-option", 4, Some(2), "Unrecognized IPv6 option"),
+    ("echo-request", 128, None, "Ping request"),
+    ("echo-reply", 129, None, "Ping reply"),
+    (
+        "router-solicitation",
+        133,
+        None,
+        "Router solicitation (SLAAC)",
+    ),
+    (
+        "router-advertisement",
+        134,
+        None,
+        "Router advertisement (SLAAC)",
+    ),
+    (
+        "neighbour-solicitation",
+        135,
+        None,
+        "Neighbor solicitation (address resolution)",
+    ),
+    (
+        "neighbour-advertisement",
+        136,
+        None,
+        "Neighbor advertisement (address resolution)",
+    ),
+    ("redirect", 137, None, "Redirect"),
+];
+
+/// Canonical spelling of an ICMP type name for the rule's family
+fn icmp_type_name(name: &str, v6: bool) -> Result<&'static str> {
+    let table = if v6 { ICMPV6_TYPES } else { ICMP_TYPES };
+    // Accept the American spelling used in most documentation
+    let wanted = name.replace("neighbor", "neighbour");
+    table
+        .iter()
+        .find(|(n, ..)| n.eq_ignore_ascii_case(&wanted))
+        .map(|(n, ..)| *n)
+        .ok_or_else(|| {
+            let family = if v6 { "ICMPv6" } else { "ICMP" };
+            anyhow::anyhow!("Unknown {family} type '{name}' (see `unifi firewall icmp-types`)")
+        })
+}
+
+/// IPv6 rulesets are named like `WANv6_IN`, `LANv6_LOCAL`, `GUESTv6_OUT`
+pub fn is_v6_ruleset(ruleset: &str) -> bool {
+    ruleset.contains("v6_")
+}
+
+/// Address family of an IP or CIDR (`true` for IPv6); `None` if it doesn't parse
+fn is_v6_address(address: &str) -> Option<bool> {
+    let (ip, prefix) = match address.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (address, None),
+    };
+    let ip: IpAddr = ip.parse().ok()?;
+    let max = if ip.is_ipv6() { 128 } else { 32 };
+    if let Some(prefix) = prefix {
+        prefix.parse::<u8>().ok().filter(|p| *p <= max)?;
+    }
+    Some(ip.is_ipv6())
+}
+
+fn str_field<'a>(fields: &'a Map<String, Value>, key: &str) -> &'a str {
+    fields.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+/// Move family-specific fields to their v6 names and reject v4/v6 mixing.
+///
+/// Callers always pass `protocol` and `icmp_typename`; IPv6 rules store them in
+/// `protocol_v6` and `icmpv6_typename`.
+fn adapt_for_family(fields: &mut Map<String, Value>, v6: bool, groups: &[Value]) -> Result<()> {
+    let family = if v6 { "IPv6" } else { "IPv4" };
+    for key in ["src_address", "dst_address"] {
+        let address = str_field(fields, key);
+        if address.is_empty() {
+            continue;
+        }
+        match is_v6_address(address) {
+            None => bail!("Invalid {key} '{address}'"),
+            Some(is_v6) if is_v6 != v6 => {
+                bail!("{key} '{address}' does not match the {family} ruleset")
+            }
+            Some(_) => {}
+        }
+    }
+
+    for key in ["src_firewallgroup_ids", "dst_firewallgroup_ids"] {
+        let ids = fields.get(key).and_then(|v| v.as_array());
+        for id in ids.into_iter().flatten().filter_map(|v| v.as_str()) {
+            let Some(group) = groups
+                .iter()
+                .find(|g| g.get("_id").and_then(|v| v.as_str()) == Some(id))
+            else {
+                bail!("Firewall group {id} not found");
+            };
+            let group_type = group
+                .get("group_type")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            let mismatch = match group_type {
+                "address-group" => v6,
+                "ipv6-address-group" => !v6,
+                _ => false,
+            };
+            if mismatch {
+                let name = group.get("name").and_then(|v| v.as_str()).unwrap_or(id);
+                bail!("Group '{name}' ({group_type}) cannot be used in an {family} rule");
+            }
+        }
+    }
+
+    let icmp_protocol = if v6 { "icmpv6" } else { "icmp" };
+    let icmp_type = str_field(fields, "icmp_typename").to_string();
+    if !icmp_type.is_empty() {
+        let name = icmp_type_name(&icmp_type, v6)?;
+        fields.insert("icmp_typename".into(), Value::String(name.into()));
+        // An ICMP type implies the protocol when none was chosen
+        match fields.get("protocol").and_then(|v| v.as_str()) {
+            Some("" | "all") => {
+                fields.insert("protocol".into(), Value::String(icmp_protocol.into()));
+            }
+            Some(p) if p != icmp_protocol => {
+                bail!("--icmp-type requires protocol '{icmp_protocol}', not '{p}'")
+            }
+            _ => {}
+        }
+    }
+
+    let protocol = str_field(fields, "protocol");
+    match (protocol, v6) {
+        ("icmp", true) => bail!("Use protocol 'icmpv6' on IPv6 rulesets"),
+        ("icmpv6", false) => bail!("Protocol 'icmpv6' requires an IPv6 ruleset"),
+        _ => {}
+    }
+
+    if v6 {
+        for (v4_key, v6_key) in [
+            ("protocol", "protocol_v6"),
+            ("icmp_typename", "icmpv6_typename"),
+        ] {
+            if let Some(value) = fields.remove(v4_key) {
+                fields.insert(v6_key.into(), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A new connection to check against a ruleset with [`simulate`]
+#[derive(Debug, Clone, Copy)]
+pub struct Flow<'a> {
+    pub ruleset: &'a str,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// `tcp`, `udp`, `icmp`, ...
+    pub protocol: &'a str,
+    pub dst_port: Option<u16>,
+}
+
+/// `53`, `67-68`, `80,443`; an empty spec matches any port
+fn port_matches(spec: &str, port: Option<u16>) -> bool {
+    if spec.is_empty() {
+        return true;
+    }
+    let Some(port) = port else { return false };
+    spec.split(',')
+        .any(|part| match part.trim().split_once('-') {
+            Some((lo, hi)) => {
+                lo.parse().is_ok_and(|lo: u16| lo <= port)
+                    && hi.parse().is_ok_and(|hi: u16| port <= hi)
+            }
+            None => part.trim().parse() == Ok(port),
+        })
+}
+
+fn rule_matches(rule: &Value, flow: &Flow) -> bool {
+    let text = |k: &str| rule.get(k).and_then(|v| v.as_str()).unwrap_or_default();
+    let flag = |k: &str| rule.get(k).and_then(|v| v.as_bool()).unwrap_or(false);
+    let address_matches = |k: &str, ip: IpAddr| {
+        let address = text(k);
+        address.is_empty() || crate::audit::parse_network(address).is_ok_and(|n| n.contains(ip))
+    };
+    let has_groups = ["src_firewallgroup_ids", "dst_firewallgroup_ids"]
+        .iter()
+        .any(|k| {
+            rule.get(*k)
+                .and_then(|v| v.as_array())
+                .is_some_and(|a| !a.is_empty())
+        });
+    // A rule limited to established/related/invalid state never sees a new connection
+    let state_only = !flag("state_new")
+        && (flag("state_established") || flag("state_related") || flag("state_invalid"));
+    let protocol = match text("protocol") {
+        "" => "all",
+        p => p,
+    };
+    let protocol_matches = match protocol {
+        "all" => true,
+        "tcp_udp" => matches!(flow.protocol, "tcp" | "udp"),
+        p => p == flow.protocol,
+    };
+
+    rule.get("enabled").and_then(|v| v.as_bool()) != Some(false)
+        && text("ruleset") == flow.ruleset
+        && !has_groups
+        && !state_only
+        && protocol_matches
+        && address_matches("src_address", flow.src)
+        && address_matches("dst_address", flow.dst)
+        && port_matches(text("dst_port"), flow.dst_port)
+}
+
+/// The first rule (by `rule_index`) that a new connection hits, or `None` for the ruleset's
+/// default action.
+///
+/// Rules that match on firewall groups, networks, or MAC addresses are not evaluated and are
+/// treated as not matching.
+pub fn simulate<'a>(rules: &'a [Value], flow: &Flow) -> Option<&'a Value> {
+    let mut ordered: Vec<&Value> = rules.iter().collect();
+    ordered.sort_by_key(|r| {
+        r.get("rule_index")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(u64::MAX)
+    });
+    ordered.into_iter().find(|r| {
+        let unmodelled = [
+            "src_networkconf_id",
+            "dst_networkconf_id",
+            "src_mac_address",
+        ]
+        .iter()
+        .any(|k| {
+            r.get(*k)
+                .and_then(|v| v.as_str())
+                .is_some_and(|s| !s.is_empty())
+        });
+        !unmodelled && rule_matches(r, flow)
+    })
+}
+
+/// Diagram syntax for `firewall graph`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz (`dot -Tsvg`)
+    Dot,
+    /// Mermaid flowchart (rendered by GitHub and GitLab in Markdown)
+    Mermaid,
+}
+
+/// Traffic from one node to another that a rule allows or blocks
+struct Edge {
+    from: String,
+    to: String,
+    allow: bool,
+    label: String,
+}
+
+/// Where a ruleset's traffic comes from and goes to when a rule does not narrow it down:
+/// `LAN_IN` is any LAN to anywhere, `WAN_LOCAL` is the internet to the gateway itself, ...
+fn ruleset_ends(ruleset: &str) -> (&'static str, &'static str) {
+    let (zone, direction) = ruleset.split_once('_').unwrap_or((ruleset, ""));
+    let zone = match zone.trim_end_matches("v6") {
+        "WAN" => "Internet",
+        "GUEST" => "Any guest network",
+        _ => "Any LAN",
+    };
+    match direction {
+        "LOCAL" => (zone, "Gateway"),
+        "OUT" => ("Any", zone),
+        _ => (zone, "Any"),
+    }
+}
+
+/// The nodes on one side (`src` or `dst`) of a rule: networks, firewall groups, addresses,
+/// or MACs it names, else the ruleset's default end
+fn rule_ends(
+    rule: &Value,
+    side: &str,
+    default: &str,
+    networks: &[Value],
+    groups: &[Value],
+) -> Vec<String> {
+    let text = |k: &str| {
+        rule.get(format!("{side}_{k}"))
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+    };
+    let name_of = |records: &[Value], id: &str| {
+        records
+            .iter()
+            .find(|r| r.get("_id").and_then(|v| v.as_str()) == Some(id))
+            .and_then(|r| r.get("name").and_then(|v| v.as_str()))
+            .unwrap_or(id)
+            .to_string()
+    };
+    let group_ids: Vec<&str> = rule
+        .get(format!("{side}_firewallgroup_ids"))
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+
+    if let Some(id) = text("networkconf_id") {
+        vec![name_of(networks, id)]
+    } else if !group_ids.is_empty() {
+        group_ids.iter().map(|id| name_of(groups, id)).collect()
+    } else if let Some(address) = text("address") {
+        // A subnet that is exactly a network's is drawn as that network
+        let network = networks.iter().find(|n| {
+            n.get("ip_subnet")
+                .and_then(|v| v.as_str())
+                .and_then(|s| crate::audit::parse_network(s).ok())
+                .zip(crate::audit::parse_network(address).ok())
+                .is_some_and(|(a, b)| a.same_prefix(&b))
+        });
+        vec![
+            match network.and_then(|n| n.get("name")).and_then(|v| v.as_str()) {
+                Some(name) => name.to_string(),
+                None => address.to_string(),
+            },
+        ]
+    } else if let Some(mac) = text("mac_address") {
+        vec![mac.to_string()]
+    } else {
+        vec![default.to_string()]
+    }
+}
+
+fn edge_label(rule: &Value) -> String {
+    let text = |k: &str| rule.get(k).and_then(|v| v.as_str()).unwrap_or_default();
+    let mut label = text("name").to_string();
+    let protocol = match text("protocol") {
+        "" | "all" => "",
+        p => p,
+    };
+    let ports = text("dst_port");
+    if !protocol.is_empty() || !ports.is_empty() {
+        let detail = [protocol, ports]
+            .iter()
+            .filter(|s| !s.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        label = format!("{label} ({detail})");
+    }
+    label
+}
+
+/// Draw networks, zones, and the enabled rules between them as a diagram: solid green edges
+/// allow, dashed red edges drop or reject. WAN networks are left out; the internet and the
+/// gateway itself are drawn as `Internet` and `Gateway`.
+pub fn graph(rules: &[Value], networks: &[Value], groups: &[Value], format: GraphFormat) -> String {
+    let mut ordered: Vec<&Value> = rules
+        .iter()
+        .filter(|r| r.get("enabled").and_then(|v| v.as_bool()) != Some(false))
+        .collect();
+    ordered.sort_by_key(|r| {
+        (
+            r.get("ruleset")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default(),
+            r.get("rule_index")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(u64::MAX),
+        )
+    });
+
+    let mut nodes: Vec<String> = networks
+        .iter()
+        .filter(|n| n.get("purpose").and_then(|v| v.as_str()) != Some("wan"))
+        .filter_map(|n| n.get("name").and_then(|v| v.as_str()))
+        .map(String::from)
+        .chain(["Internet".to_string(), "Gateway".to_string()])
+        .collect();
+    let mut edges = Vec::new();
+    for rule in ordered {
+        let ruleset = rule
+            .get("ruleset")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let (src, dst) = ruleset_ends(ruleset);
+        let allow = rule.get("action").and_then(|v| v.as_str()) == Some("accept");
+        for from in rule_ends(rule, "src", src, networks, groups) {
+            for to in rule_ends(rule, "dst", dst, networks, groups) {
+                for node in [&from, &to] {
+                    if !nodes.contains(node) {
+                        nodes.push(node.clone());
+                    }
+                }
+                edges.push(Edge {
+                    from: from.clone(),
+                    to,
+                    allow,
+                    label: edge_label(rule),
+                });
+            }
+        }
+    }
+
+    let id = |name: &str| format!("n{}", nodes.iter().position(|n| n == name).unwrap_or(0));
+    let mut out = String::new();
+    match format {
+        GraphFormat::Dot => {
+            let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str("digraph firewall {\n    rankdir=LR;\n    node [shape=box];\n");
+            for node in &nodes {
+                out.push_str(&format!("    {} [label=\"{}\"];\n", id(node), quote(node)));
+            }
+            for e in &edges {
+                let style = if e.allow {
+                    "color=forestgreen"
+                } else {
+                    "color=red, style=dashed"
+                };
+                out.push_str(&format!(
+                    "    {} -> {} [label=\"{}\", {style}];\n",
+                    id(&e.from),
+                    id(&e.to),
+                    quote(&e.label)
+                ));
+            }
+            out.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            let quote = |s: &str| s.replace('"', "#quot;");
+            out.push_str("flowchart LR\n");
+            for node in &nodes {
+                out.push_str(&format!("    {}[\"{}\"]\n", id(node), quote(node)));
+            }
+            for e in &edges {
+                let arrow = if e.allow { "-->" } else { "-.->" };
+                out.push_str(&format!(
+                    "    {} {arrow}|\"{}\"| {}\n",
+                    id(&e.from),
+                    quote(&e.label),
+                    id(&e.to)
+                ));
+            }
+            for (i, e) in edges.iter().enumerate() {
+                let color = if e.allow { "green" } else { "red" };
+                out.push_str(&format!("    linkStyle {i} stroke:{color}\n"));
+            }
+        }
+    }
+    out
+}
+
+/// Reference listing of ICMP and ICMPv6 type names
+pub fn icmp_types() -> Vec<Value> {
+    let rows = |family: &str, table: &[(&str, u8, Option<u8>, &str)]| {
+        table
+            .iter()
+            .map(|(name, kind, code, description)| {
+                serde_json::json!({
+                    "family": family,
+                    "name": name,
+                    "type": kind,
+                    "code": code,
+                    "description": description,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut out = rows("icmp", ICMP_TYPES);
+    out.extend(rows("icmpv6", ICMPV6_TYPES));
+    out
+}
 
 impl Client {
     /// Get firewall rules
@@ -14,6 +643,97 @@ impl Client {
         self.get_rest("firewallgroup").await
     }
 
+    /// Rules and port forwards referencing a firewall group (by name or ID), with hit counters
+    /// where the gateway reports them
+    pub async fn get_firewall_group_usage(&self, group: &str) -> Result<Value> {
+        let (groups, rules, forwards) = tokio::try_join!(
+            self.get_firewall_groups(),
+            self.get_firewall_rules(),
+            self.get_rest("portforward")
+        )?;
+        let found = groups
+            .as_array()
+            .and_then(|a| {
+                a.iter().find(|g| {
+                    g.get("name").and_then(|v| v.as_str()) == Some(group)
+                        || g.get("_id").and_then(|v| v.as_str()) == Some(group)
+                })
+            })
+            .ok_or_else(|| NotFound(format!("Firewall group '{group}' not found")))?;
+        let id = found
+            .get("_id")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+
+        let references_id = |record: &Value, key: &str| match record.get(key) {
+            Some(Value::Array(ids)) => ids.iter().any(|v| v.as_str() == Some(id)),
+            Some(Value::String(v)) => v == id,
+            _ => false,
+        };
+        let counter = |record: &Value, keys: &[&str]| {
+            keys.iter()
+                .find_map(|k| record.get(*k).and_then(|v| v.as_u64()))
+        };
+
+        let mut references = Vec::new();
+        let (mut packets, mut bytes) = (None::<u64>, None::<u64>);
+        for rule in rules.as_array().into_iter().flatten() {
+            for (key, side) in [
+                ("src_firewallgroup_ids", "source"),
+                ("dst_firewallgroup_ids", "destination"),
+            ] {
+                if !references_id(rule, key) {
+                    continue;
+                }
+                let rule_packets = counter(rule, &["hits", "packets", "pkts"]);
+                let rule_bytes = counter(rule, &["bytes"]);
+                if let Some(n) = rule_packets {
+                    *packets.get_or_insert(0) += n;
+                }
+                if let Some(n) = rule_bytes {
+                    *bytes.get_or_insert(0) += n;
+                }
+                references.push(serde_json::json!({
+                    "type": "firewall-rule",
+                    "id": rule.get("_id"),
+                    "name": rule.get("name"),
+                    "ruleset": rule.get("ruleset"),
+                    "rule_index": rule.get("rule_index"),
+                    "enabled": rule.get("enabled"),
+                    "side": side,
+                    "packets": rule_packets,
+                    "bytes": rule_bytes,
+                }));
+            }
+        }
+        for forward in forwards.as_array().into_iter().flatten() {
+            if references_id(forward, "src_firewallgroup_id")
+                || references_id(forward, "src_firewallgroup_ids")
+            {
+                references.push(serde_json::json!({
+                    "type": "port-forward",
+                    "id": forward.get("_id"),
+                    "name": forward.get("name"),
+                    "enabled": forward.get("enabled"),
+                    "side": "source",
+                }));
+            }
+        }
+
+        Ok(serde_json::json!({
+            "group": {
+                "id": id,
+                "name": found.get("name"),
+                "group_type": found.get("group_type"),
+                "members": found.get("group_members"),
+            },
+            "in_use": !references.is_empty(),
+            "references": references,
+            "packets": packets,
+            "bytes": bytes,
+        }))
+    }
+
     /// Get traffic rules
     pub async fn get_traffic_rules(&self) -> Result<Value> {
         self.get_v2("trafficrules").await
@@ -24,15 +744,20 @@ impl Client {
         &self,
         rule: &serde_json::Map<String, Value>,
     ) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/firewallrule",
-            self.base_url
-        );
+        let url = self.site_url("rest/firewallrule");
+        let v6 = is_v6_ruleset(str_field(rule, "ruleset"));
+        let net_type = if v6 { "NETv6" } else { "NETv4" };
 
         let mut body = serde_json::Map::new();
         // Required defaults that UniFi expects
-        body.insert("src_networkconf_type".into(), Value::String("NETv4".into()));
-        body.insert("dst_networkconf_type".into(), Value::String("NETv4".into()));
+        body.insert(
+            "src_networkconf_type".into(),
+            Value::String(net_type.into()),
+        );
+        body.insert(
+            "dst_networkconf_type".into(),
+            Value::String(net_type.into()),
+        );
         body.insert("src_networkconf_id".into(), Value::String(String::new()));
         body.insert("dst_networkconf_id".into(), Value::String(String::new()));
         body.insert("src_mac_address".into(), Value::String(String::new()));
@@ -49,29 +774,18 @@ impl Client {
         body.insert("setting_preference".into(), Value::String("manual".into()));
         // Caller-provided fields override defaults
         body.extend(rule.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let groups = self.get_firewall_groups().await?;
+        adapt_for_family(
+            &mut body,
+            v6,
+            groups.as_array().map(Vec::as_slice).unwrap_or_default(),
+        )?;
 
+        let body = Value::Object(body);
         let resp = self
-            .http
-            .post(&url)
-            .header("X-API-Key", &self.api_key)
-            .json(&body)
-            .send()
-            .await
-            .context("Failed to create firewall rule")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to create firewall rule ({}): {}", status, body);
-        }
-
-        let body: Value = resp.json().await?;
-        Ok(body
-            .get("data")
-            .and_then(|d| d.as_array())
-            .and_then(|a| a.first())
-            .cloned()
-            .unwrap_or(body))
+            .send(Method::POST, &url, Some(&body), "create firewall rule")
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
     }
 
     /// Update a firewall rule by ID
@@ -80,56 +794,35 @@ impl Client {
         id: &str,
         fields: &serde_json::Map<String, Value>,
     ) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/firewallrule/{}",
-            self.base_url, id
-        );
+        let url = self.site_url(&format!("rest/firewallrule/{id}"));
+        let endpoint = format!("firewallrule/{id}");
+        let (current, groups) =
+            tokio::try_join!(self.get_rest(&endpoint), self.get_firewall_groups())?;
+        let ruleset = current
+            .get(0)
+            .and_then(|r| r.get("ruleset"))
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
 
+        let mut fields = fields.clone();
+        adapt_for_family(
+            &mut fields,
+            is_v6_ruleset(ruleset),
+            groups.as_array().map(Vec::as_slice).unwrap_or_default(),
+        )?;
+        let body = Value::Object(fields);
         let resp = self
-            .http
-            .put(&url)
-            .header("X-API-Key", &self.api_key)
-            .json(fields)
-            .send()
-            .await
-            .context("Failed to update firewall rule")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to update firewall rule ({}): {}", status, body);
-        }
-
-        let body: Value = resp.json().await?;
-        Ok(body
-            .get("data")
-            .and_then(|d| d.as_array())
-            .and_then(|a| a.first())
-            .cloned()
-            .unwrap_or(body))
+            .send(Method::PUT, &url, Some(&body), "update firewall rule")
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
     }
 
     /// Delete a firewall rule by ID
     pub async fn delete_firewall_rule(&self, id: &str) -> Result<()> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/firewallrule/{}",
-            self.base_url, id
-        );
-
-        let resp = self
-            .http
-            .delete(&url)
-            .header("X-API-Key", &self.api_key)
-            .send()
-            .await
-            .context("Failed to delete firewall rule")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to delete firewall rule ({}): {}", status, body);
-        }
+        let url = self.site_url(&format!("rest/firewallrule/{id}"));
 
+        self.send(Method::DELETE, &url, None, "delete firewall rule")
+            .await?;
         Ok(())
     }
 }
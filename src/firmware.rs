@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Ubiquiti's firmware release API, which links each release to its notes
+pub const RELEASE_API_URL: &str = "https://fw-update.ui.com/api/firmware";
+
+/// Release notes of one firmware version for a device model
+#[derive(Debug)]
+pub struct Changelog {
+    pub model: String,
+    pub version: String,
+    pub channel: Option<String>,
+    pub released: Option<String>,
+    pub url: String,
+    pub notes: String,
+}
+
+impl Changelog {
+    /// Header and notes as plain text, for the terminal
+    pub fn render(&self) -> String {
+        let mut out = format!("{} {}", self.model, self.version);
+        if let Some(channel) = &self.channel {
+            out.push_str(&format!(" ({channel})"));
+        }
+        if let Some(released) = &self.released {
+            out.push_str(&format!(", released {released}"));
+        }
+        out.push_str(&format!("\n{}\n\n{}\n", self.url, self.notes.trim_end()));
+        out
+    }
+}
+
+/// The firmware version a device record is waiting to be upgraded to
+pub fn pending_version(device: &Value) -> Option<&str> {
+    if device.get("upgradable").and_then(|v| v.as_bool()) != Some(true) {
+        return None;
+    }
+    device
+        .get("upgrade_to_firmware")
+        .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+/// The numeric parts of a version ("v6.6.77+15402" -> [6, 6, 77, 15402])
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether two spellings name the same build: the controller and the release API disagree on
+/// prefixes and build separators, and one may drop the build number
+fn same_version(a: &str, b: &str) -> bool {
+    let (a, b) = (version_parts(a), version_parts(b));
+    let len = a.len().min(b.len());
+    len >= 3 && a[..len] == b[..len]
+}
+
+/// Look `version` of `model` up in the release API at `api` (the newest release when `None`)
+/// and download its release notes
+pub async fn changelog(api: &str, model: &str, version: Option<&str>) -> Result<Changelog> {
+    let http = reqwest::Client::new();
+    let mut query = vec![
+        ("filter", "eq~~product~~unifi-firmware".to_string()),
+        ("filter", format!("eq~~platform~~{}", model.to_lowercase())),
+        ("sort", "-version".to_string()),
+        ("limit", "100".to_string()),
+    ];
+    if version.is_none() {
+        query.push(("filter", "eq~~channel~~release".to_string()));
+    }
+    let releases: Value = http
+        .get(api)
+        .query(&query)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to query {api}"))?
+        .json()
+        .await
+        .with_context(|| format!("Unexpected response from {api}"))?;
+    let releases = releases
+        .pointer("/_embedded/firmware")
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let field =
+        |release: &Value, key: &str| release.get(key).and_then(|v| v.as_str()).map(String::from);
+    let release = match version {
+        Some(version) => releases
+            .iter()
+            .find(|r| field(r, "version").is_some_and(|v| same_version(&v, version)))
+            .with_context(|| format!("Firmware {version} for {model} is not in the release API"))?,
+        None => releases
+            .first()
+            .with_context(|| format!("No firmware releases found for model {model}"))?,
+    };
+    let version = field(release, "version").unwrap_or_default();
+    let url = release
+        .pointer("/_links/changelog/href")
+        .and_then(|v| v.as_str())
+        .with_context(|| format!("No release notes published for {model} {version}"))?
+        .to_string();
+    let page = http
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?
+        .text()
+        .await?;
+    Ok(Changelog {
+        model: model.to_string(),
+        version: version.trim_start_matches('v').to_string(),
+        channel: field(release, "channel"),
+        released: field(release, "created").map(|c| c.chars().take(10).collect()),
+        url,
+        notes: html_to_text(&page),
+    })
+}
+
+/// Plain text of an HTML page: block ends become line breaks, list items bullets, and
+/// everything else between tags is dropped. Text that is not HTML passes through.
+fn html_to_text(html: &str) -> String {
+    // Only the body holds the notes; scripts and styles are not text
+    let lower = html.to_ascii_lowercase();
+    let body = match (lower.find("<body"), lower.rfind("</body>")) {
+        (Some(start), Some(end)) if start < end => &html[start..end],
+        _ => html,
+    };
+    let mut text = String::new();
+    let mut rest = body;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[open + 1..open + close].to_ascii_lowercase();
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        rest = &rest[open + close + 1..];
+        if !tag.starts_with('/') && matches!(name.as_str(), "script" | "style") {
+            let end = format!("</{name}");
+            rest = match rest.to_ascii_lowercase().find(&end) {
+                Some(at) => &rest[at..],
+                None => "",
+            };
+            continue;
+        }
+        match name.as_str() {
+            "li" if !tag.starts_with('/') => text.push_str("\n- "),
+            "br" | "p" | "div" | "ul" | "ol" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                text.push('\n')
+            }
+            _ => {}
+        }
+    }
+    text.push_str(rest);
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    // Collapse the blank runs left by nested blocks
+    let mut out = String::new();
+    let mut blank = true;
+    for line in text.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            if !blank {
+                out.push('\n');
+            }
+            blank = true;
+        } else {
+            out.push_str(line);
+            out.push('\n');
+            blank = false;
+        }
+    }
+    out
+}
@@ -0,0 +1,165 @@
+//! Home Assistant friendly views served by `unifi serve` under `/ha/`.
+//!
+//! Every response is a flat object with a top-level `state` so a RESTful sensor
+//! only needs `value_template: "{{ value_json.state }}"`. Field names are part of
+//! the contract; add fields rather than renaming them.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::Client;
+
+/// A person and the MAC addresses of the devices they carry
+#[derive(Debug, Clone)]
+pub struct Person {
+    pub name: String,
+    pub macs: Vec<String>,
+}
+
+/// Parse `--person name=mac[,mac...]`
+pub fn parse_person(s: &str) -> Result<Person> {
+    let (name, macs) = s.split_once('=').context("Expected NAME=MAC[,MAC...]")?;
+    Ok(Person {
+        name: name.to_string(),
+        macs: macs.split(',').map(|m| m.trim().to_lowercase()).collect(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Presence {
+    /// "home" or "not_home", matching HA's device_tracker states
+    pub state: &'static str,
+    pub devices_online: usize,
+    pub devices: Vec<String>,
+    /// Unix timestamp of the most recent activity of any of the person's devices
+    pub last_seen: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WanStatus {
+    /// "online" or "offline"
+    pub state: &'static str,
+    pub wan_ip: Option<String>,
+    pub isp: Option<String>,
+    pub latency_ms: Option<i64>,
+    pub uptime: Option<i64>,
+    pub rx_bytes_rate: Option<i64>,
+    pub tx_bytes_rate: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GuestWifi {
+    /// "on" if any guest SSID is enabled, otherwise "off"
+    pub state: &'static str,
+    pub ssids: Vec<String>,
+    pub clients: usize,
+}
+
+fn str_of(v: &Value, key: &str) -> Option<String> {
+    v.get(key).and_then(|v| v.as_str()).map(|s| s.to_string())
+}
+
+fn int_of(v: &Value, key: &str) -> Option<i64> {
+    v.get(key).and_then(|v| v.as_i64())
+}
+
+/// Presence for each configured person, keyed by name
+pub async fn presence(client: &Client, people: &[Person]) -> Result<BTreeMap<String, Presence>> {
+    let online = client.get_clients_online().await?;
+    let online = online.as_array().map(Vec::as_slice).unwrap_or_default();
+
+    Ok(people
+        .iter()
+        .map(|person| {
+            let matching: Vec<&Value> = online
+                .iter()
+                .filter(|c| {
+                    str_of(c, "mac").is_some_and(|mac| person.macs.contains(&mac.to_lowercase()))
+                })
+                .collect();
+            let presence = Presence {
+                state: if matching.is_empty() {
+                    "not_home"
+                } else {
+                    "home"
+                },
+                devices_online: matching.len(),
+                devices: matching.iter().filter_map(|c| str_of(c, "mac")).collect(),
+                last_seen: matching.iter().filter_map(|c| int_of(c, "last_seen")).max(),
+            };
+            (person.name.clone(), presence)
+        })
+        .collect())
+}
+
+/// WAN state from the controller's `www`/`wan` health subsystems
+pub async fn wan(client: &Client) -> Result<WanStatus> {
+    let health = client.get_health().await?;
+    Ok(wan_from_health(&health))
+}
+
+/// WAN state from an already fetched `stat/health` response
+pub fn wan_from_health(health: &Value) -> WanStatus {
+    let subsystem = |name: &str| {
+        health
+            .as_array()
+            .and_then(|a| {
+                a.iter()
+                    .find(|s| str_of(s, "subsystem").as_deref() == Some(name))
+            })
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    let wan = subsystem("wan");
+    let www = subsystem("www");
+    let up = str_of(&www, "status")
+        .or_else(|| str_of(&wan, "status"))
+        .is_some_and(|s| s == "ok");
+
+    WanStatus {
+        state: if up { "online" } else { "offline" },
+        wan_ip: str_of(&wan, "wan_ip"),
+        isp: str_of(&wan, "isp_name"),
+        latency_ms: int_of(&www, "latency"),
+        uptime: int_of(&www, "uptime"),
+        rx_bytes_rate: int_of(&wan, "rx_bytes-r"),
+        tx_bytes_rate: int_of(&wan, "tx_bytes-r"),
+    }
+}
+
+/// Guest SSID state and number of connected guests
+pub async fn guest_wifi(client: &Client) -> Result<GuestWifi> {
+    let (wlans, online) = tokio::try_join!(client.get_wifi(), client.get_clients_online())?;
+
+    let guest_wlans: Vec<&Value> = wlans
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter(|w| w.get("is_guest").and_then(|g| g.as_bool()) == Some(true))
+                .collect()
+        })
+        .unwrap_or_default();
+    let enabled = guest_wlans
+        .iter()
+        .any(|w| w.get("enabled").and_then(|e| e.as_bool()) == Some(true));
+    let clients = online
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter(|c| c.get("is_guest").and_then(|g| g.as_bool()) == Some(true))
+                .count()
+        })
+        .unwrap_or(0);
+
+    Ok(GuestWifi {
+        state: if enabled { "on" } else { "off" },
+        ssids: guest_wlans
+            .iter()
+            .filter_map(|w| str_of(w, "name"))
+            .collect(),
+        clients,
+    })
+}
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+
+use crate::api::Client;
+use crate::config::Hook;
+
+/// Event key of a controller event record (e.g., `EVT_WU_Disconnected`)
+pub fn event_key(event: &Value) -> Option<&str> {
+    event.get("key").and_then(|k| k.as_str())
+}
+
+impl Hook {
+    pub fn matches(&self, key: &str) -> bool {
+        match self.event.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => self.event == key,
+        }
+    }
+
+    /// Run the hook's command with the event JSON on stdin and `UNIFI_EVENT` set to its key
+    pub async fn run(&self, event: &Value) -> Result<()> {
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", &self.run])
+            .env("UNIFI_EVENT", event_key(event).unwrap_or_default())
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run '{}'", self.run))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that does not read its input is fine
+            let _ = stdin.write_all(event.to_string().as_bytes()).await;
+        }
+        let status = child.wait().await?;
+        anyhow::ensure!(status.success(), "'{}' exited with {status}", self.run);
+        Ok(())
+    }
+}
+
+/// Tracks which events were already delivered between polls of the event log
+#[derive(Default)]
+pub struct EventCursor {
+    seen: Option<HashSet<String>>,
+}
+
+impl EventCursor {
+    /// Events of `batch` not returned by the previous poll, oldest first. The first poll only
+    /// records what is there, so hooks fire for new events only.
+    pub fn advance(&mut self, batch: Vec<Value>) -> Vec<Value> {
+        let id = |e: &Value| e.get("_id").and_then(|v| v.as_str()).map(String::from);
+        let ids: HashSet<String> = batch.iter().filter_map(id).collect();
+        let fresh = match &self.seen {
+            None => vec![],
+            Some(seen) => {
+                let mut fresh: Vec<Value> = batch
+                    .into_iter()
+                    .filter(|e| id(e).is_some_and(|i| !seen.contains(&i)))
+                    .collect();
+                fresh.sort_by_key(|e| e.get("time").and_then(|t| t.as_i64()));
+                fresh
+            }
+        };
+        self.seen = Some(ids);
+        fresh
+    }
+}
+
+impl Client {
+    /// Most recent controller events (newest first), from the last hour
+    pub async fn get_recent_events(&self, limit: usize) -> Result<Vec<Value>> {
+        let query = json!({"within": 1, "_sort": "-time", "_limit": limit});
+        let events = self.query_stat("event", &query).await?;
+        Ok(match events {
+            Value::Array(events) => events,
+            _ => vec![],
+        })
+    }
+}
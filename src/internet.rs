@@ -1,8 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value, json};
 
-use crate::api::Client;
+use crate::api::{Client, NotFound};
+use crate::{duration, ha};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DnsSettings {
@@ -14,30 +15,247 @@ pub struct DnsSettings {
     pub dns2_ipv6: Option<String>,
 }
 
+/// The fields of the WAN `networkconf` object the summary needs
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WanNetwork {
+    name: Option<String>,
+    wan_type: Option<String>,
+    wan_ip: Option<String>,
+    wan_gateway: Option<String>,
+    wan_dns_preference: Option<String>,
+    wan_dns1: Option<String>,
+    wan_dns2: Option<String>,
+    wan_vlan_enabled: bool,
+    /// A number or a numeric string depending on the controller version
+    wan_vlan: Value,
+    wan_type_v6: Option<String>,
+}
+
+/// `internet all` at a glance: the WAN configuration, with the live address, gateway, and
+/// resolvers from site health where the configuration leaves them to the ISP
+#[derive(Debug, Serialize)]
+pub struct WanSummary {
+    pub name: Option<String>,
+    /// dhcp, static, or pppoe
+    pub connection: String,
+    pub wan_ip: Option<String>,
+    pub gateway: Option<String>,
+    pub dns: Vec<String>,
+    pub uptime: Option<String>,
+    pub vlan: Option<i64>,
+    pub ipv6: String,
+}
+
+fn non_empty(s: &Option<String>) -> Option<String> {
+    s.clone().filter(|s| !s.is_empty())
+}
+
+/// Strings of a health field that is a list (`gateways`, `nameservers`)
+fn health_list(wan: &Value, key: &str) -> Vec<String> {
+    wan.get(key)
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn summarize(network: Value, health: &Value) -> Result<WanSummary> {
+    let net: WanNetwork = serde_json::from_value(network)?;
+    let live = ha::wan_from_health(health);
+    let wan = health
+        .as_array()
+        .and_then(|a| a.iter().find(|s| s.get("subsystem") == Some(&"wan".into())))
+        .cloned()
+        .unwrap_or(Value::Null);
+
+    let configured_dns: Vec<String> = [&net.wan_dns1, &net.wan_dns2]
+        .into_iter()
+        .filter_map(non_empty)
+        .collect();
+    let dns = match net.wan_dns_preference.as_deref() {
+        Some("manual") if !configured_dns.is_empty() => configured_dns,
+        _ => health_list(&wan, "nameservers"),
+    };
+    let vlan = match &net.wan_vlan {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    };
+
+    Ok(WanSummary {
+        name: net.name,
+        connection: non_empty(&net.wan_type).unwrap_or_else(|| "dhcp".into()),
+        wan_ip: live.wan_ip.or_else(|| non_empty(&net.wan_ip)),
+        gateway: non_empty(&net.wan_gateway)
+            .or_else(|| health_list(&wan, "gateways").into_iter().next()),
+        dns,
+        uptime: live.uptime.map(duration::humanize),
+        vlan: vlan.filter(|_| net.wan_vlan_enabled),
+        ipv6: non_empty(&net.wan_type_v6).unwrap_or_else(|| "disabled".into()),
+    })
+}
+
+/// How traffic is spread over several WANs
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum LoadBalanceMode {
+    /// Spread connections by weight
+    Weighted,
+    /// Use the highest-priority WAN that is up; the others stand by
+    FailoverOnly,
+}
+
+impl LoadBalanceMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Weighted => "weighted",
+            Self::FailoverOnly => "failover-only",
+        }
+    }
+}
+
+/// One WAN's part in load balancing
+#[derive(Debug, Serialize)]
+pub struct WanBalance {
+    /// `wan1`, `wan2`, ... by the WAN port group
+    pub wan: String,
+    pub name: Option<String>,
+    pub mode: Option<String>,
+    /// Share of connections in weighted mode, in percent
+    pub weight: Option<i64>,
+    /// 1 is used first in failover-only mode
+    pub failover_priority: Option<i64>,
+}
+
+/// WAN number from `wan_networkgroup` (`WAN` is 1, `WAN2` is 2, ...)
+fn wan_slot(network: &Value) -> usize {
+    network
+        .get("wan_networkgroup")
+        .and_then(|v| v.as_str())
+        .and_then(|g| g.strip_prefix("WAN"))
+        .map_or(1, |n| n.parse().unwrap_or(1))
+}
+
+fn balance(network: &Value) -> WanBalance {
+    WanBalance {
+        wan: format!("wan{}", wan_slot(network)),
+        name: network
+            .get("name")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        mode: network
+            .get("wan_load_balance_type")
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        weight: network
+            .get("wan_load_balance_weight")
+            .and_then(|v| v.as_i64()),
+        failover_priority: network
+            .get("wan_failover_priority")
+            .and_then(|v| v.as_i64()),
+    }
+}
+
 impl Client {
-    async fn get_wan_network(&self) -> Result<Value> {
-        let url = format!(
-            "{}/proxy/network/api/s/default/rest/networkconf",
-            self.base_url
+    /// WAN networks ordered by WAN number
+    async fn get_wan_networks(&self) -> Result<Vec<Value>> {
+        let networks = self.get_rest("networkconf").await?;
+        let mut wans: Vec<Value> = networks
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter(|n| n.get("purpose").and_then(|p| p.as_str()) == Some("wan"))
+            .cloned()
+            .collect();
+        wans.sort_by_key(wan_slot);
+        Ok(wans)
+    }
+
+    /// Load-balancing mode, weight, and failover priority of each WAN
+    pub async fn get_load_balance(&self) -> Result<Vec<WanBalance>> {
+        Ok(self.get_wan_networks().await?.iter().map(balance).collect())
+    }
+
+    /// Set the load-balancing mode of every WAN. `weights` are percentages by WAN number
+    /// (`[wan1, wan2]`); with two WANs a missing one is the rest of 100, and they must add
+    /// up to 100. In failover-only mode, `primary` (a WAN number) gets priority 1 and the
+    /// others follow in order.
+    pub async fn set_load_balance(
+        &self,
+        mode: LoadBalanceMode,
+        weights: [Option<u32>; 2],
+        primary: usize,
+    ) -> Result<Vec<WanBalance>> {
+        let wans = self.get_wan_networks().await?;
+        anyhow::ensure!(
+            wans.len() >= 2,
+            "Load balancing needs at least two WANs; this site has {}",
+            wans.len()
         );
+        let slots: Vec<usize> = wans.iter().map(wan_slot).collect();
+        anyhow::ensure!(
+            slots.contains(&primary),
+            "No WAN {primary} (this site has {})",
+            slots
+                .iter()
+                .map(|s| format!("wan{s}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let weights = match (mode, weights, wans.len()) {
+            (LoadBalanceMode::FailoverOnly, _, _) => vec![None; wans.len()],
+            (_, [Some(a), None], 2) => vec![Some(a), 100u32.checked_sub(a)],
+            (_, [None, Some(b)], 2) => vec![100u32.checked_sub(b), Some(b)],
+            (_, [a, b], n) => {
+                let mut weights = vec![a, b];
+                weights.resize(n, None);
+                weights
+            }
+        };
+        if mode == LoadBalanceMode::Weighted {
+            let given: Vec<u32> = weights.iter().flatten().copied().collect();
+            anyhow::ensure!(
+                given.len() == wans.len(),
+                "Give a weight for every WAN (--wan1, --wan2)"
+            );
+            anyhow::ensure!(
+                given.iter().all(|w| (1..=99).contains(w)) && given.iter().sum::<u32>() == 100,
+                "Weights must be 1-99 and add up to 100, got {}",
+                given
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" + ")
+            );
+        }
 
-        let resp = self
-            .http
-            .get(&url)
-            .header("X-API-Key", &self.api_key)
-            .send()
-            .await
-            .context("Failed to fetch network config")?;
-
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            anyhow::bail!("Failed to get network config ({}): {}", status, body);
+        // The primary first, then the rest in WAN order
+        let mut order = slots.clone();
+        order.sort_by_key(|s| (*s != primary, *s));
+        let mut updated = Vec::new();
+        for ((network, slot), weight) in wans.iter().zip(&slots).zip(weights) {
+            let priority = order.iter().position(|s| s == slot).unwrap_or_default() + 1;
+            let mut fields = Map::new();
+            fields.insert("wan_load_balance_type".into(), json!(mode.as_str()));
+            fields.insert("wan_failover_priority".into(), json!(priority));
+            if let Some(weight) = weight {
+                fields.insert("wan_load_balance_weight".into(), json!(weight));
+            }
+            let network = self.update_network(network, &fields).await?;
+            updated.push(balance(&network));
         }
+        Ok(updated)
+    }
 
-        let body: Value = resp.json().await?;
+    async fn get_wan_network(&self) -> Result<Value> {
+        let networks = self.get_rest("networkconf").await?;
 
-        if let Some(data) = body.get("data").and_then(|d| d.as_array()) {
+        if let Some(data) = networks.as_array() {
             for network in data {
                 let purpose = network.get("purpose").and_then(|p| p.as_str());
                 if purpose == Some("wan") {
@@ -46,7 +264,7 @@ impl Client {
             }
         }
 
-        anyhow::bail!("No WAN network found")
+        Err(NotFound("No WAN network found".to_string()).into())
     }
 
     /// Get all WAN settings
@@ -54,6 +272,12 @@ impl Client {
         self.get_wan_network().await
     }
 
+    /// Summarized WAN configuration and live state
+    pub async fn get_wan_summary(&self) -> Result<WanSummary> {
+        let (network, health) = tokio::try_join!(self.get_wan_network(), self.get_health())?;
+        summarize(network, &health)
+    }
+
     /// Get DNS settings from internet/WAN configuration
     pub async fn get_dns_settings(&self) -> Result<DnsSettings> {
         let network = self.get_wan_network().await?;
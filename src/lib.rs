@@ -0,0 +1,50 @@
+//! UniFi Network controller client, and the building blocks of the `unifi` CLI
+
+pub mod api;
+pub mod audit;
+pub mod automate;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod clients;
+pub mod config;
+pub mod copy;
+pub mod devices;
+pub mod diag;
+#[cfg(feature = "cli")]
+pub mod discover;
+pub mod dns;
+#[cfg(feature = "cli")]
+pub mod doctor;
+pub mod duration;
+pub mod firewall;
+pub mod firmware;
+pub mod ha;
+#[cfg(feature = "cli")]
+pub mod hooks;
+pub mod internet;
+pub mod maintenance;
+pub mod networks;
+pub mod oui;
+pub mod output;
+pub mod overview;
+pub mod pager;
+pub mod permissions;
+pub mod portal;
+pub mod repl;
+pub mod report;
+pub mod schedule;
+pub mod search;
+pub mod secret;
+pub mod security;
+#[cfg(feature = "cli")]
+pub mod serve;
+pub mod stats;
+pub mod style;
+pub mod system;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tls;
+pub mod transport;
+pub mod vpn;
+pub mod wifi;
+#[cfg(feature = "cli")]
+pub mod wifi_card;
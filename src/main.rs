@@ -1,36 +1,191 @@
-mod api;
-mod clients;
-mod config;
-mod devices;
-mod dns;
-mod firewall;
-mod internet;
-mod networks;
-mod security;
-mod vpn;
-mod wifi;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::time::Duration;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use unifi::{
+    api, audit, automate, clients, config, copy, devices, discover, doctor, duration, firewall,
+    firmware, ha, hooks, internet, maintenance, networks, oui, output, pager, portal, repl, report,
+    schedule, secret, security, serve, stats, style, system, tls, wifi, wifi_card,
+};
+
+/// Exit codes: 0 ok, 1 generic error, 2 usage (clap), 3 auth, 4 not found, 5 API error
+const EXIT_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_AUTH: u8 = 3;
+const EXIT_NOT_FOUND: u8 = 4;
+const EXIT_API: u8 = 5;
 
 #[derive(Parser)]
 #[command(name = "unifi")]
 #[command(about = "CLI tool to access UniFi router API")]
 struct Cli {
+    /// Suppress informational messages (data still goes to stdout, errors to stderr)
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Do not ask before deleting, kicking, or blocking (required without a terminal)
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Print the method, URL, and body of every change instead of sending it
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Trace HTTP requests to stderr: -v for URLs, status, and timing, -vv adds headers
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Same as -vv
+    #[arg(long, global = true)]
+    debug: bool,
+
+    /// Log level for stderr logs [default: warn, or from -v]; RUST_LOG overrides it
+    #[arg(long, global = true)]
+    log_level: Option<LogLevel>,
+
+    /// Log line format (json for systemd journals and log shippers)
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Exit with status 4 when a list command returns no results
+    #[arg(long, global = true)]
+    fail_on_empty: bool,
+
+    /// Print each controller request's latency and the total wall time to stderr at the end
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Output format [default: json, or the profile's `output` or `defaults.output` from the
+    /// config]
+    #[arg(short, long, global = true)]
+    output: Option<output::Format>,
+
+    /// When to color output [default: auto, or `defaults.color` from the config]
+    #[arg(long, global = true)]
+    color: Option<style::ColorChoice>,
+
+    /// Print straight to the terminal instead of through $PAGER
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// Same as --color never
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+
+    /// Show long nested JSON arrays in full on a terminal instead of only their first items
+    #[arg(long, global = true)]
+    no_fold: bool,
+
+    /// Re-run the command every SECONDS (default 2), redrawing the screen and highlighting
+    /// lines that changed since the previous refresh
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "2"
+    )]
+    watch: Option<u64>,
+
+    /// Give up on a controller request after SECS seconds, 0 for no limit
+    /// [default: 30, or `defaults.timeout` from the config]
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Change ticket sent as `X-Change-Ticket` with every change, for matching changes to
+    /// change records in proxy logs
+    #[arg(long, global = true, value_name = "ID")]
+    ticket: Option<String>,
+
+    /// Controller host (e.g., 192.168.2.1 or https://host:8443/prefix) for this command only,
+    /// over the config [env: UNIFI_HOST]; with `unifi config`, the host to save
+    #[arg(short = 'H', long, global = true)]
+    host: Option<String>,
+
+    /// API key for this command only, over the config [env: UNIFI_API_KEY]; with
+    /// `unifi config`, the key to save
+    #[arg(short, long, global = true)]
+    api_key: Option<String>,
+
+    /// Accept any controller certificate (e.g., a console's self-signed one) for this
+    /// command; with `unifi config`, save `insecure = true` to the profile's [tls] table
+    #[arg(long, global = true)]
+    insecure: bool,
+
+    /// Config file to read and save
+    /// [env: UNIFI_CONFIG; default: unifi/config.toml under $XDG_CONFIG_HOME or ~/.config]
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// Named controller from `profiles` in the config [env: UNIFI_PROFILE]
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Site by short name, display name, or ID (see `unifi sites`)
+    /// [env: UNIFI_SITE; default: the profile's or `defaults.site` from the config, else default]
+    #[arg(long, global = true)]
+    site: Option<String>,
+
+    /// Only print these fields of each object (comma-separated, e.g. mac,ip,name)
+    #[arg(long, global = true, value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// Sort list output by a field (e.g. last_seen, uptime:desc, system-stats.cpu:desc)
+    #[arg(long, global = true, value_name = "FIELD[:desc]")]
+    sort_by: Option<output::SortKey>,
+
+    /// Only print list records matching FIELD=VALUE, FIELD!=VALUE, or FIELD~=SUBSTRING
+    /// (repeatable, all must match; case-insensitive)
+    #[arg(long = "filter", global = true, value_name = "EXPR")]
+    filters: Vec<output::Filter>,
+
+    /// Print at most N records of a list
+    #[arg(long, global = true, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Skip the first N records of a list (after --filter and --sort-by)
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    offset: usize,
+
+    /// Print one line per record from a template instead of --output, e.g. "{name}\t{ip}"
+    /// (fields in braces, dotted paths reach into objects; \t and \n are expanded)
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    template: Option<output::Template>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Configure host and API key
+    #[command(args_conflicts_with_subcommands = true)]
     Config {
-        /// UniFi controller/UDM host (e.g., 192.168.2.1)
-        #[arg(short = 'H', long)]
-        host: Option<String>,
-        /// API key
-        #[arg(short, long)]
-        api_key: Option<String>,
+        #[command(subcommand)]
+        command: Option<ConfigCommands>,
+        /// Local account username, for session login instead of an API key
+        #[arg(long)]
+        username: Option<String>,
+        /// Local account password
+        #[arg(long)]
+        password: Option<String>,
+        /// Classic self-hosted controller without the UniFi OS `/proxy/network` prefix
+        /// (detected automatically when not set)
+        #[arg(long)]
+        legacy: Option<bool>,
+        /// Store the API keys and password encrypted with a passphrase (asked for, or taken
+        /// from UNIFI_PASSPHRASE)
+        #[arg(long, conflicts_with = "decrypt")]
+        encrypt: bool,
+        /// Store the API keys and password in plaintext again
+        #[arg(long)]
+        decrypt: bool,
     },
     /// Internet/WAN settings
     Internet {
@@ -43,7 +198,10 @@ enum Commands {
         command: DnsCommands,
     },
     /// Security settings (IPS, ad blocking, DNS filtering)
-    Security,
+    Security {
+        #[command(subcommand)]
+        command: Option<SecurityCommands>,
+    },
     /// Firewall rules and policies
     Firewall {
         #[command(subcommand)]
@@ -55,26 +213,241 @@ enum Commands {
         command: VpnCommands,
     },
     /// Network/VLAN settings
-    Networks,
+    Networks {
+        #[command(subcommand)]
+        command: Option<NetworksCommands>,
+    },
     /// WiFi/WLAN settings
-    Wifi,
+    Wifi {
+        #[command(subcommand)]
+        command: Option<WifiCommands>,
+    },
     /// UniFi devices (APs, switches, gateways)
-    Devices,
+    Devices {
+        /// Add clients, CPU and memory columns
+        #[arg(long, conflicts_with = "short")]
+        wide: bool,
+        /// Only name, model, IP and state
+        #[arg(long)]
+        short: bool,
+        #[command(subcommand)]
+        command: Option<DevicesCommands>,
+    },
     /// Connected clients
     Clients {
         #[command(subcommand)]
         command: ClientsCommands,
     },
+    /// Controller maintenance and system settings
+    System {
+        #[command(subcommand)]
+        command: SystemCommands,
+    },
+    /// Maintenance window: back up, pause alerts (and IPS blocking), and restore afterwards
+    Maintenance {
+        #[command(subcommand)]
+        command: MaintenanceCommands,
+    },
+    /// Guest portal branding
+    Portal {
+        #[command(subcommand)]
+        command: PortalCommands,
+    },
+    /// MAC vendor (OUI) lookup
+    Oui {
+        #[command(subcommand)]
+        command: OuiCommands,
+    },
+    /// Switch port checks
+    Switch {
+        #[command(subcommand)]
+        command: SwitchCommands,
+    },
+    /// Historical statistics from controller reports
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommands,
+    },
+    /// Call any controller endpoint with the configured credentials
+    Api {
+        #[command(subcommand)]
+        command: ApiCommands,
+    },
+    /// Site summary: health, device and client counts, WAN, and active alerts
+    Overview,
+    /// Find clients, devices, networks, and WLANs by name, MAC, IP, or SSID
+    Search {
+        /// Text to look for (an IP also finds the network containing it)
+        term: String,
+    },
+    /// What a controller object ID (e.g., from an API response) refers to
+    Resolve {
+        /// Object ID (`_id`)
+        id: String,
+    },
+    /// List the sites on the controller
+    Sites,
+    /// Find UniFi consoles and devices on the local network (UDP discovery on port 10001) and
+    /// offer to save a console as the controller host
+    Discover {
+        /// Where to send the probe: the broadcast address, a subnet's (e.g., 192.168.1.255),
+        /// or one device's
+        #[arg(long, default_value = "255.255.255.255")]
+        address: std::net::IpAddr,
+        /// How long to wait for answers
+        #[arg(long, default_value = "3s", value_parser = duration::parse)]
+        wait: Duration,
+    },
+    /// Check configuration, connectivity, certificate, clock, and credentials
+    Doctor,
+    /// Probe what the current credentials may do, one harmless request per capability
+    /// (writes target an object that does not exist; with --dry-run only reads are probed)
+    Permissions,
+    /// Read commands from stdin and run them in one session: the login, the site and profile
+    /// selected with `site`/`profile`, and the last result (`$_`) carry over between lines
+    Repl,
+    /// Diagnostics run on devices
+    Diag {
+        #[command(subcommand)]
+        command: DiagCommands,
+    },
+    /// Evaluate remediation rules in a loop and run their actions, printing one JSON line per
+    /// action (always JSON lines, whatever --output says)
+    Automate {
+        /// Rule as CONDITION => ACTION (repeatable), e.g. 'device-offline>5m => restart',
+        /// 'client-rssi<-80 => kick', 'wan-loss>5% => webhook:https://...'
+        #[arg(long = "rule", required = true, value_parser = automate::parse_rule)]
+        rules: Vec<automate::Rule>,
+        /// How often to evaluate the rules
+        #[arg(long, value_parser = duration::parse, default_value = "30s")]
+        interval: std::time::Duration,
+        /// Minimum time between two actions of a rule on the same target
+        #[arg(long, value_parser = duration::parse, default_value = "30m")]
+        cooldown: std::time::Duration,
+    },
+    /// Security audits of controller access
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    /// Run scripts when controller events happen
+    Hooks {
+        #[command(subcommand)]
+        command: HooksCommands,
+    },
+    /// Friendly names for client and device MACs, usable wherever a MAC is expected
+    Alias {
+        #[command(subcommand)]
+        command: Option<AliasCommands>,
+    },
+    /// Copy objects from one controller/site to another, remapping IDs by name
+    Copy {
+        /// Source as [PROFILE:]SITE (e.g., home:default)
+        #[arg(long, value_parser = copy::parse_location)]
+        from: copy::Location,
+        /// Target as [PROFILE:]SITE (e.g., branch:default)
+        #[arg(long, value_parser = copy::parse_location)]
+        to: copy::Location,
+        /// Resources to copy (comma-separated)
+        #[arg(long, value_delimiter = ',', required = true)]
+        resources: Vec<copy::Resource>,
+        /// Prefix prepended to the name of every copied object
+        #[arg(long)]
+        rename_prefix: Option<String>,
+    },
+    /// Generate a summary report from controller statistics
+    Report {
+        /// Reporting period
+        period: report::Period,
+        /// Sections to include (comma-separated)
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "wan,clients,threats,devices"
+        )]
+        sections: Vec<report::SectionKind>,
+        /// Output format
+        #[arg(long, default_value = "md")]
+        format: report::Format,
+        /// Write the report to a file instead of stdout
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        /// E-mail the report to this address (via the local sendmail)
+        #[arg(long)]
+        mail: Option<String>,
+    },
+    /// Run a local REST API that shares one cached controller session
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8799")]
+        listen: std::net::SocketAddr,
+        /// Bearer token required by callers (default: $UNIFI_SERVE_TOKEN, else generated)
+        #[arg(long)]
+        token: Option<String>,
+        /// How long controller responses are cached (e.g., 10s, 1m)
+        #[arg(long, default_value = "10s", value_parser = duration::parse)]
+        cache_ttl: std::time::Duration,
+        /// Track a person's presence for /ha/presence (NAME=MAC[,MAC...], repeatable)
+        #[arg(long = "person", value_parser = ha::parse_person)]
+        people: Vec<ha::Person>,
+    },
+}
+
+#[derive(Subcommand)]
+enum NetworksCommands {
+    /// List networks
+    List,
+    /// Create the standard isolation firewall rules for a network and verify them
+    Isolate {
+        /// Network name or ID
+        network: String,
+        /// Let clients use the gateway as their DNS server
+        #[arg(long)]
+        allow_dns: bool,
+        /// Still allow traffic to this network or CIDR (repeatable)
+        #[arg(long)]
+        allow_to: Vec<String>,
+    },
+    /// Let clients on another network reach one service on a device (mDNS plus firewall rules)
+    Expose {
+        /// Device IP address, or client name, hostname, or MAC
+        device: String,
+        /// Service to expose
+        #[arg(long)]
+        service: networks::Service,
+        /// Network whose clients should reach it
+        #[arg(long)]
+        to: String,
+    },
+    /// Change network settings
+    Set {
+        /// Network name or ID
+        network: String,
+        /// Default user group (bandwidth profile) for clients joining the network
+        #[arg(long)]
+        user_group: String,
+    },
 }
 
 #[derive(Subcommand)]
 enum FirewallCommands {
     /// List firewall rules
     Rules,
-    /// List firewall groups (IP groups, port groups)
-    Groups,
+    /// Firewall groups (IP groups, port groups)
+    Groups {
+        #[command(subcommand)]
+        command: Option<FirewallGroupsCommands>,
+    },
     /// List traffic rules
     Traffic,
+    /// List ICMP/ICMPv6 type names accepted by --icmp-type
+    IcmpTypes,
+    /// Diagram of networks and zones with the rules between them as allow/block edges
+    Graph {
+        /// Diagram syntax
+        #[arg(long, default_value = "dot")]
+        format: firewall::GraphFormat,
+    },
     /// Create a firewall rule
     Add {
         /// Rule name
@@ -84,6 +457,7 @@ enum FirewallCommands {
         #[arg(long)]
         action: String,
         /// Ruleset: LAN_IN, LAN_OUT, LAN_LOCAL, WAN_IN, WAN_OUT, WAN_LOCAL, etc.
+        /// IPv6 rulesets (WANv6_IN, LANv6_LOCAL, ...) use NETv6 defaults
         #[arg(long)]
         ruleset: String,
         /// Rule index (priority order)
@@ -98,6 +472,9 @@ enum FirewallCommands {
         /// Protocol: tcp, udp, tcp_udp, all, etc.
         #[arg(long)]
         protocol: Option<String>,
+        /// ICMP/ICMPv6 type name, e.g. echo-request (implies protocol icmp/icmpv6)
+        #[arg(long)]
+        icmp_type: Option<String>,
         /// Source port
         #[arg(long)]
         src_port: Option<String>,
@@ -110,6 +487,10 @@ enum FirewallCommands {
         /// Destination firewall group IDs (comma-separated)
         #[arg(long, value_delimiter = ',')]
         dst_firewallgroup_ids: Option<Vec<String>>,
+        /// Active times, e.g. "mon-fri 08:00-17:00" or "weekends Europe/Paris"
+        /// (requires firmware with scheduled firewall rules)
+        #[arg(long, value_parser = schedule::parse)]
+        schedule: Option<schedule::Schedule>,
         /// Enable the rule (default: true)
         #[arg(long, default_value_t = true)]
         enabled: bool,
@@ -139,6 +520,9 @@ enum FirewallCommands {
         /// Protocol: tcp, udp, tcp_udp, all, etc.
         #[arg(long)]
         protocol: Option<String>,
+        /// ICMP/ICMPv6 type name, e.g. echo-request (implies protocol icmp/icmpv6)
+        #[arg(long)]
+        icmp_type: Option<String>,
         /// Source port
         #[arg(long)]
         src_port: Option<String>,
@@ -151,6 +535,9 @@ enum FirewallCommands {
         /// Destination firewall group IDs (comma-separated)
         #[arg(long, value_delimiter = ',')]
         dst_firewallgroup_ids: Option<Vec<String>>,
+        /// Active times, e.g. "mon-fri 08:00-17:00", or "always"
+        #[arg(long, value_parser = schedule::parse)]
+        schedule: Option<schedule::Schedule>,
         /// Enable or disable the rule
         #[arg(long)]
         enabled: Option<bool>,
@@ -160,11 +547,443 @@ enum FirewallCommands {
     },
     /// Delete a firewall rule by ID
     Delete {
-        /// Rule ID
+        /// Rule ID, or - to read one per line from stdin
         id: String,
     },
 }
 
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// Per-client latency over time, as a terminal heatmap
+    Latency {
+        /// Only this client (MAC)
+        #[arg(long)]
+        client: Option<String>,
+        /// Look-back window (e.g., 24h, 7d)
+        #[arg(long, default_value = "24h", value_parser = duration::parse)]
+        range: std::time::Duration,
+        /// Write CSV samples instead of the heatmap
+        #[arg(long)]
+        csv: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApiCommands {
+    /// GET a path
+    Get {
+        /// Path relative to the Network application (e.g., `api/s/{site}/stat/health`), or to
+        /// the host with a leading `/`
+        path: String,
+    },
+    /// POST a JSON body to a path
+    Post {
+        /// Path, as for `get`
+        path: String,
+        /// JSON file to send (`-` for stdin)
+        #[arg(long)]
+        body: Option<std::path::PathBuf>,
+    },
+    /// PUT a JSON body to a path
+    Put {
+        /// Path, as for `get`
+        path: String,
+        /// JSON file to send (`-` for stdin)
+        #[arg(long)]
+        body: Option<std::path::PathBuf>,
+    },
+    /// DELETE a path
+    Delete {
+        /// Path, as for `get`
+        path: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommands {
+    /// Admin sign-ins and API key usage, flagging unexpected source addresses
+    Remote {
+        /// How far back to look (e.g., 24h, 7d)
+        #[arg(long, value_parser = duration::parse, default_value = "7d")]
+        since: std::time::Duration,
+        /// Expected source networks (comma-separated CIDRs or addresses;
+        /// default: private and loopback ranges)
+        #[arg(long, value_delimiter = ',', value_parser = audit::parse_network)]
+        allow: Vec<audit::Network>,
+        /// Only show accesses from unexpected addresses
+        #[arg(long)]
+        unexpected_only: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HooksCommands {
+    /// List configured hooks
+    List,
+    /// Run a command when an event arrives (the event JSON is passed on stdin)
+    Add {
+        /// Event key (e.g., EVT_WU_Disconnected, or EVT_AP_* for a prefix)
+        #[arg(long)]
+        event: String,
+        /// Shell command to run
+        #[arg(long)]
+        run: String,
+    },
+    /// Remove a hook by its position in `hooks list` (starting at 0)
+    Remove { index: usize },
+    /// Watch the event log and run matching hooks until interrupted
+    Run {
+        /// How often to poll for new events
+        #[arg(long, value_parser = duration::parse, default_value = "5s")]
+        interval: std::time::Duration,
+    },
+}
+
+#[derive(Subcommand)]
+enum AliasCommands {
+    /// List aliases (default)
+    List,
+    /// Name a client or device (replaces an alias of the same name)
+    Add {
+        /// Alias (e.g., living-room-tv)
+        name: String,
+        /// MAC address or ID it stands for
+        mac: String,
+    },
+    /// Forget an alias
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum DiagCommands {
+    /// Built-in throughput test from a device to the gateway
+    Throughput {
+        /// Device name, MAC, IP, or alias
+        device: String,
+        /// Give up on the test after this long
+        #[arg(long, default_value = "90s", value_parser = duration::parse)]
+        wait: std::time::Duration,
+    },
+    /// Events, alarms, WAN samples, and device changes around a moment, in order
+    Timeline {
+        /// The moment to look at: "YYYY-MM-DD HH:MM[:SS]" (local time), RFC 3339, or now
+        #[arg(long, value_parser = duration::parse_time)]
+        around: i64,
+        /// How far to look before and after it (e.g., 10m, 1h)
+        #[arg(long, default_value = "10m", value_parser = duration::parse)]
+        window: std::time::Duration,
+    },
+}
+
+#[derive(Subcommand)]
+enum FirewallGroupsCommands {
+    /// List firewall groups (default)
+    List,
+    /// Show which rules reference a group, and their hit counts
+    Usage {
+        /// Group name or ID
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecurityCommands {
+    /// Show security settings (default)
+    Settings,
+    /// List clients first seen recently
+    NewDevices {
+        /// Look-back window (e.g., 24h, 7d)
+        #[arg(long, default_value = "24h", value_parser = duration::parse)]
+        since: std::time::Duration,
+        /// Exit non-zero if any new device joined a sensitive network
+        #[arg(long)]
+        alert: bool,
+        /// Networks that trigger --alert (comma-separated; default: any network)
+        #[arg(long, value_delimiter = ',')]
+        sensitive: Vec<String>,
+    },
+    /// Compare online clients against an approved MAC list
+    EnforceAllowlist {
+        /// File with one approved MAC per line (`#` comments allowed)
+        #[arg(long)]
+        file: std::path::PathBuf,
+        /// What to do with clients not on the list
+        #[arg(long, value_enum, default_value = "alert")]
+        action: AllowlistAction,
+    },
+    /// List active UPnP port mappings, or revoke one
+    UpnpLeases {
+        /// Lease ID to remove (from the listing)
+        #[arg(long)]
+        revoke: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, with span and event fields
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AllowlistAction {
+    /// Block unknown clients
+    Block,
+    /// Only report unknown clients (exits non-zero if any are found)
+    Alert,
+}
+
+#[derive(Subcommand)]
+enum SystemCommands {
+    /// Prune old events and long-gone clients
+    #[command(group(clap::ArgGroup::new("what").required(true).multiple(true)))]
+    Prune {
+        /// Keep events only for this long (sets the controller's retention policy)
+        #[arg(long, group = "what", value_parser = duration::parse)]
+        events_older_than: Option<std::time::Duration>,
+        /// Forget clients not seen for this long
+        #[arg(long, group = "what", value_parser = duration::parse)]
+        clients_not_seen: Option<std::time::Duration>,
+        /// Also forget clients that have a name, fixed IP, or are blocked
+        #[arg(long)]
+        include_named: bool,
+    },
+    /// Generate and download the controller support file for support tickets
+    SupportBundle {
+        /// Where to save it (default: unifi-support-YYYYMMDD-HHMM.tgz)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Device SSH credentials (setting/mgmt) and per-device SSH endpoints
+    DeviceSsh {
+        #[command(subcommand)]
+        command: DeviceSshCommands,
+    },
+    /// Automatic device firmware updates: on/off, start time, and release channel
+    AutoUpdate {
+        #[command(subcommand)]
+        command: AutoUpdateCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum AutoUpdateCommands {
+    /// Show the auto-update policy
+    Show,
+    /// Change the auto-update policy
+    #[command(group(clap::ArgGroup::new("change").required(true).multiple(true)))]
+    Set {
+        /// Update devices automatically (on/off)
+        #[arg(long, group = "change", value_parser = clap::builder::BoolishValueParser::new())]
+        devices: Option<bool>,
+        /// When updates start, in the site timezone (e.g., "sun 04:00", "daily 03:00")
+        #[arg(long, group = "change", value_parser = schedule::parse_start_hour)]
+        schedule: Option<(Option<&'static str>, u32)>,
+        /// Firmware release channel
+        #[arg(long, group = "change", value_enum)]
+        channel: Option<system::FirmwareChannel>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MaintenanceCommands {
+    /// Back up the settings, pause alert notifications (and optionally IPS blocking), and
+    /// restore them when the window ends or on Ctrl-C
+    Start {
+        /// Length of the window (e.g., 30m, 1h)
+        #[arg(long, value_parser = duration::parse)]
+        duration: std::time::Duration,
+        /// Also switch IPS to detection only for the window
+        #[arg(long)]
+        pause_ips: bool,
+        /// Where to save the backup (default: unifi-backup-YYYYMMDD-HHMM.unf)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        /// Return right away; close the window later with `unifi maintenance end`
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Show the open maintenance window
+    Status,
+    /// Close the open window now, restoring alerts and IPS
+    End,
+}
+
+#[derive(Subcommand)]
+enum DeviceSshCommands {
+    /// Show SSH settings (password masked) and how to reach each device
+    Show,
+    /// Update the SSH username, password, or authorized keys
+    #[command(group(clap::ArgGroup::new("change").required(true).multiple(true)))]
+    Set {
+        /// New SSH username
+        #[arg(long, group = "change")]
+        username: Option<String>,
+        /// Generate a new random password and print it once
+        #[arg(long, group = "change")]
+        password_rotate: bool,
+        /// Add (or replace) an authorized public key from a file
+        #[arg(long, group = "change")]
+        key_file: Vec<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum WifiCommands {
+    /// List WLAN configurations (default)
+    List,
+    /// Worst-performing wireless clients, with probable causes (the 10 worst unless --limit)
+    Unhappy,
+    /// APs that override a WLAN's SSID, passphrase, VLAN, or enabled state, and how
+    Overrides,
+    /// Log a client's signal, AP, and channel on an interval (e.g., while walking the building)
+    Survey {
+        /// Client MAC address
+        #[arg(long)]
+        client: String,
+        /// Time between samples (e.g., 2s)
+        #[arg(long, value_parser = duration::parse, default_value = "2s")]
+        interval: std::time::Duration,
+        /// Stop after this long (default: until Ctrl-C)
+        #[arg(long, value_parser = duration::parse)]
+        duration: Option<std::time::Duration>,
+        /// CSV file to write (default: stdout)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Write join QR codes / credential cards for a WLAN
+    ExportCredentials {
+        /// SSID of the WLAN
+        ssid: String,
+        /// Artifacts to produce (comma-separated)
+        #[arg(long, value_delimiter = ',', default_value = "txt")]
+        format: Vec<wifi_card::CardFormat>,
+        /// Output directory
+        #[arg(long, default_value = ".")]
+        out: std::path::PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum PortalCommands {
+    /// Show guest portal branding
+    Show,
+    /// Update guest portal branding (only the given fields change)
+    Set {
+        /// Portal title
+        #[arg(long)]
+        title: Option<String>,
+        /// Welcome text shown above the login form
+        #[arg(long)]
+        welcome_text: Option<String>,
+        /// Logo image to upload
+        #[arg(long)]
+        logo: Option<std::path::PathBuf>,
+        /// Background color (#RRGGBB)
+        #[arg(long, value_parser = portal::parse_color)]
+        bg_color: Option<String>,
+        /// Text color (#RRGGBB)
+        #[arg(long, value_parser = portal::parse_color)]
+        text_color: Option<String>,
+        /// Link color (#RRGGBB)
+        #[arg(long, value_parser = portal::parse_color)]
+        link_color: Option<String>,
+        /// Button color (#RRGGBB)
+        #[arg(long, value_parser = portal::parse_color)]
+        button_color: Option<String>,
+        /// Button text color (#RRGGBB)
+        #[arg(long, value_parser = portal::parse_color)]
+        button_text_color: Option<String>,
+        /// Offered languages (comma-separated codes, e.g., en,fr,de)
+        #[arg(long, value_delimiter = ',')]
+        languages: Option<Vec<String>>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Log in and report the controller model and version, the visible sites, and the role
+    /// on the selected site, with hints on failure
+    Test,
+    /// Print the effective settings after flags and environment variables are merged over the
+    /// config, with where each comes from; secrets are masked
+    Show,
+}
+
+#[derive(Subcommand)]
+enum OuiCommands {
+    /// Download the IEEE OUI registry into the local cache
+    Update {
+        /// Registry CSV URL
+        #[arg(long, default_value = oui::IEEE_OUI_URL)]
+        url: String,
+    },
+    /// Look up the vendor of one or more MAC addresses
+    Lookup {
+        /// MAC addresses
+        #[arg(required = true)]
+        macs: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevicesCommands {
+    /// Save a device's writable settings (port overrides, radios, overrides) to a file
+    ExportConfig {
+        /// Device name, MAC, IP, or alias
+        device: String,
+        /// File to write (default: stdout)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Restore settings saved by export-config, e.g. onto a replacement device
+    ApplyConfig {
+        /// Device name, MAC, IP, or alias
+        device: String,
+        /// File written by export-config (JSON or YAML)
+        #[arg(long)]
+        file: std::path::PathBuf,
+        /// Apply even if the file was exported from a different model
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the release notes of the firmware a device (or model) is waiting to upgrade to
+    Changelog {
+        /// Device name, MAC, IP, or alias, or a model code (e.g., U6LR)
+        device: String,
+        /// Firmware release API
+        #[arg(long, default_value = firmware::RELEASE_API_URL)]
+        api: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SwitchCommands {
+    /// Check that the expected device is connected on each switch port
+    PortWatch {
+        /// Switch name, MAC, IP, or alias
+        switch: String,
+        /// Expected device per port as PORT=MAC (repeatable)
+        #[arg(long = "expect", required = true, value_parser = devices::parse_port_expectation)]
+        expect: Vec<devices::PortExpectation>,
+        /// Keep polling at this interval and print a JSON line whenever a port changes
+        /// (always JSON lines, whatever --output says)
+        #[arg(long, value_parser = duration::parse)]
+        interval: Option<std::time::Duration>,
+    },
+}
+
 #[derive(Subcommand)]
 enum VpnCommands {
     /// Show Teleport VPN settings
@@ -175,6 +994,15 @@ enum VpnCommands {
     Servers,
     /// List VPN clients
     Clients,
+    /// Check that a network can reach the internet only through a VPN client
+    Verify {
+        /// Network name or ID
+        #[arg(long)]
+        network: String,
+        /// VPN client network it must leave through (name or ID)
+        #[arg(long)]
+        expect_exit_via: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -182,22 +1010,96 @@ enum ClientsCommands {
     /// All known clients
     All,
     /// Currently online clients
-    Online,
-    /// Offline clients
-    Offline,
+    Online {
+        /// Only wireless clients with at least this satisfaction score (0-100)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        min_satisfaction: Option<u8>,
+    },
+    /// Offline clients, with last seen time, IP and uplink
+    Offline {
+        /// Only clients not seen for at least this long (e.g., 30d, 12h)
+        #[arg(long, value_parser = duration::parse)]
+        not_seen_for: Option<std::time::Duration>,
+    },
+    /// Export every known client with first/last seen and traffic totals, page by page
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value = "csv")]
+        format: clients::ExportFormat,
+        /// Columns to include (name, mac, ip, network, first_seen, last_seen, total_rx,
+        /// total_tx, or any controller field)
+        #[arg(long, value_delimiter = ',', default_values = clients::EXPORT_COLUMNS)]
+        columns: Vec<String>,
+        /// Clients fetched per request
+        #[arg(long, default_value = "500")]
+        page_size: usize,
+    },
     /// Reconnect a client (kick and let it rejoin)
     Reconnect {
-        /// Client MAC address (e.g., aa:bb:cc:dd:ee:ff)
+        /// Client MAC address (e.g., aa:bb:cc:dd:ee:ff) or alias, or - to read one per line from stdin
+        mac: String,
+    },
+    /// Kick every online wireless client on an SSID or AP so they reconnect (e.g., after a
+    /// WLAN change that only applies on reconnect)
+    #[command(group(clap::ArgGroup::new("which").required(true).multiple(true)))]
+    Kick {
+        /// Clients on this SSID
+        #[arg(long, group = "which")]
+        ssid: Option<String>,
+        /// Clients on this AP (name or MAC)
+        #[arg(long, group = "which")]
+        ap: Option<String>,
+        /// Kicks in flight at once
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+    },
+    /// Block a client from connecting
+    Block {
+        /// Client MAC address or alias, or - to read one per line from stdin
+        mac: String,
+    },
+    /// Unblock a previously blocked client
+    Unblock {
+        /// Client MAC address or alias, or - to read one per line from stdin
         mac: String,
     },
 }
 
 #[derive(Subcommand)]
 enum InternetCommands {
-    /// Show all WAN settings
-    All,
+    /// Summarize the WAN: connection type, address, gateway, DNS, uptime, VLAN, IPv6
+    All {
+        /// Print the raw WAN network object instead
+        #[arg(long)]
+        json: bool,
+    },
     /// Show DNS settings
     Dns,
+    /// How traffic is spread over several WANs
+    LoadBalance {
+        #[command(subcommand)]
+        command: Option<LoadBalanceCommands>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LoadBalanceCommands {
+    /// Mode, weight, and failover priority of each WAN (default)
+    Show,
+    /// Set the mode of every WAN, with weights (percent) or the primary WAN
+    Set {
+        #[arg(long, value_enum)]
+        mode: internet::LoadBalanceMode,
+        /// Weight of WAN 1 in weighted mode (with two WANs, WAN 2 gets the rest of 100)
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=99))]
+        wan1: Option<u32>,
+        /// Weight of WAN 2 in weighted mode
+        #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=99))]
+        wan2: Option<u32>,
+        /// WAN used first in failover-only mode (and on failover in weighted mode)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        primary: usize,
+    },
 }
 
 #[derive(Subcommand)]
@@ -213,80 +1115,868 @@ enum DnsCommands {
     },
     /// Delete a static DNS record by ID
     Delete {
-        /// Record ID
+        /// Record ID, or - to read one per line from stdin
         id: String,
     },
 }
 
-fn get_client() -> Result<api::Client> {
-    let cfg = config::load_config()?;
-    let host = cfg
-        .host
-        .ok_or_else(|| anyhow::anyhow!("Not configured. Run 'unifi config' first"))?;
-    let api_key = cfg
-        .api_key
-        .ok_or_else(|| anyhow::anyhow!("API key not configured. Run 'unifi config' first"))?;
-    api::Client::new(&host, &api_key)
+/// A list command returned nothing and --fail-on-empty was given
+#[derive(Debug)]
+struct EmptyResult;
+
+impl std::fmt::Display for EmptyResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("No results")
+    }
+}
+
+impl std::error::Error for EmptyResult {}
+
+/// Arguments that parse but cannot be acted on; exits like a clap usage error
+#[derive(Debug)]
+struct UsageError(String);
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Per-invocation state shared by command handlers
+struct Context {
+    quiet: bool,
+    /// Skip confirmation prompts
+    yes: bool,
+    /// Build clients that print writes instead of sending them
+    dry_run: bool,
+    fail_on_empty: bool,
+    /// `--timings`: requests sent by every client of this command
+    timings: Option<api::Timings>,
+    output: output::Format,
+    color: bool,
+    /// Send long output through `$PAGER`
+    pager: bool,
+    /// `--config`, `UNIFI_CONFIG`, or the default location
+    config_path: std::path::PathBuf,
+    /// Fold long nested JSON arrays (stdout is a terminal and no `--no-fold`)
+    fold: bool,
+    /// `--profile` as given; see `config::Config::settings`
+    profile: Option<String>,
+    /// `--site` as given, resolved by `get_client`
+    site: Option<String>,
+    /// `--host` and `--api-key` as given, for this run only (saved by `unifi config`)
+    host: Option<String>,
+    api_key: Option<String>,
+    /// `--insecure`: accept any controller certificate
+    insecure: bool,
+    /// Per-request time limit for controller clients
+    timeout: Option<Duration>,
+    /// Global flags given on the command line that have a `defaults` entry, for `config show`
+    flags: config::Defaults,
+    fields: Vec<String>,
+    sort_by: Option<output::SortKey>,
+    filters: Vec<output::Filter>,
+    limit: Option<usize>,
+    offset: usize,
+    template: Option<output::Template>,
+    /// `alias` section of the config: name to MAC or ID
+    aliases: BTreeMap<String, String>,
+    /// Fields shown by `--output table` for this command, from the `columns` config section
+    table_columns: Vec<String>,
+    /// `write_headers` from the config plus `--ticket`
+    write_headers: Vec<(String, String)>,
+    /// Output collected for the next `--watch` frame instead of going to stdout
+    capture: Option<RefCell<String>>,
+    /// What the lines of `unifi repl` share
+    session: Option<Rc<Session>>,
+}
+
+/// State kept across the command lines of `unifi repl`
+#[derive(Default)]
+struct Session {
+    /// Logged-in clients by connection settings (see `client_key`)
+    clients: RefCell<HashMap<String, api::Client>>,
+    /// The last value printed, for `$_`
+    last: RefCell<Option<serde_json::Value>>,
+}
+
+impl Context {
+    /// Print command output to stdout in the selected format
+    fn print(&self, value: &impl serde::Serialize) -> Result<()> {
+        self.emit(self.arrange(value)?, None)
+    }
+
+    /// Like `print`, with a resource-specific column layout for `--output table`
+    fn print_list(&self, value: &impl serde::Serialize, columns: &[output::Column]) -> Result<()> {
+        self.emit(self.arrange(value)?, Some(columns))
+    }
+
+    /// Like `print_list`, but other formats also keep only the fields behind `columns`
+    fn print_columns(
+        &self,
+        value: &impl serde::Serialize,
+        columns: &[output::Column],
+    ) -> Result<()> {
+        let value = self.arrange(value)?;
+        match self.output {
+            _ if self.template.is_some() => self.emit(value, None),
+            output::Format::Table => self.emit(value, Some(columns)),
+            _ => self.emit(output::select(value, columns), None),
+        }
+    }
+
+    /// Apply `--filter`, `--sort-by`, `--offset`, and `--limit` to a list, before a projection
+    /// drops or renames fields
+    fn arrange(&self, value: &impl serde::Serialize) -> Result<serde_json::Value> {
+        let mut value = serde_json::to_value(value)?;
+        if !self.filters.is_empty()
+            && let Some(records) = value.as_array_mut()
+        {
+            records.retain(|r| self.filters.iter().all(|f| f.matches(r)));
+        }
+        if let Some(key) = &self.sort_by {
+            output::sort(&mut value, key);
+        }
+        if let Some(records) = value.as_array_mut() {
+            records.drain(..self.offset.min(records.len()));
+            if let Some(limit) = self.limit {
+                records.truncate(limit);
+            }
+        }
+        Ok(value)
+    }
+
+    fn emit(&self, mut value: serde_json::Value, columns: Option<&[output::Column]>) -> Result<()> {
+        if let Some(session) = &self.session {
+            *session.last.borrow_mut() = Some(value.clone());
+        }
+        let mut columns = columns;
+        let fields = match self.output {
+            _ if !self.fields.is_empty() => &self.fields,
+            output::Format::Table => &self.table_columns,
+            _ => &self.fields,
+        };
+        // A template names its own fields, so it sees the whole record
+        if !fields.is_empty() && self.template.is_none() {
+            value = output::project(value, fields);
+            columns = None;
+        }
+        let rendered = match (&self.template, self.output) {
+            (Some(template), _) => template.render(&value),
+            (None, output::Format::Json) => output::json(&value, self.color, self.fold),
+            (None, output::Format::Table) => output::table(&value, columns, self.color),
+            (None, output::Format::Yaml) => serde_yaml::to_string(&value)?,
+            (None, output::Format::Csv) => output::csv(&value),
+        };
+        self.write(&rendered, value.as_array().is_some_and(|a| a.is_empty()))
+    }
+
+    /// Write rendered output to stdout: into the `--watch` frame, through the pager, or
+    /// directly. Under --fail-on-empty, an `empty` result then fails the command.
+    fn write(&self, rendered: &str, empty: bool) -> Result<()> {
+        match &self.capture {
+            Some(buf) => buf.borrow_mut().push_str(rendered),
+            None if self.pager => pager::show(rendered)?,
+            None => print!("{rendered}"),
+        }
+        if self.fail_on_empty && empty {
+            return Err(EmptyResult.into());
+        }
+        Ok(())
+    }
+
+    /// Write one record of an open-ended stream as a JSON line, whatever --output says: a
+    /// table or CSV has no header to print before the first change, and the pager would wait
+    /// for the end of a stream that has none
+    fn stream(&self, record: &serde_json::Value) -> Result<()> {
+        self.stream_text(&format!("{}\n", serde_json::to_string(record)?))
+    }
+
+    /// Write output as it arrives, bypassing the pager: into the `--watch` frame, or to
+    /// stdout, flushed so a reader sees each part right away
+    fn stream_text(&self, text: &str) -> Result<()> {
+        match &self.capture {
+            Some(buf) => buf.borrow_mut().push_str(text),
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The MAC or ID behind an alias (any case), or `name` unchanged
+    fn resolve(&self, name: &str) -> String {
+        self.aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map_or(name, |(_, target)| target.as_str())
+            .to_string()
+    }
+
+    /// Print an informational message to stderr unless --quiet is set
+    fn info(&self, message: impl std::fmt::Display) {
+        (self.info_sink())(&message.to_string());
+    }
+
+    /// `info` as a function that outlives the context, for the API client's dry-run reports
+    fn info_sink(&self) -> impl Fn(&str) + Send + Sync + 'static {
+        let (quiet, dry_run) = (self.quiet, self.dry_run);
+        move |message| match (quiet, dry_run) {
+            (true, _) => {}
+            (false, true) => eprintln!("(dry run) {message}"),
+            (false, false) => eprintln!("{message}"),
+        }
+    }
+
+    /// Ask before a destructive change, after showing the object it affects. Handlers skip the
+    /// lookup and this call under --yes; without a terminal to ask on, refuse.
+    fn confirm(&self, question: &str, object: Option<&serde_json::Value>) -> Result<()> {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("{question} Pass --yes to confirm without a terminal");
+        }
+        if let Some(object) = object {
+            eprint!("{}", output::table(object, None, false));
+        }
+        eprint!("{question} [y/N] ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => anyhow::bail!("Aborted"),
+        }
+    }
+}
+
+/// The record in a list whose `key` equals `value`
+fn find_record(list: &serde_json::Value, key: &str, value: &str) -> Option<serde_json::Value> {
+    list.as_array()?
+        .iter()
+        .find(|r| r.get(key).and_then(|v| v.as_str()) == Some(value))
+        .cloned()
+}
+
+/// Run `action` for each MAC or ID read from stdin (one per line; blank lines and `#` comments
+/// are skipped), in order, then print a per-item report. Fails if any item failed. Stdin is
+/// taken, so there is nothing to answer prompts with: requires --yes.
+async fn run_batch<F, Fut>(ctx: &Context, action: F) -> Result<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    if !ctx.yes {
+        anyhow::bail!("Reading from stdin leaves no terminal for confirmation; pass --yes");
+    }
+    let items: Vec<String> = std::io::stdin()
+        .lines()
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let mut report = Vec::new();
+    let mut failed = 0;
+    for item in items {
+        let (status, error) = match action(item.clone()).await {
+            Ok(()) => ("ok", None),
+            Err(e) => {
+                failed += 1;
+                ("failed", Some(format!("{e:#}")))
+            }
+        };
+        report.push(serde_json::json!({"item": item, "status": status, "error": error}));
+    }
+    let total = report.len();
+    ctx.print(&report)?;
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} item(s) failed");
+    }
+    Ok(())
+}
+
+/// The config with the global flags and `UNIFI_*` environment variables merged over it
+fn load_settings(ctx: &Context) -> Result<config::Settings> {
+    Ok(config::load_config(&ctx.config_path)?
+        .settings(ctx.profile.as_deref(), ctx.site.as_deref())?
+        .with_overrides(ctx.host.as_deref(), ctx.api_key.as_deref()))
+}
+
+/// Client for the configured controller and site, after flags and `UNIFI_*` environment
+/// variables are merged over the config; `unifi repl` keeps it logged in for later lines
+async fn get_client(ctx: &Context) -> Result<api::Client> {
+    let Some(session) = &ctx.session else {
+        return connect(ctx).await;
+    };
+    let key = client_key(ctx);
+    if let Some(client) = session.clients.borrow().get(&key) {
+        return Ok(client.clone().with_timings(ctx.timings.clone()));
+    }
+    let client = connect(ctx).await?;
+    session.clients.borrow_mut().insert(key, client.clone());
+    Ok(client)
+}
+
+/// What a client from `get_client` depends on, so the REPL reuses one only for the same
+/// connection settings
+fn client_key(ctx: &Context) -> String {
+    format!(
+        "{:?}",
+        (
+            config::profile_name(ctx.profile.as_deref()),
+            &ctx.site,
+            &ctx.host,
+            &ctx.api_key,
+            ctx.insecure,
+            ctx.dry_run,
+            ctx.timeout,
+            &ctx.write_headers,
+        )
+    )
+}
+
+/// `get_client` without the REPL's reuse
+async fn connect(ctx: &Context) -> Result<api::Client> {
+    let settings = load_settings(ctx)?;
+    let site = settings.site.as_deref();
+    let client = client_for(ctx, settings.profile.clone(), site).await?;
+    match site {
+        None | Some("default") => Ok(client),
+        Some(site) => {
+            let site = client.resolve_site(site).await?;
+            Ok(client.with_site(&site))
+        }
+    }
+}
+
+/// Build a client for the top-level config, or for a named entry of `profiles`.
+///
+/// Uses the API key when configured (the one for `site` if there is one), else logs in with
+/// username/password; the URL layout (UniFi OS or classic controller) is detected unless set
+/// explicitly.
+async fn client_for_profile(
+    ctx: &Context,
+    cfg: &config::Config,
+    profile: Option<&str>,
+    site: Option<&str>,
+) -> Result<api::Client> {
+    client_for(ctx, cfg.profile(profile)?, site).await
+}
+
+/// `client_for_profile` with the profile already looked up
+async fn client_for(
+    ctx: &Context,
+    mut p: config::Profile,
+    site: Option<&str>,
+) -> Result<api::Client> {
+    // Only ask for the passphrase when the credential in use is encrypted (not, e.g., when
+    // `--api-key` replaces it)
+    let in_use = p.api_key_for(site).or_else(|| p.password.clone());
+    if in_use.is_some_and(|s| secret::is_encrypted(&s)) {
+        p.decrypt(&passphrase(false)?)?;
+    }
+    let api_key = p.api_key_for(site);
+    let host = p
+        .host
+        .ok_or_else(|| anyhow::anyhow!("Not configured. Run 'unifi config' first"))?;
+    let mut builder = api::Client::builder()
+        .host(&host)
+        .dry_run(ctx.dry_run)
+        .dry_run_report(ctx.info_sink());
+    let insecure = ctx.insecure || p.tls.insecure == Some(true);
+    builder = match (insecure, &p.tls.fingerprint, &p.tls.ca_file) {
+        (true, _, _) => builder.verify_tls(false),
+        (false, Some(pin), _) => builder.pin_certificate(tls::parse_fingerprint(pin)?),
+        (false, None, Some(path)) => builder.ca_bundle(
+            std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?,
+        ),
+        (false, None, None) => builder,
+    };
+    if let Some(timeout) = ctx.timeout {
+        builder = builder.timeout(timeout);
+    }
+    for (name, value) in &ctx.write_headers {
+        builder = builder.write_header(name, value);
+    }
+    if let Some(timings) = &ctx.timings {
+        builder = builder.timings(timings.clone());
+    }
+    let client = match (api_key, p.username, p.password) {
+        (Some(key), _, _) => builder.api_key(key).build()?,
+        (None, Some(user), Some(pass)) => builder
+            .credentials(user, pass)
+            .mfa_prompt(|| {
+                if !std::io::stdin().is_terminal() {
+                    anyhow::bail!("The controller asks for a two-factor code; run in a terminal");
+                }
+                prompt("Two-factor code", None)
+            })
+            .build()?,
+        _ if !p.sites.is_empty() => anyhow::bail!(
+            "No API key for this site; use --site with one of: {}",
+            p.sites.keys().cloned().collect::<Vec<_>>().join(", ")
+        ),
+        _ => anyhow::bail!(
+            "No credentials configured. Run 'unifi config' with --api-key or --username/--password"
+        ),
+    };
+    let legacy = match p.legacy {
+        Some(legacy) => legacy,
+        None => client.detect_legacy().await,
+    };
+    let client = client.with_legacy_paths(legacy);
+    client.login().await?;
+    Ok(client)
+}
+
+/// Ask on stderr and read a line from stdin; an empty answer takes `default`
+fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) => eprint!("{question} [{default}]: "),
+        None => eprint!("{question}: "),
+    }
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        anyhow::bail!("Aborted");
+    }
+    let answer = answer.trim();
+    Ok(match (answer, default) {
+        ("", Some(default)) => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+/// The passphrase for encrypted credentials: `UNIFI_PASSPHRASE`, else asked for on the
+/// terminal (twice when `new`)
+fn passphrase(new: bool) -> Result<String> {
+    if let Some(passphrase) = config::passphrase_env() {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("The credentials are encrypted; set UNIFI_PASSPHRASE or run in a terminal");
+    }
+    let passphrase = rpassword::prompt_password("Config passphrase (input hidden): ")?;
+    if passphrase.is_empty() {
+        anyhow::bail!("A passphrase is required");
+    }
+    if new && rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+        anyhow::bail!("The passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// `unifi config` without flags: ask for the host and credentials, check them against the
+/// controller, and save only if that works
+async fn config_wizard(ctx: &Context) -> Result<()> {
+    let mut cfg = config::load_config(&ctx.config_path).unwrap_or_default();
+    let name = config::profile_name(ctx.profile.as_deref());
+    let name = name.as_deref();
+    // A profile that does not exist yet is created
+    let mut p = cfg.profile(name).unwrap_or_default();
+    // An encrypted profile stays encrypted, with the same passphrase
+    let passphrase = match p.is_encrypted() {
+        true => Some(passphrase(false)?),
+        false => None,
+    };
+    if let Some(passphrase) = &passphrase {
+        p.decrypt(passphrase)?;
+    }
+    let host = prompt("Controller host (e.g., 192.168.1.1)", p.host.as_deref())?;
+    if host.is_empty() {
+        anyhow::bail!("A host is required");
+    }
+    p.host = Some(api::normalize_base_url(&host)?);
+    let api_key = rpassword::prompt_password(
+        "API key (input hidden; leave empty to use a username and password): ",
+    )?;
+    if api_key.trim().is_empty() {
+        let username = prompt("Username", p.username.as_deref())?;
+        let password = rpassword::prompt_password("Password (input hidden): ")?;
+        if username.is_empty() || password.is_empty() {
+            anyhow::bail!("A username and password are required without an API key");
+        }
+        p.api_key = None;
+        p.username = Some(username);
+        p.password = Some(password);
+    } else {
+        p.api_key = Some(api_key.trim().to_string());
+        p.username = None;
+        p.password = None;
+    }
+    // Let the test request detect the controller type again for the new host
+    p.legacy = None;
+    if ctx.insecure {
+        p.tls.insecure = Some(true);
+    }
+
+    ctx.info(format!(
+        "Testing connection to {}...",
+        p.host.as_deref().unwrap_or_default()
+    ));
+    let sites = async { client_for(ctx, p.clone(), None).await?.get_sites().await }
+        .await
+        .map_err(|e| e.context("Connection test failed, config not saved"))?;
+    let count = sites.as_array().map_or(0, Vec::len);
+    ctx.info(format!("Connected, {count} site(s) visible"));
+
+    if let Some(passphrase) = &passphrase {
+        p.encrypt(passphrase)?;
+    }
+    cfg.set_profile(name, p);
+    config::save_config(&cfg, &ctx.config_path)?;
+    ctx.info(format!("Config saved to {}", ctx.config_path.display()));
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_config(
+    ctx: &Context,
+    host: Option<String>,
+    api_key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    legacy: Option<bool>,
+    encrypt: bool,
+    decrypt: bool,
+) -> Result<()> {
+    let no_flags = host.is_none()
+        && api_key.is_none()
+        && username.is_none()
+        && password.is_none()
+        && legacy.is_none()
+        && !encrypt
+        && !decrypt
+        && !ctx.insecure
+        && (config::profile_name(ctx.profile.as_deref()).is_none() || ctx.site.is_none());
+    if no_flags {
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!(
+                "Nothing to configure: pass --host and --api-key (or --username/--password), \
+                 or run in a terminal for the setup wizard"
+            );
+        }
+        return config_wizard(ctx).await;
+    }
+    let mut cfg = config::load_config(&ctx.config_path).unwrap_or_default();
+    let name = config::profile_name(ctx.profile.as_deref());
+    let name = name.as_deref();
+    let mut p = cfg.profile(name).unwrap_or_default();
+    // New credentials for an encrypted profile are encrypted too, with the same passphrase
+    let encrypted = p.is_encrypted();
+    let passphrase = match encrypt || encrypted {
+        true => Some(passphrase(!encrypted)?),
+        false => None,
+    };
+    if let Some(passphrase) = &passphrase {
+        p.decrypt(passphrase)?;
+    }
+    if let Some(h) = host {
+        p.host = Some(api::normalize_base_url(&h)?);
+    }
+    if let Some(k) = api_key {
+        p.api_key = Some(k);
+    }
+    if username.is_some() {
+        p.username = username;
+    }
+    if password.is_some() {
+        p.password = password;
+    }
+    if legacy.is_some() {
+        p.legacy = legacy;
+    }
+    if ctx.insecure {
+        p.tls.insecure = Some(true);
+    }
+    // `--site` while configuring a profile becomes that profile's default site
+    if name.is_some() && ctx.site.is_some() {
+        p.site = ctx.site.clone();
+    }
+    if let Some(passphrase) = passphrase.filter(|_| !decrypt) {
+        p.encrypt(&passphrase)?;
+    }
+    cfg.set_profile(name, p);
+    config::save_config(&cfg, &ctx.config_path)?;
+    ctx.info(format!("Config saved to {}", ctx.config_path.display()));
+    Ok(())
+}
+
+async fn handle_maintenance(ctx: &Context, command: MaintenanceCommands) -> Result<()> {
+    match command {
+        MaintenanceCommands::Start {
+            duration,
+            pause_ips,
+            out,
+            no_wait,
+        } => {
+            if let Some(window) = maintenance::load()? {
+                anyhow::bail!(
+                    "A maintenance window is already open (until {}); run 'unifi maintenance end' first",
+                    window.view()["until"].as_str().unwrap_or_default()
+                );
+            }
+            let client = get_client(ctx).await?;
+            let until = duration::now_secs() + duration.as_secs() as i64;
+            let mut window = client.maintenance_window(until, pause_ips).await?;
+            if ctx.dry_run {
+                ctx.info("Skipping the settings backup");
+            } else {
+                ctx.info("Backing up the settings...");
+                let backup = client.download_backup().await?;
+                let out = out.unwrap_or_else(|| {
+                    let stamp = chrono::Local::now().format("%Y%m%d-%H%M");
+                    format!("unifi-backup-{stamp}.unf").into()
+                });
+                std::fs::write(&out, &backup)
+                    .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", out.display()))?;
+                ctx.info(format!("Wrote {} ({} bytes)", out.display(), backup.len()));
+                window.backup = Some(out);
+                // Recorded before anything changes, so `maintenance end` can always undo it
+                maintenance::save(&window)?;
+            }
+            client.open_maintenance(&window).await?;
+            if window.alerts_paused {
+                ctx.info("Alert notifications paused");
+            }
+            if let Some(mode) = &window.ips_mode {
+                ctx.info(format!("IPS switched from {mode} to detection only"));
+            }
+            if ctx.dry_run {
+                return Ok(());
+            }
+            let until = window.view()["until"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            if no_wait {
+                ctx.info(format!(
+                    "Maintenance window open until {until}; close it with 'unifi maintenance end'"
+                ));
+                return Ok(());
+            }
+            ctx.info(format!(
+                "Maintenance window open until {until}; press Ctrl-C to end it early"
+            ));
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            end_maintenance(ctx, &client, &window).await?;
+        }
+        MaintenanceCommands::Status => match maintenance::load()? {
+            Some(window) => ctx.print(&window.view())?,
+            None => ctx.info("No maintenance window open"),
+        },
+        MaintenanceCommands::End => {
+            let window = maintenance::load()?
+                .ok_or_else(|| anyhow::anyhow!("No maintenance window open"))?;
+            let client = get_client(ctx).await?;
+            end_maintenance(ctx, &client, &window).await?;
+        }
+    }
+    Ok(())
 }
 
-fn handle_config(host: Option<String>, api_key: Option<String>) -> Result<()> {
-    let mut cfg = config::load_config().unwrap_or_default();
-    if let Some(h) = host {
-        cfg.host = Some(h);
+/// Restore what `maintenance start` paused and forget the window
+async fn end_maintenance(
+    ctx: &Context,
+    client: &api::Client,
+    window: &maintenance::Window,
+) -> Result<()> {
+    client.close_maintenance(window).await?;
+    maintenance::clear()?;
+    if window.alerts_paused {
+        ctx.info("Alert notifications restored");
     }
-    if let Some(k) = api_key {
-        cfg.api_key = Some(k);
+    if let Some(mode) = &window.ips_mode {
+        ctx.info(format!("IPS mode restored to {mode}"));
     }
-    config::save_config(&cfg)?;
-    println!("Config saved to ~/.config/unifi/config.json");
+    ctx.info("Maintenance window closed");
     Ok(())
 }
 
-async fn handle_internet(command: InternetCommands) -> Result<()> {
+async fn handle_internet(ctx: &Context, command: InternetCommands) -> Result<()> {
     match command {
-        InternetCommands::All => {
-            let client = get_client()?;
+        InternetCommands::All { json: true } => {
+            let client = get_client(ctx).await?;
             let wan = client.get_wan_settings().await?;
-            println!("{}", serde_json::to_string_pretty(&wan)?);
+            ctx.print(&wan)?;
+        }
+        InternetCommands::All { json: false } => {
+            let client = get_client(ctx).await?;
+            let summary = client.get_wan_summary().await?;
+            ctx.print(&summary)?;
         }
         InternetCommands::Dns => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let dns = client.get_dns_settings().await?;
-            println!("{}", serde_json::to_string_pretty(&dns)?);
+            ctx.print(&dns)?;
+        }
+        InternetCommands::LoadBalance { command } => {
+            let client = get_client(ctx).await?;
+            let wans = match command.unwrap_or(LoadBalanceCommands::Show) {
+                LoadBalanceCommands::Show => client.get_load_balance().await?,
+                LoadBalanceCommands::Set {
+                    mode,
+                    wan1,
+                    wan2,
+                    primary,
+                } => client.set_load_balance(mode, [wan1, wan2], primary).await?,
+            };
+            ctx.print(&wans)?;
         }
     }
     Ok(())
 }
 
-async fn handle_dns(command: DnsCommands) -> Result<()> {
+async fn handle_dns(ctx: &Context, command: DnsCommands) -> Result<()> {
     match command {
         DnsCommands::List => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let records = client.get_dns_records().await?;
-            println!("{}", serde_json::to_string_pretty(&records)?);
+            ctx.print(&records)?;
         }
         DnsCommands::Add { name, ip } => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let record = client.create_dns_record(&name, &ip).await?;
-            println!("{}", serde_json::to_string_pretty(&record)?);
+            ctx.print(&record)?;
         }
         DnsCommands::Delete { id } => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
+            if id == "-" {
+                let client = &client;
+                return run_batch(ctx, |id| async move { client.delete_dns_record(&id).await })
+                    .await;
+            }
+            if !ctx.yes {
+                let record = find_record(&client.get_dns_records().await?, "_id", &id)
+                    .ok_or_else(|| api::NotFound(format!("No DNS record with ID {id}")))?;
+                ctx.confirm("Delete this DNS record?", Some(&record))?;
+            }
             client.delete_dns_record(&id).await?;
-            println!("Deleted DNS record {}", id);
+            ctx.info(format!("Deleted DNS record {}", id));
         }
     }
     Ok(())
 }
 
-async fn handle_security() -> Result<()> {
-    let client = get_client()?;
-    let security = client.get_security_settings().await?;
-    println!("{}", serde_json::to_string_pretty(&security)?);
+async fn handle_security(ctx: &Context, command: Option<SecurityCommands>) -> Result<()> {
+    match command.unwrap_or(SecurityCommands::Settings) {
+        SecurityCommands::Settings => {
+            let client = get_client(ctx).await?;
+            let security = client.get_security_settings().await?;
+            ctx.print(&security)?;
+        }
+        SecurityCommands::NewDevices {
+            since,
+            alert,
+            sensitive,
+        } => {
+            let client = get_client(ctx).await?;
+            let new = client.get_new_clients(since).await?;
+            ctx.print(&new)?;
+
+            if alert {
+                let flagged = new
+                    .as_array()
+                    .map(|a| {
+                        a.iter()
+                            .filter(|c| {
+                                let network = c.get("network").and_then(|n| n.as_str());
+                                sensitive.is_empty()
+                                    || network.is_some_and(|n| sensitive.iter().any(|s| s == n))
+                            })
+                            .count()
+                    })
+                    .unwrap_or(0);
+                if flagged > 0 {
+                    anyhow::bail!("{flagged} new device(s) on sensitive networks");
+                }
+            }
+        }
+        SecurityCommands::EnforceAllowlist { file, action } => {
+            let content = std::fs::read_to_string(&file)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", file.display()))?;
+            let allowed = security::parse_mac_list(&content);
+            // Every client would be a stranger, and with --yes the whole network gets blocked
+            if allowed.is_empty() {
+                return Err(UsageError(format!(
+                    "{} lists no MAC addresses; refusing to treat every client as unknown",
+                    file.display()
+                ))
+                .into());
+            }
+            let client = get_client(ctx).await?;
+            let strangers = client.get_unlisted_clients(&allowed).await?;
+            if action == AllowlistAction::Block && !strangers.is_empty() && !ctx.yes {
+                ctx.confirm(
+                    &format!("Block {} client(s) not on the allowlist?", strangers.len()),
+                    None,
+                )?;
+            }
+
+            let mut report = Vec::new();
+            let mut failed = 0;
+            for stranger in &strangers {
+                let mac = stranger
+                    .get("mac")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or_default();
+                let outcome = match action {
+                    AllowlistAction::Alert => "unknown".to_string(),
+                    AllowlistAction::Block => match client.block_client(mac).await {
+                        Ok(()) => "blocked".to_string(),
+                        Err(e) => {
+                            failed += 1;
+                            format!("block failed: {e:#}")
+                        }
+                    },
+                };
+                report.push(serde_json::json!({
+                    "mac": mac,
+                    "name": stranger.get("name").or_else(|| stranger.get("hostname")),
+                    "ip": stranger.get("ip"),
+                    "network": stranger.get("network"),
+                    "action": outcome,
+                }));
+            }
+            ctx.print(&report)?;
+
+            if action == AllowlistAction::Alert && !strangers.is_empty() {
+                anyhow::bail!("{} client(s) not on the allowlist", strangers.len());
+            }
+            if failed > 0 {
+                anyhow::bail!("Failed to block {failed} client(s)");
+            }
+        }
+        SecurityCommands::UpnpLeases { revoke } => {
+            let client = get_client(ctx).await?;
+            match revoke {
+                Some(id) => {
+                    if !ctx.yes {
+                        let lease = find_record(&client.get_upnp_leases().await?, "id", &id)
+                            .ok_or_else(|| api::NotFound(format!("No UPnP lease with ID {id}")))?;
+                        ctx.confirm("Revoke this UPnP lease?", Some(&lease))?;
+                    }
+                    let lease = client.revoke_upnp_lease(&id).await?;
+                    ctx.info(format!("Revoked UPnP lease {id}"));
+                    ctx.print(&lease)?;
+                }
+                None => ctx.print(&client.get_upnp_leases().await?)?,
+            }
+        }
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_firewall_add(
+    ctx: &Context,
     name: String,
     action: String,
     ruleset: String,
@@ -294,14 +1984,16 @@ async fn handle_firewall_add(
     src_address: Option<String>,
     dst_address: Option<String>,
     protocol: Option<String>,
+    icmp_type: Option<String>,
     src_port: Option<String>,
     dst_port: Option<String>,
     src_firewallgroup_ids: Option<Vec<String>>,
     dst_firewallgroup_ids: Option<Vec<String>>,
+    schedule: Option<schedule::Schedule>,
     enabled: bool,
     logging: bool,
 ) -> Result<()> {
-    let client = get_client()?;
+    let client = get_client(ctx).await?;
     let mut rule = serde_json::Map::new();
     rule.insert("name".into(), serde_json::json!(name));
     rule.insert("action".into(), serde_json::json!(action));
@@ -313,6 +2005,10 @@ async fn handle_firewall_add(
         "protocol".into(),
         serde_json::json!(protocol.unwrap_or_else(|| "all".to_string())),
     );
+    rule.insert(
+        "icmp_typename".into(),
+        serde_json::json!(icmp_type.unwrap_or_default()),
+    );
     rule.insert(
         "src_address".into(),
         serde_json::json!(src_address.unwrap_or_default()),
@@ -337,12 +2033,18 @@ async fn handle_firewall_add(
         "dst_firewallgroup_ids".into(),
         serde_json::json!(dst_firewallgroup_ids.unwrap_or_default()),
     );
+    if let Some(schedule) = schedule {
+        let tz = client.get_site_timezone().await?;
+        rule.insert("schedule".into(), schedule.for_site(tz.as_deref())?);
+    }
     let created = client.create_firewall_rule(&rule).await?;
-    println!("{}", serde_json::to_string_pretty(&created)?);
+    ctx.print(&created)?;
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_firewall_update(
+    ctx: &Context,
     id: String,
     name: Option<String>,
     action: Option<String>,
@@ -350,14 +2052,16 @@ async fn handle_firewall_update(
     src_address: Option<String>,
     dst_address: Option<String>,
     protocol: Option<String>,
+    icmp_type: Option<String>,
     src_port: Option<String>,
     dst_port: Option<String>,
     src_firewallgroup_ids: Option<Vec<String>>,
     dst_firewallgroup_ids: Option<Vec<String>>,
+    schedule: Option<schedule::Schedule>,
     enabled: Option<bool>,
     logging: Option<bool>,
 ) -> Result<()> {
-    let client = get_client()?;
+    let client = get_client(ctx).await?;
     let mut fields = serde_json::Map::new();
     if let Some(v) = name {
         fields.insert("name".into(), serde_json::json!(v));
@@ -377,6 +2081,9 @@ async fn handle_firewall_update(
     if let Some(v) = protocol {
         fields.insert("protocol".into(), serde_json::json!(v));
     }
+    if let Some(v) = icmp_type {
+        fields.insert("icmp_typename".into(), serde_json::json!(v));
+    }
     if let Some(v) = src_port {
         fields.insert("src_port".into(), serde_json::json!(v));
     }
@@ -389,6 +2096,10 @@ async fn handle_firewall_update(
     if let Some(v) = dst_firewallgroup_ids {
         fields.insert("dst_firewallgroup_ids".into(), serde_json::json!(v));
     }
+    if let Some(schedule) = schedule {
+        let tz = client.get_site_timezone().await?;
+        fields.insert("schedule".into(), schedule.for_site(tz.as_deref())?);
+    }
     if let Some(v) = enabled {
         fields.insert("enabled".into(), serde_json::json!(v));
     }
@@ -396,26 +2107,46 @@ async fn handle_firewall_update(
         fields.insert("logging".into(), serde_json::json!(v));
     }
     let updated = client.update_firewall_rule(&id, &fields).await?;
-    println!("{}", serde_json::to_string_pretty(&updated)?);
+    ctx.print(&updated)?;
     Ok(())
 }
 
-async fn handle_firewall(command: FirewallCommands) -> Result<()> {
+async fn handle_firewall(ctx: &Context, command: FirewallCommands) -> Result<()> {
     match command {
         FirewallCommands::Rules => {
-            let client = get_client()?;
-            let rules = client.get_firewall_rules().await?;
-            println!("{}", serde_json::to_string_pretty(&rules)?);
+            let client = get_client(ctx).await?;
+            let mut rules = client.get_firewall_rules().await?;
+            schedule::annotate(&mut rules);
+            ctx.print(&rules)?;
         }
-        FirewallCommands::Groups => {
-            let client = get_client()?;
-            let groups = client.get_firewall_groups().await?;
-            println!("{}", serde_json::to_string_pretty(&groups)?);
+        FirewallCommands::Groups { command } => {
+            let client = get_client(ctx).await?;
+            match command.unwrap_or(FirewallGroupsCommands::List) {
+                FirewallGroupsCommands::List => ctx.print(&client.get_firewall_groups().await?)?,
+                FirewallGroupsCommands::Usage { name } => {
+                    ctx.print(&client.get_firewall_group_usage(&name).await?)?
+                }
+            }
         }
         FirewallCommands::Traffic => {
-            let client = get_client()?;
-            let traffic = client.get_traffic_rules().await?;
-            println!("{}", serde_json::to_string_pretty(&traffic)?);
+            let client = get_client(ctx).await?;
+            let mut traffic = client.get_traffic_rules().await?;
+            schedule::annotate(&mut traffic);
+            ctx.print(&traffic)?;
+        }
+        FirewallCommands::IcmpTypes => ctx.print(&firewall::icmp_types())?,
+        FirewallCommands::Graph { format } => {
+            let client = get_client(ctx).await?;
+            let (rules, networks, groups) = tokio::try_join!(
+                client.get_firewall_rules(),
+                client.get_networks(),
+                client.get_firewall_groups()
+            )?;
+            let list = |v: &serde_json::Value| v.as_array().cloned().unwrap_or_default();
+            ctx.write(
+                &firewall::graph(&list(&rules), &list(&networks), &list(&groups), format),
+                false,
+            )?;
         }
         FirewallCommands::Add {
             name,
@@ -425,14 +2156,17 @@ async fn handle_firewall(command: FirewallCommands) -> Result<()> {
             src_address,
             dst_address,
             protocol,
+            icmp_type,
             src_port,
             dst_port,
             src_firewallgroup_ids,
             dst_firewallgroup_ids,
+            schedule,
             enabled,
             logging,
         } => {
             handle_firewall_add(
+                ctx,
                 name,
                 action,
                 ruleset,
@@ -440,10 +2174,12 @@ async fn handle_firewall(command: FirewallCommands) -> Result<()> {
                 src_address,
                 dst_address,
                 protocol,
+                icmp_type,
                 src_port,
                 dst_port,
                 src_firewallgroup_ids,
                 dst_firewallgroup_ids,
+                schedule,
                 enabled,
                 logging,
             )
@@ -457,14 +2193,17 @@ async fn handle_firewall(command: FirewallCommands) -> Result<()> {
             src_address,
             dst_address,
             protocol,
+            icmp_type,
             src_port,
             dst_port,
             src_firewallgroup_ids,
             dst_firewallgroup_ids,
+            schedule,
             enabled,
             logging,
         } => {
             handle_firewall_update(
+                ctx,
                 id,
                 name,
                 action,
@@ -472,113 +2211,1662 @@ async fn handle_firewall(command: FirewallCommands) -> Result<()> {
                 src_address,
                 dst_address,
                 protocol,
+                icmp_type,
                 src_port,
                 dst_port,
                 src_firewallgroup_ids,
                 dst_firewallgroup_ids,
+                schedule,
                 enabled,
                 logging,
             )
             .await?;
         }
         FirewallCommands::Delete { id } => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
+            if id == "-" {
+                let client = &client;
+                return run_batch(
+                    ctx,
+                    |id| async move { client.delete_firewall_rule(&id).await },
+                )
+                .await;
+            }
+            if !ctx.yes {
+                let rule = find_record(&client.get_firewall_rules().await?, "_id", &id)
+                    .ok_or_else(|| api::NotFound(format!("No firewall rule with ID {id}")))?;
+                ctx.confirm("Delete this firewall rule?", Some(&rule))?;
+            }
             client.delete_firewall_rule(&id).await?;
-            println!("Deleted firewall rule {}", id);
+            ctx.info(format!("Deleted firewall rule {}", id));
         }
     }
     Ok(())
 }
 
-async fn handle_vpn(command: VpnCommands) -> Result<()> {
+async fn handle_vpn(ctx: &Context, command: VpnCommands) -> Result<()> {
     match command {
         VpnCommands::Teleport => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let teleport = client.get_vpn_teleport().await?;
-            println!("{}", serde_json::to_string_pretty(&teleport)?);
+            ctx.print(&teleport)?;
         }
         VpnCommands::SiteToSite => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let s2s = client.get_vpn_site_to_site().await?;
-            println!("{}", serde_json::to_string_pretty(&s2s)?);
+            ctx.print(&s2s)?;
         }
         VpnCommands::Servers => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let servers = client.get_vpn_servers().await?;
-            println!("{}", serde_json::to_string_pretty(&servers)?);
+            ctx.print(&servers)?;
         }
         VpnCommands::Clients => {
-            let client = get_client()?;
+            let client = get_client(ctx).await?;
             let clients = client.get_vpn_clients().await?;
-            println!("{}", serde_json::to_string_pretty(&clients)?);
+            ctx.print(&clients)?;
+        }
+        VpnCommands::Verify {
+            network,
+            expect_exit_via,
+        } => {
+            let client = get_client(ctx).await?;
+            let checks = client.verify_vpn_exit(&network, &expect_exit_via).await?;
+            ctx.print_list(&checks, output::DOCTOR)?;
+            let failed = checks.iter().filter(|c| c.failed()).count();
+            if failed > 0 {
+                anyhow::bail!(
+                    "{failed} check(s) failed: {network} can leave outside {expect_exit_via}"
+                );
+            }
         }
     }
     Ok(())
 }
 
-async fn handle_networks() -> Result<()> {
-    let client = get_client()?;
-    let networks = client.get_networks().await?;
-    println!("{}", serde_json::to_string_pretty(&networks)?);
+async fn handle_networks(ctx: &Context, command: Option<NetworksCommands>) -> Result<()> {
+    let client = get_client(ctx).await?;
+    match command.unwrap_or(NetworksCommands::List) {
+        NetworksCommands::List => {
+            let networks = client.list_networks().await?;
+            ctx.print_list(&networks, output::NETWORKS)?;
+        }
+        NetworksCommands::Isolate {
+            network,
+            allow_dns,
+            allow_to,
+        } => {
+            let report = client
+                .isolate_network(&network, allow_dns, &allow_to)
+                .await?;
+            ctx.print(&serde_json::to_value(&report)?)?;
+            if !report.verified {
+                anyhow::bail!(
+                    "Isolation of '{}' did not verify: an earlier rule overrides the new ones",
+                    report.network
+                );
+            }
+        }
+        NetworksCommands::Expose {
+            device,
+            service,
+            to,
+        } => {
+            let report = client
+                .expose_service(&ctx.resolve(&device), service, &to)
+                .await?;
+            ctx.print(&serde_json::to_value(&report)?)?;
+            if !report.verified {
+                anyhow::bail!(
+                    "Exposing {} to '{}' did not verify: an earlier rule still drops it",
+                    report.device,
+                    report.to
+                );
+            }
+        }
+        NetworksCommands::Set {
+            network,
+            user_group,
+        } => {
+            let updated = client.set_network_user_group(&network, &user_group).await?;
+            ctx.print(&updated)?;
+        }
+    }
     Ok(())
 }
 
-async fn handle_wifi() -> Result<()> {
-    let client = get_client()?;
-    let wifi = client.get_wifi().await?;
-    println!("{}", serde_json::to_string_pretty(&wifi)?);
+async fn handle_wifi(ctx: &Context, command: Option<WifiCommands>) -> Result<()> {
+    match command.unwrap_or(WifiCommands::List) {
+        WifiCommands::List => {
+            let client = get_client(ctx).await?;
+            let wifi = client.get_wifi().await?;
+            ctx.print_list(&wifi, output::WIFI)?;
+        }
+        WifiCommands::Unhappy => {
+            let client = get_client(ctx).await?;
+            let limit = ctx.offset + ctx.limit.unwrap_or(10);
+            let unhappy = client.get_unhappy_clients(limit).await?;
+            ctx.print_list(&unhappy, output::UNHAPPY_CLIENTS)?;
+        }
+        WifiCommands::Overrides => {
+            let client = get_client(ctx).await?;
+            let overrides = client.get_wlan_overrides().await?;
+            ctx.print_list(&overrides, output::WLAN_OVERRIDES)?;
+        }
+        WifiCommands::Survey {
+            client: mac,
+            interval,
+            duration,
+            out,
+        } => {
+            let mac = ctx.resolve(&mac);
+            let client = get_client(ctx).await?;
+            let ap_names = client.get_ap_names().await?;
+            let mut file = match &out {
+                Some(path) => Some(
+                    std::fs::File::create(path)
+                        .map_err(|e| anyhow::anyhow!("Failed to create {}: {e}", path.display()))?,
+                ),
+                None => None,
+            };
+            // Lines go to the file as they come, or through the context without one
+            let mut write_line = |line: &str| -> Result<()> {
+                match &mut file {
+                    Some(file) => {
+                        writeln!(file, "{line}")?;
+                        file.flush()?;
+                        Ok(())
+                    }
+                    None => ctx.stream_text(&format!("{line}\n")),
+                }
+            };
+            write_line(wifi::SURVEY_CSV_HEADER)?;
+            ctx.info("Recording, press Ctrl-C to stop");
+
+            let deadline = duration.map(|d| tokio::time::Instant::now() + d);
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            let mut count = 0;
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = tokio::signal::ctrl_c() => break,
+                }
+                if deadline.is_some_and(|d| tokio::time::Instant::now() >= d) {
+                    break;
+                }
+                let sample = client.survey_sample(&mac, &ap_names).await?;
+                write_line(&sample.csv_line())?;
+                count += 1;
+                match (&sample.ap, sample.signal) {
+                    (Some(ap), Some(signal)) => ctx.info(format!(
+                        "{}  {ap}  ch {}  {signal} dBm",
+                        sample.time,
+                        sample.channel.unwrap_or_default()
+                    )),
+                    _ => ctx.info(format!("{}  not connected", sample.time)),
+                }
+            }
+            if let Some(path) = &out {
+                ctx.info(format!("Wrote {count} samples to {}", path.display()));
+            }
+        }
+        WifiCommands::ExportCredentials { ssid, format, out } => {
+            let client = get_client(ctx).await?;
+            let wlan = client.find_wlan(&ssid).await?;
+            let creds = wifi_card::Credentials::from_wlan(&wlan);
+            for path in wifi_card::export(&creds, &format, &out)? {
+                ctx.info(format!("Wrote {}", path.display()));
+            }
+        }
+    }
     Ok(())
 }
 
-async fn handle_devices() -> Result<()> {
-    let client = get_client()?;
-    let devices = client.get_devices().await?;
-    println!("{}", serde_json::to_string_pretty(&devices)?);
+async fn handle_devices(
+    ctx: &Context,
+    wide: bool,
+    short: bool,
+    command: Option<DevicesCommands>,
+) -> Result<()> {
+    let client = get_client(ctx).await?;
+    match command {
+        Some(DevicesCommands::ExportConfig { device, out }) => {
+            let device = client.find_device(&ctx.resolve(&device)).await?;
+            let exported = devices::export_config(&device);
+            match out {
+                Some(path) => {
+                    let json = serde_json::to_string_pretty(&exported)? + "\n";
+                    std::fs::write(&path, json)
+                        .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", path.display()))?;
+                    ctx.info(format!("Config written to {}", path.display()));
+                }
+                None => ctx.print(&exported)?,
+            }
+        }
+        Some(DevicesCommands::ApplyConfig {
+            device,
+            file,
+            force,
+        }) => {
+            let content = std::fs::read_to_string(&file)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", file.display()))?;
+            let exported: serde_json::Value = serde_yaml::from_str(&content)
+                .map_err(|e| anyhow::anyhow!("Failed to parse {}: {e}", file.display()))?;
+            let device = client.find_device(&ctx.resolve(&device)).await?;
+            if !ctx.yes {
+                let summary = &devices::export_config(&device)["device"];
+                ctx.confirm("Overwrite this device's settings?", Some(summary))?;
+            }
+            let updated = client
+                .apply_device_config(&device, &exported, force)
+                .await?;
+            ctx.print(&devices::export_config(&updated))?;
+        }
+        Some(DevicesCommands::Changelog { device, api }) => {
+            let query = ctx.resolve(&device);
+            let devices = client.get_devices().await?;
+            let text = |d: &serde_json::Value, key: &str| {
+                d.get(key)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let same_model: Vec<&serde_json::Value> = devices
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .filter(|d| text(d, "model").eq_ignore_ascii_case(&query))
+                .collect();
+            let (model, version) = match same_model.first() {
+                Some(first) => {
+                    let version = same_model.iter().find_map(|d| firmware::pending_version(d));
+                    if version.is_none() {
+                        ctx.info(format!(
+                            "No {} has a firmware update pending; showing the latest release",
+                            text(first, "model")
+                        ));
+                    }
+                    (text(first, "model"), version.map(String::from))
+                }
+                None => match client.find_device(&query).await {
+                    Ok(device) => {
+                        let version = firmware::pending_version(&device).map(String::from);
+                        if version.is_none() {
+                            ctx.info(format!(
+                                "{} has no firmware update pending (running {}); showing the \
+                                 latest release",
+                                text(&device, "name"),
+                                text(&device, "version")
+                            ));
+                        }
+                        (text(&device, "model"), version)
+                    }
+                    Err(e) if e.is::<api::NotFound>() => (query, None),
+                    Err(e) => return Err(e),
+                },
+            };
+            let changelog = firmware::changelog(&api, &model, version.as_deref()).await?;
+            ctx.write(&changelog.render(), false)?;
+        }
+        None => {
+            let mut devices = client.get_devices().await?;
+            oui::annotate(&mut devices);
+            match (wide, short) {
+                (true, _) => ctx.print_columns(&devices, output::DEVICES_WIDE)?,
+                (_, true) => ctx.print_columns(&devices, output::DEVICES_SHORT)?,
+                _ => ctx.print_list(&devices, output::DEVICES)?,
+            }
+        }
+    }
     Ok(())
 }
 
-async fn handle_clients(command: ClientsCommands) -> Result<()> {
+async fn handle_clients(ctx: &Context, command: ClientsCommands) -> Result<()> {
     match command {
         ClientsCommands::All => {
-            let client = get_client()?;
-            let clients = client.get_clients_all().await?;
-            println!("{}", serde_json::to_string_pretty(&clients)?);
+            let client = get_client(ctx).await?;
+            let mut clients = client.get_clients_all().await?;
+            oui::annotate(&mut clients);
+            ctx.print_list(&clients, output::CLIENTS)?;
         }
-        ClientsCommands::Online => {
-            let client = get_client()?;
-            let clients = client.get_clients_online().await?;
-            println!("{}", serde_json::to_string_pretty(&clients)?);
+        ClientsCommands::Online { min_satisfaction } => {
+            let client = get_client(ctx).await?;
+            let mut clients = client.get_clients_online().await?;
+            if let Some(min) = min_satisfaction {
+                wifi::filter_satisfaction(&mut clients, min);
+            }
+            oui::annotate(&mut clients);
+            ctx.print_list(&clients, output::CLIENTS)?;
         }
-        ClientsCommands::Offline => {
-            let client = get_client()?;
-            let clients = client.get_clients_offline().await?;
-            println!("{}", serde_json::to_string_pretty(&clients)?);
+        ClientsCommands::Offline { not_seen_for } => {
+            let client = get_client(ctx).await?;
+            let mut clients = client.get_clients_offline(not_seen_for).await?;
+            oui::annotate(&mut clients);
+            ctx.print_list(&clients, output::CLIENTS)?;
+        }
+        ClientsCommands::Export {
+            format,
+            columns,
+            page_size,
+        } => {
+            let client = get_client(ctx).await?;
+            // The pager needs the whole export at once; otherwise each page goes out as it
+            // arrives
+            let mut paged = String::new();
+            let mut write = |text: String| {
+                if ctx.pager {
+                    paged.push_str(&text);
+                    Ok(())
+                } else {
+                    ctx.stream_text(&text)
+                }
+            };
+            if let clients::ExportFormat::Csv = format {
+                write(format!("{}\n", clients::export_header(&columns)))?;
+            }
+            let count = client
+                .export_clients(page_size.max(1), |page| {
+                    let text: String = page
+                        .iter()
+                        .map(|c| format!("{}\n", clients::export_line(c, &columns, format)))
+                        .collect();
+                    write(text)
+                })
+                .await?;
+            if ctx.pager {
+                ctx.write(&paged, false)?;
+            }
+            ctx.info(format!("Exported {count} clients"));
         }
         ClientsCommands::Reconnect { mac } => {
-            let client = get_client()?;
+            let mac = ctx.resolve(&mac);
+            let client = get_client(ctx).await?;
+            if mac == "-" {
+                let client = &client;
+                return run_batch(ctx, |mac| {
+                    let mac = ctx.resolve(&mac);
+                    async move { client.kick_client(&mac).await }
+                })
+                .await;
+            }
+            if !ctx.yes {
+                let online = client.get_clients_online().await?;
+                let station = find_record(&online, "mac", &clients::normalize_mac(&mac));
+                ctx.confirm(&format!("Kick client {mac}?"), station.as_ref())?;
+            }
             client.kick_client(&mac).await?;
-            println!("Kicked client {}, it will reconnect", mac);
+            ctx.info(format!("Kicked client {}, it will reconnect", mac));
+        }
+        ClientsCommands::Kick {
+            ssid,
+            ap,
+            concurrency,
+        } => {
+            let ap = ap.map(|ap| ctx.resolve(&ap));
+            let client = get_client(ctx).await?;
+            let targets = client
+                .get_clients_on(ssid.as_deref(), ap.as_deref())
+                .await?;
+            let on = match (&ssid, &ap) {
+                (Some(ssid), Some(ap)) => format!("SSID {ssid} on {ap}"),
+                (Some(ssid), None) => format!("SSID {ssid}"),
+                (None, ap) => ap.clone().unwrap_or_default(),
+            };
+            if targets.is_empty() {
+                ctx.info(format!("No online clients on {on}"));
+                return Ok(());
+            }
+            if !ctx.yes {
+                let listed: Vec<serde_json::Value> = targets
+                    .iter()
+                    .map(|c| {
+                        serde_json::json!({
+                            "mac": c.get("mac"),
+                            "name": c.get("name").or_else(|| c.get("hostname")),
+                            "essid": c.get("essid"),
+                            "ap": c.get("ap_name"),
+                        })
+                    })
+                    .collect();
+                ctx.confirm(
+                    &format!("Kick {} client(s) on {on}?", targets.len()),
+                    Some(&serde_json::Value::Array(listed)),
+                )?;
+            }
+
+            // Kicks are independent; a few at a time keeps the controller responsive
+            let client = std::sync::Arc::new(client);
+            let mut pending = targets.into_iter();
+            let mut running = tokio::task::JoinSet::new();
+            let mut report = Vec::new();
+            loop {
+                while running.len() < concurrency as usize {
+                    let Some(target) = pending.next() else { break };
+                    let client = client.clone();
+                    running.spawn(async move {
+                        let mac = target
+                            .get("mac")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or_default();
+                        let result = client.kick_client(mac).await;
+                        (target, result)
+                    });
+                }
+                let Some(done) = running.join_next().await else {
+                    break;
+                };
+                let (target, result) = done?;
+                let error = result.err().map(|e| format!("{e:#}"));
+                report.push(serde_json::json!({
+                    "mac": target.get("mac"),
+                    "name": target.get("name").or_else(|| target.get("hostname")),
+                    "ap": target.get("ap_name"),
+                    "status": if error.is_none() { "ok" } else { "failed" },
+                    "error": error,
+                }));
+            }
+            let failed = report.iter().filter(|r| r["status"] == "failed").count();
+            let total = report.len();
+            ctx.print(&report)?;
+            if failed > 0 {
+                anyhow::bail!("{failed} of {total} kick(s) failed");
+            }
+            ctx.info(format!("Kicked {total} client(s), they will reconnect"));
+        }
+        ClientsCommands::Block { mac } => {
+            let mac = ctx.resolve(&mac);
+            let client = get_client(ctx).await?;
+            if mac == "-" {
+                let client = &client;
+                return run_batch(ctx, |mac| {
+                    let mac = ctx.resolve(&mac);
+                    async move { client.block_client(&mac).await }
+                })
+                .await;
+            }
+            if !ctx.yes {
+                let known = client.get_clients_all().await?;
+                let station = find_record(&known, "mac", &clients::normalize_mac(&mac));
+                ctx.confirm(&format!("Block client {mac}?"), station.as_ref())?;
+            }
+            client.block_client(&mac).await?;
+            ctx.info(format!("Blocked client {}", mac));
+        }
+        ClientsCommands::Unblock { mac } => {
+            let mac = ctx.resolve(&mac);
+            let client = get_client(ctx).await?;
+            if mac == "-" {
+                let client = &client;
+                return run_batch(ctx, |mac| {
+                    let mac = ctx.resolve(&mac);
+                    async move { client.unblock_client(&mac).await }
+                })
+                .await;
+            }
+            client.unblock_client(&mac).await?;
+            ctx.info(format!("Unblocked client {}", mac));
+        }
+    }
+    Ok(())
+}
+
+async fn handle_system(ctx: &Context, command: SystemCommands) -> Result<()> {
+    match command {
+        SystemCommands::Prune {
+            events_older_than,
+            clients_not_seen,
+            include_named,
+        } => {
+            let client = get_client(ctx).await?;
+            let mut summary = serde_json::Map::new();
+
+            if let Some(age) = events_older_than {
+                let hours = age.as_secs().div_ceil(3600);
+                if !ctx.yes {
+                    ctx.confirm(
+                        &format!("Keep events for {hours} hours and delete older ones?"),
+                        None,
+                    )?;
+                }
+                client.set_event_retention(hours).await?;
+                summary.insert(
+                    "events".into(),
+                    serde_json::json!({"retention_hours": hours, "applied": !ctx.dry_run}),
+                );
+            }
+
+            if let Some(age) = clients_not_seen {
+                let stale = client.get_clients_offline(Some(age)).await?;
+                let macs: Vec<String> = stale
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|c| {
+                        include_named
+                            || !["name", "use_fixedip", "blocked"].iter().any(|k| {
+                                c.get(*k).is_some_and(|v| {
+                                    v.as_bool() == Some(true)
+                                        || v.as_str().is_some_and(|s| !s.is_empty())
+                                })
+                            })
+                    })
+                    .filter_map(|c| c.get("mac").and_then(|m| m.as_str()).map(String::from))
+                    .collect();
+                if !macs.is_empty() {
+                    if !ctx.yes {
+                        ctx.confirm(&format!("Forget {} client(s)?", macs.len()), None)?;
+                    }
+                    client.forget_clients(&macs).await?;
+                }
+                summary.insert(
+                    "clients".into(),
+                    serde_json::json!({"forgotten": macs.len(), "applied": !ctx.dry_run, "macs": macs}),
+                );
+            }
+            ctx.print(&summary)?;
+        }
+        SystemCommands::SupportBundle { out } => {
+            let client = get_client(ctx).await?;
+            ctx.info("Generating support file (this can take a minute)...");
+            let bundle = client.download_support_bundle().await?;
+            let out = out.unwrap_or_else(|| {
+                let stamp = chrono::Local::now().format("%Y%m%d-%H%M");
+                format!("unifi-support-{stamp}.tgz").into()
+            });
+            std::fs::write(&out, &bundle)
+                .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", out.display()))?;
+            ctx.info(format!("Wrote {} ({} bytes)", out.display(), bundle.len()));
+        }
+        SystemCommands::DeviceSsh { command } => match command {
+            DeviceSshCommands::Show => {
+                ctx.print(&get_client(ctx).await?.get_device_ssh().await?)?
+            }
+            DeviceSshCommands::Set {
+                username,
+                password_rotate,
+                key_file,
+            } => {
+                let mut update = system::DeviceSshUpdate {
+                    username,
+                    ..Default::default()
+                };
+                for path in &key_file {
+                    let content = std::fs::read_to_string(path)
+                        .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+                    let stem = path
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let key = system::parse_ssh_key(content.trim(), &stem)
+                        .map_err(|e| anyhow::anyhow!("Invalid key in {}: {e}", path.display()))?;
+                    update.add_keys.push(key);
+                }
+                if password_rotate {
+                    update.password = Some(secret::random_password()?);
+                }
+                let password = update.password.clone();
+
+                let mut result = get_client(ctx).await?.update_device_ssh(update).await?;
+                if let Some(password) = password {
+                    // Shown only here; the controller never returns it in clear text again
+                    result["password"] = serde_json::Value::String(password);
+                    ctx.info("Devices pick up the new credentials on their next provision");
+                }
+                ctx.print(&result)?;
+            }
+        },
+        SystemCommands::AutoUpdate { command } => {
+            let client = get_client(ctx).await?;
+            let policy = match command {
+                AutoUpdateCommands::Show => client.get_auto_update().await?,
+                AutoUpdateCommands::Set {
+                    devices,
+                    schedule,
+                    channel,
+                } => {
+                    let policy = system::AutoUpdatePolicy {
+                        devices,
+                        start: schedule,
+                        channel,
+                    };
+                    client.update_auto_update(policy).await?
+                }
+            };
+            ctx.print(&policy)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_portal(ctx: &Context, command: PortalCommands) -> Result<()> {
+    match command {
+        PortalCommands::Show => {
+            let client = get_client(ctx).await?;
+            let portal = client.get_portal_settings().await?;
+            ctx.print(&portal)?;
+        }
+        PortalCommands::Set {
+            title,
+            welcome_text,
+            logo,
+            bg_color,
+            text_color,
+            link_color,
+            button_color,
+            button_text_color,
+            languages,
+        } => {
+            let client = get_client(ctx).await?;
+            let mut fields = serde_json::Map::new();
+            let strings = [
+                ("title", title),
+                ("welcome_text", welcome_text),
+                ("bg_color", bg_color),
+                ("text_color", text_color),
+                ("link_color", link_color),
+                ("button_color", button_color),
+                ("button_text_color", button_text_color),
+            ];
+            for (key, value) in strings {
+                if let Some(v) = value {
+                    fields.insert(key.into(), serde_json::json!(v));
+                }
+            }
+            if let Some(v) = languages {
+                fields.insert("languages".into(), serde_json::json!(v));
+            }
+            if let Some(path) = logo {
+                let file = client.upload_portal_logo(&path).await?;
+                fields.insert("logo_file".into(), serde_json::json!(file));
+                fields.insert("logo_enabled".into(), serde_json::json!(true));
+            }
+            if fields.is_empty() {
+                anyhow::bail!("Nothing to update; pass at least one option");
+            }
+            let portal = client.update_portal_settings(&fields).await?;
+            ctx.print(&portal)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_oui(ctx: &Context, command: OuiCommands) -> Result<()> {
+    match command {
+        OuiCommands::Update { url } => {
+            let count = oui::update(&url).await?;
+            ctx.info(format!("Saved {count} OUI entries"));
+        }
+        OuiCommands::Lookup { macs } => {
+            let results: Vec<_> = macs
+                .iter()
+                .map(|mac| serde_json::json!({"mac": mac, "vendor": oui::vendor(mac, None)}))
+                .collect();
+            ctx.print(&results)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_switch(ctx: &Context, command: SwitchCommands) -> Result<()> {
+    match command {
+        SwitchCommands::PortWatch {
+            switch,
+            expect,
+            interval,
+        } => {
+            let client = get_client(ctx).await?;
+            let device = client.find_device(&ctx.resolve(&switch)).await?;
+            let switch_mac = device
+                .get("mac")
+                .and_then(|m| m.as_str())
+                .unwrap_or_default()
+                .to_string();
+
+            let Some(interval) = interval else {
+                let ports = client.get_switch_port_clients(&switch_mac).await?;
+                let checks = devices::check_ports(&ports, &expect);
+                ctx.print(&checks)?;
+                let bad = checks
+                    .iter()
+                    .filter(|c| c.status != devices::PortStatus::Ok)
+                    .count();
+                if bad > 0 {
+                    anyhow::bail!("{bad} port(s) do not have the expected device");
+                }
+                return Ok(());
+            };
+
+            ctx.info(format!(
+                "Watching {} port(s) on {switch} every {}s",
+                expect.len(),
+                interval.as_secs()
+            ));
+            let mut previous: std::collections::HashMap<u64, devices::PortStatus> =
+                Default::default();
+            loop {
+                let ports = match client.get_switch_port_clients(&switch_mac).await {
+                    Ok(ports) => ports,
+                    Err(err) => {
+                        tracing::warn!(switch = %switch, "{err:#}");
+                        tokio::time::sleep(interval).await;
+                        continue;
+                    }
+                };
+                for check in devices::check_ports(&ports, &expect) {
+                    if previous.get(&check.port) != Some(&check.status) {
+                        let mut line = serde_json::to_value(&check)?;
+                        line["time"] = chrono::Local::now().to_rfc3339().into();
+                        ctx.stream(&line)?;
+                        previous.insert(check.port, check.status);
+                    }
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }
+    }
+}
+
+async fn handle_stats(ctx: &Context, command: StatsCommands) -> Result<()> {
+    match command {
+        StatsCommands::Latency { client, range, csv } => {
+            let client = client.map(|c| ctx.resolve(&c));
+            let samples = get_client(ctx)
+                .await?
+                .get_client_samples(range, client.as_deref())
+                .await?;
+            let rendered = match csv {
+                true => stats::csv(&samples),
+                false => stats::heatmap(&samples, range, 72),
+            };
+            ctx.write(&rendered, samples.is_empty())?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_diag(ctx: &Context, command: DiagCommands) -> Result<()> {
+    match command {
+        DiagCommands::Throughput { device, wait } => {
+            let device = ctx.resolve(&device);
+            let client = get_client(ctx).await?;
+            ctx.info(format!("Running throughput test on {device}..."));
+            let result = client.run_throughput_test(&device, wait).await?;
+            ctx.print(&result)?;
+        }
+        DiagCommands::Timeline { around, window } => {
+            let client = get_client(ctx).await?;
+            let timeline = client.get_timeline(around, window).await?;
+            ctx.print_list(&timeline, output::TIMELINE)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_automate(
+    ctx: &Context,
+    rules: Vec<automate::Rule>,
+    interval: Duration,
+    cooldown: Duration,
+) -> Result<()> {
+    // Rules watching the same list share one request per round
+    let client = get_client(ctx)
+        .await?
+        .with_cache(interval.min(Duration::from_secs(10)));
+    ctx.info(format!(
+        "Evaluating {} rule(s) every {}s",
+        rules.len(),
+        interval.as_secs()
+    ));
+    let mut cooldown = automate::Cooldown::new(cooldown);
+    loop {
+        for (i, rule) in rules.iter().enumerate() {
+            let matches = match client.evaluate(&rule.condition).await {
+                Ok(matches) => matches,
+                Err(err) => {
+                    tracing::warn!(rule = %rule.text, "{err:#}");
+                    continue;
+                }
+            };
+            for m in matches {
+                if !cooldown.ready(i, &m.target) {
+                    continue;
+                }
+                // Webhooks do not go through the client, so skip every action here
+                let result = if ctx.dry_run {
+                    "dry-run".to_string()
+                } else {
+                    match client.remediate(rule, &m).await {
+                        Ok(()) => "ok".to_string(),
+                        Err(err) => format!("error: {err:#}"),
+                    }
+                };
+                let line = serde_json::json!({
+                    "time": chrono::Local::now().to_rfc3339(),
+                    "rule": rule.text,
+                    "target": m.target,
+                    "name": m.name,
+                    "value": m.value,
+                    "result": result,
+                });
+                ctx.stream(&line)?;
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn handle_api(ctx: &Context, command: ApiCommands) -> Result<()> {
+    let (method, path, body) = match command {
+        ApiCommands::Get { path } => (reqwest::Method::GET, path, None),
+        ApiCommands::Post { path, body } => (reqwest::Method::POST, path, body),
+        ApiCommands::Put { path, body } => (reqwest::Method::PUT, path, body),
+        ApiCommands::Delete { path } => (reqwest::Method::DELETE, path, None),
+    };
+    let body = match body {
+        Some(file) => {
+            let text = if file.as_os_str() == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", file.display()))?
+            };
+            let value: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid JSON body: {e}"))?;
+            Some(value)
+        }
+        None => None,
+    };
+    let client = get_client(ctx).await?;
+    if method == reqwest::Method::DELETE && !ctx.yes {
+        ctx.confirm(&format!("DELETE {path}?"), None)?;
+    }
+    let response = client.raw(method, &path, body.as_ref()).await?;
+    ctx.print(&response)?;
+    Ok(())
+}
+
+async fn handle_config_test(ctx: &Context) -> Result<()> {
+    let settings = load_settings(ctx)?;
+    let mut checks = vec![doctor::config_check(&settings.profile)];
+    if !checks[0].failed() {
+        // Not switched to the site: the checks look it up, so a wrong --site is reported there
+        let site = settings.site.as_deref();
+        match client_for(ctx, settings.profile.clone(), site).await {
+            Ok(client) => checks.extend(doctor::connection_checks(&client, site).await),
+            Err(err) => checks.push(doctor::auth_failure(&err)),
+        }
+    }
+    ctx.print_list(&checks, output::DOCTOR)?;
+    let failed = checks.iter().filter(|c| c.failed()).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+async fn handle_discover(ctx: &Context, address: std::net::IpAddr, wait: Duration) -> Result<()> {
+    ctx.info(format!(
+        "Waiting {} for answers from {address}...",
+        duration::humanize(wait.as_secs() as i64)
+    ));
+    let found = discover::discover(address, wait).await?;
+    if found.is_empty() {
+        ctx.info(format!(
+            "Nothing answered; consoles only answer on their own LAN (UDP {})",
+            discover::PORT
+        ));
+    }
+    ctx.print_list(&found, output::DISCOVERED)?;
+    let consoles: Vec<_> = found.iter().filter(|d| d.console).collect();
+    let console = match consoles.as_slice() {
+        [] => return Ok(()),
+        [console] if ctx.yes => *console,
+        _ if !std::io::stdin().is_terminal() => {
+            ctx.info("Save a console with 'unifi config -H IP'");
+            return Ok(());
+        }
+        [console] => {
+            let question = format!(
+                "Save {} ({}) as the controller host? [y/N]",
+                console.ip, console.model
+            );
+            match prompt(&question, None)?.to_lowercase().as_str() {
+                "y" | "yes" => *console,
+                _ => return Ok(()),
+            }
+        }
+        _ => {
+            for (i, console) in consoles.iter().enumerate() {
+                eprintln!(
+                    "{}) {} ({}, {})",
+                    i + 1,
+                    console.ip,
+                    console.name,
+                    console.model
+                );
+            }
+            let answer = prompt(
+                "Console to save as the controller host (empty for none)",
+                None,
+            )?;
+            if answer.is_empty() {
+                return Ok(());
+            }
+            answer
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| consoles.get(n.wrapping_sub(1)))
+                .ok_or_else(|| anyhow::anyhow!("No console numbered '{answer}'"))?
+        }
+    };
+    let host = api::normalize_base_url(&console.ip)?;
+    if ctx.dry_run {
+        ctx.info(format!("Would save {host} as the controller host"));
+        return Ok(());
+    }
+    let mut cfg = config::load_config(&ctx.config_path).unwrap_or_default();
+    let name = config::profile_name(ctx.profile.as_deref());
+    let mut p = cfg.profile(name.as_deref()).unwrap_or_default();
+    p.host = Some(host.clone());
+    // The new console is detected again on the first request
+    p.legacy = None;
+    cfg.set_profile(name.as_deref(), p);
+    config::save_config(&cfg, &ctx.config_path)?;
+    ctx.info(format!(
+        "Saved {host} to {}; add credentials with 'unifi config'",
+        ctx.config_path.display()
+    ));
+    Ok(())
+}
+
+async fn handle_doctor(ctx: &Context) -> Result<()> {
+    let settings = load_settings(ctx)?;
+    let profile = settings.profile;
+    let mut checks = vec![doctor::config_check(&profile)];
+    if let Some(host) = &profile.host {
+        checks.extend(doctor::tls_checks(host, profile.tls.fingerprint.as_deref()).await);
+        checks.push(match get_client(ctx).await {
+            Ok(client) => doctor::api_check(&client).await,
+            Err(err) => doctor::Check::error("api", format!("{err:#}")),
+        });
+    }
+    ctx.print_list(&checks, output::DOCTOR)?;
+    let failed = checks.iter().filter(|c| c.failed()).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+async fn handle_audit(ctx: &Context, command: AuditCommands) -> Result<()> {
+    match command {
+        AuditCommands::Remote {
+            since,
+            allow,
+            unexpected_only,
+        } => {
+            let allow = if allow.is_empty() {
+                audit::PRIVATE_NETWORKS
+                    .iter()
+                    .map(|n| audit::parse_network(n))
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                allow
+            };
+            let client = get_client(ctx).await?;
+            let mut accesses = client.get_remote_access(since, &allow).await?;
+            let unexpected = accesses.iter().filter(|a| a.status != "ok").count();
+            if unexpected_only {
+                accesses.retain(|a| a.status != "ok");
+            }
+            ctx.print(&accesses)?;
+            if unexpected > 0 {
+                anyhow::bail!("{unexpected} access(es) from unexpected addresses");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_alias(ctx: &Context, command: Option<AliasCommands>) -> Result<()> {
+    match command.unwrap_or(AliasCommands::List) {
+        AliasCommands::List => {
+            let cfg = config::load_config(&ctx.config_path)?;
+            ctx.print(&cfg.aliases)?;
+        }
+        AliasCommands::Add { name, mac } => {
+            let mut cfg = config::load_config(&ctx.config_path)?;
+            let target = if mac.contains([':', '-']) {
+                clients::normalize_mac(&mac)
+            } else {
+                mac
+            };
+            cfg.aliases
+                .retain(|alias, _| !alias.eq_ignore_ascii_case(&name));
+            ctx.info(format!("{name} -> {target}"));
+            cfg.aliases.insert(name, target);
+            config::save_config(&cfg, &ctx.config_path)?;
+        }
+        AliasCommands::Remove { name } => {
+            let mut cfg = config::load_config(&ctx.config_path)?;
+            let before = cfg.aliases.len();
+            cfg.aliases
+                .retain(|alias, _| !alias.eq_ignore_ascii_case(&name));
+            if cfg.aliases.len() == before {
+                return Err(api::NotFound(format!("No alias '{name}'")).into());
+            }
+            config::save_config(&cfg, &ctx.config_path)?;
+            ctx.info(format!("Removed alias {name}"));
+        }
+    }
+    Ok(())
+}
+
+async fn handle_hooks(ctx: &Context, command: HooksCommands) -> Result<()> {
+    match command {
+        HooksCommands::List => {
+            let cfg = config::load_config(&ctx.config_path)?;
+            ctx.print(&cfg.hooks)?;
+        }
+        HooksCommands::Add { event, run } => {
+            let mut cfg = config::load_config(&ctx.config_path)?;
+            cfg.hooks.push(config::Hook { event, run });
+            config::save_config(&cfg, &ctx.config_path)?;
+            ctx.info(format!("Added hook #{}", cfg.hooks.len() - 1));
+        }
+        HooksCommands::Remove { index } => {
+            let mut cfg = config::load_config(&ctx.config_path)?;
+            if index >= cfg.hooks.len() {
+                return Err(api::NotFound(format!("No hook #{index}")).into());
+            }
+            let hook = cfg.hooks.remove(index);
+            config::save_config(&cfg, &ctx.config_path)?;
+            ctx.info(format!("Removed hook for {}", hook.event));
+        }
+        HooksCommands::Run { interval } => {
+            let hooks = config::load_config(&ctx.config_path)?.hooks;
+            if hooks.is_empty() {
+                anyhow::bail!("No hooks configured. Add one with 'unifi hooks add'");
+            }
+            let client = get_client(ctx).await?;
+            ctx.info(format!(
+                "Watching events for {} hook(s) every {}s",
+                hooks.len(),
+                interval.as_secs()
+            ));
+            let mut cursor = hooks::EventCursor::default();
+            loop {
+                match client.get_recent_events(200).await {
+                    Ok(batch) => {
+                        for event in cursor.advance(batch) {
+                            let key = hooks::event_key(&event).unwrap_or_default();
+                            for hook in hooks.iter().filter(|h| h.matches(key)) {
+                                ctx.info(format!("{key}: running '{}'", hook.run));
+                                if let Err(err) = hook.run(&event).await {
+                                    tracing::warn!(event = key, hook = %hook.run, "{err:#}");
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => tracing::warn!("{err:#}"),
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_copy(
+    ctx: &Context,
+    from: copy::Location,
+    to: copy::Location,
+    resources: Vec<copy::Resource>,
+    rename_prefix: Option<String>,
+) -> Result<()> {
+    let cfg = config::load_config(&ctx.config_path)?;
+    let src = client_for_profile(ctx, &cfg, from.profile.as_deref(), Some(&from.site)).await?;
+    let src_site = src.resolve_site(&from.site).await?;
+    let src = src.with_site(&src_site);
+    let dst = client_for_profile(ctx, &cfg, to.profile.as_deref(), Some(&to.site)).await?;
+    let dst_site = dst.resolve_site(&to.site).await?;
+    let dst = dst.with_site(&dst_site);
+    let results = copy::copy(&src, &dst, &resources, rename_prefix.as_deref()).await?;
+
+    let failed = results
+        .iter()
+        .filter(|r| matches!(r.status, copy::Status::Failed))
+        .count();
+    ctx.print(&results)?;
+    if failed > 0 {
+        anyhow::bail!("{failed} object(s) failed to copy");
+    }
+    Ok(())
+}
+
+async fn handle_report(
+    ctx: &Context,
+    period: report::Period,
+    sections: Vec<report::SectionKind>,
+    format: report::Format,
+    out: Option<std::path::PathBuf>,
+    mail: Option<String>,
+) -> Result<()> {
+    let client = get_client(ctx).await?;
+    let report = report::build(&client, period, &sections).await;
+    let rendered = report::render(&report, format)?;
+
+    match &out {
+        Some(path) => {
+            std::fs::write(path, &rendered)
+                .map_err(|e| anyhow::anyhow!("Failed to write {}: {e}", path.display()))?;
+            ctx.info(format!("Report written to {}", path.display()));
+        }
+        None if mail.is_none() => {
+            let empty = report
+                .sections
+                .iter()
+                .all(|s| s.summary.is_empty() && s.rows.is_empty());
+            ctx.write(&rendered, empty)?;
+        }
+        None => {}
+    }
+    if let Some(to) = mail {
+        let subject = format!("{} ({})", report.title, report.generated_at);
+        report::mail(&to, &subject, &rendered, format)?;
+        ctx.info(format!("Report mailed to {to}"));
+    }
+    Ok(())
+}
+
+async fn handle_serve(
+    ctx: &Context,
+    listen: std::net::SocketAddr,
+    token: Option<String>,
+    cache_ttl: std::time::Duration,
+    people: Vec<ha::Person>,
+) -> Result<()> {
+    let client = get_client(ctx).await?.with_cache(cache_ttl);
+    let token = match token.or_else(|| std::env::var("UNIFI_SERVE_TOKEN").ok()) {
+        Some(token) => token,
+        None => {
+            let token = secret::random_token()?;
+            eprintln!("Generated API token: {token}");
+            token
+        }
+    };
+    ctx.info(format!("Listening on http://{listen}"));
+    serve::serve(client, listen, token, people).await
+}
+
+/// Map an error to the documented exit code
+fn exit_code(err: &anyhow::Error) -> u8 {
+    if let Some(e) = err.downcast_ref::<api::ApiError>() {
+        return match e.status.as_u16() {
+            401 | 403 => EXIT_AUTH,
+            404 => EXIT_NOT_FOUND,
+            _ => EXIT_API,
+        };
+    }
+    if err.is::<api::NotFound>() || err.is::<EmptyResult>() {
+        return EXIT_NOT_FOUND;
+    }
+    if err.is::<UsageError>() {
+        return EXIT_USAGE;
+    }
+    if err.chain().any(|e| e.is::<reqwest::Error>()) {
+        return EXIT_API;
+    }
+    EXIT_ERROR
+}
+
+/// `--color`/`--no-color`, else `defaults.color`
+fn color_choice(cli: &Cli, defaults: &config::Defaults) -> style::ColorChoice {
+    if cli.no_color {
+        style::ColorChoice::Never
+    } else {
+        cli.color.or(defaults.color).unwrap_or_default()
+    }
+}
+
+/// The `Context` of one command line, and its command; the config
+/// supplies defaults, aliases, the command's table columns, and write headers
+fn context(cli: Cli, matches: &clap::ArgMatches, cfg: &config::Config) -> (Context, Commands) {
+    let defaults = &cfg.defaults;
+    let table_columns = cfg
+        .columns
+        .get(&command_key(matches))
+        .cloned()
+        .unwrap_or_default();
+    let mut write_headers: Vec<_> = cfg.write_headers.clone().into_iter().collect();
+    if let Some(ticket) = &cli.ticket {
+        write_headers.push(("X-Change-Ticket".into(), ticket.clone()));
+    }
+    let color = color_choice(&cli, defaults);
+    let flags = config::Defaults {
+        output: cli.output,
+        color: match cli.no_color {
+            true => Some(style::ColorChoice::Never),
+            false => cli.color,
+        },
+        pager: cli.no_pager.then_some(false),
+        timezone: None,
+        site: cli.site.clone(),
+        timeout: cli.timeout,
+    };
+    let ctx = Context {
+        quiet: cli.quiet,
+        // Nothing to confirm when nothing is sent
+        yes: cli.yes || cli.dry_run,
+        dry_run: cli.dry_run,
+        fail_on_empty: cli.fail_on_empty,
+        timings: cli.timings.then(api::Timings::default),
+        output: cli
+            .output
+            .or_else(|| cfg.output(cli.profile.as_deref()))
+            .unwrap_or_default(),
+        color: color.stdout(),
+        pager: !cli.no_pager && defaults.pager.unwrap_or(true) && std::io::stdout().is_terminal(),
+        fold: !cli.no_fold && std::io::stdout().is_terminal(),
+        config_path: config::config_path(cli.config.as_deref()),
+        profile: cli.profile,
+        site: cli.site,
+        host: cli.host,
+        api_key: cli.api_key,
+        insecure: cli.insecure,
+        timeout: match cli
+            .timeout
+            .or(defaults.timeout)
+            .unwrap_or(config::DEFAULT_TIMEOUT_SECS)
+        {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        flags,
+        fields: cli.fields,
+        sort_by: cli.sort_by,
+        filters: cli.filters,
+        limit: cli.limit,
+        offset: cli.offset,
+        template: cli.template,
+        aliases: cfg.aliases.clone(),
+        table_columns,
+        write_headers,
+        capture: cli.watch.map(|_| RefCell::default()),
+        session: None,
+    };
+    (ctx, cli.command)
+}
+
+/// Run the parsed command line; `migrated` is the outcome of moving an old `config.json`
+async fn run(
+    cli: Cli,
+    matches: &clap::ArgMatches,
+    cfg: &config::Config,
+    migrated: Result<Option<String>>,
+) -> Result<()> {
+    let watch_secs = cli.watch;
+    let (ctx, command) = context(cli, matches, cfg);
+    if let Some(notice) = migrated? {
+        ctx.info(notice);
+    }
+    if let Commands::Repl = command {
+        anyhow::ensure!(watch_secs.is_none(), "--watch does not apply to the REPL");
+        return run_repl(cfg).await;
+    }
+    let started = std::time::Instant::now();
+    let result = match watch_secs {
+        Some(secs) => watch(&ctx, Duration::from_secs(secs.max(1))).await,
+        None => dispatch(&ctx, command).await,
+    };
+    if let Some(timings) = &ctx.timings {
+        eprint!("{}", timings.report(started.elapsed()));
+    }
+    result
+}
+
+/// `unifi repl`: run command lines from stdin until EOF or `exit`. Global flags given with
+/// `repl` apply to every line; `site NAME` and `profile NAME` select a site and profile for
+/// the lines after (without a name, back to the configured one).
+async fn run_repl(cfg: &config::Config) -> Result<()> {
+    // The flags given with `repl` itself, minus the subcommand (but not a flag value that
+    // happens to read `repl`, as in `--profile repl`)
+    let mut base: Vec<String> = std::env::args().collect();
+    if let Some(at) = subcommand_position(&base) {
+        base.remove(at);
+    }
+    let session = Rc::new(Session::default());
+    let (mut site, mut profile): (Option<String>, Option<String>) = (None, None);
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        eprintln!(
+            "Commands as for unifi (e.g., `clients online`); `$_` is the last result \
+             (`$_.0.mac`), `site NAME` and `profile NAME` switch, `exit` quits"
+        );
+    }
+    let mut line = String::new();
+    loop {
+        if interactive {
+            eprint!("unifi> ");
+            std::io::stderr().flush()?;
+        }
+        line.clear();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+        let last = session.last.borrow().clone();
+        let words = repl::split_words(&line).and_then(|words| {
+            words
+                .iter()
+                .map(|w| repl::expand_last(w, last.as_ref()))
+                .collect::<Result<Vec<_>>>()
+        });
+        let words = match words {
+            Ok(words) => words,
+            Err(err) => {
+                eprintln!("Error: {err}");
+                continue;
+            }
+        };
+        match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] => continue,
+            ["exit" | "quit"] => break,
+            ["site"] => site = None,
+            ["site", name] => site = Some(name.to_string()),
+            ["profile"] => profile = None,
+            ["profile", name] => profile = Some(name.to_string()),
+            _ => {
+                let mut args = base.clone();
+                if let Some(site) = &site {
+                    args.extend(["--site".to_string(), site.clone()]);
+                }
+                if let Some(profile) = &profile {
+                    args.extend(["--profile".to_string(), profile.clone()]);
+                }
+                args.extend(words);
+                if let Err(err) = run_repl_line(cfg, &session, args).await
+                    && !err.is::<EmptyResult>()
+                {
+                    eprintln!("Error: {err:#}");
+                }
+            }
         }
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Where the subcommand sits in `args` (program name first): the first word that is neither
+/// a flag nor the value of one
+fn subcommand_position(args: &[String]) -> Option<usize> {
+    let command = Cli::command();
+    let takes_value = |arg: Option<&clap::Arg>| arg.is_some_and(|a| a.get_action().takes_values());
+    let mut at = 1;
+    while let Some(arg) = args.get(at) {
+        if arg == "--" {
+            return None;
+        }
+        let needs_value = if let Some(long) = arg.strip_prefix("--") {
+            !long.contains('=')
+                && takes_value(command.get_arguments().find(|a| a.get_long() == Some(long)))
+        } else if let Some(shorts) = arg.strip_prefix('-').filter(|s| !s.is_empty()) {
+            // In a cluster like `-qo json`, the first short flag that takes a value uses the
+            // rest of the word, or the next word when it ends the cluster
+            let mut chars = shorts.chars();
+            loop {
+                let Some(c) = chars.next() else { break false };
+                if takes_value(command.get_arguments().find(|a| a.get_short() == Some(c))) {
+                    break chars.as_str().is_empty();
+                }
+            }
+        } else {
+            return Some(at);
+        };
+        at += if needs_value { 2 } else { 1 };
+    }
+    None
+}
+
+/// One command line of the REPL, as full `unifi` arguments
+async fn run_repl_line(
+    cfg: &config::Config,
+    session: &Rc<Session>,
+    args: Vec<String>,
+) -> Result<()> {
+    let matches = match Cli::command().try_get_matches_from(args) {
+        Ok(matches) => matches,
+        Err(err) => {
+            // Help and usage errors are printed as on the command line
+            err.print()?;
+            return Ok(());
+        }
+    };
+    let cli = Cli::from_arg_matches(&matches)?;
+    anyhow::ensure!(cli.watch.is_none(), "--watch is not available in the REPL");
+    let (mut ctx, command) = context(cli, &matches, cfg);
+    ctx.session = Some(session.clone());
+    let started = std::time::Instant::now();
+    let result = dispatch(&ctx, command).await;
+    if let Some(timings) = &ctx.timings {
+        eprint!("{}", timings.report(started.elapsed()));
+    }
+    result
+}
+
+/// Re-run the command line every `interval`, redrawing the screen; lines not present in the
+/// previous frame are highlighted
+async fn watch(ctx: &Context, interval: Duration) -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let title = format!("Every {}s: unifi {}", interval.as_secs(), args.join(" "));
+    let mut previous: Option<HashSet<String>> = None;
+    loop {
+        // Commands are consumed by dispatch, so parse a fresh copy for every run
+        let result = dispatch(ctx, Cli::parse().command).await;
+        let frame = ctx.capture.as_ref().map(RefCell::take).unwrap_or_default();
+
+        let mut screen = format!(
+            "\x1b[2J\x1b[H{title}    {}\n\n",
+            chrono::Local::now().format("%H:%M:%S")
+        );
+        for line in frame.lines() {
+            let changed = previous.as_ref().is_some_and(|p| !p.contains(line));
+            if changed && ctx.color {
+                screen.push_str(&style::Style::Reverse.paint(line, true));
+            } else {
+                screen.push_str(line);
+            }
+            screen.push('\n');
+        }
+        if let Err(err) = result
+            && !err.is::<EmptyResult>()
+        {
+            screen.push_str(&format!("Error: {err:#}\n"));
+        }
+        print!("{screen}");
+        std::io::stdout().flush()?;
+
+        previous = Some(frame.lines().map(String::from).collect());
+        tokio::time::sleep(interval).await;
+    }
+}
 
-    match cli.command {
-        Commands::Config { host, api_key } => handle_config(host, api_key)?,
-        Commands::Internet { command } => handle_internet(command).await?,
-        Commands::Dns { command } => handle_dns(command).await?,
-        Commands::Security => handle_security().await?,
-        Commands::Firewall { command } => handle_firewall(command).await?,
-        Commands::Vpn { command } => handle_vpn(command).await?,
-        Commands::Networks => handle_networks().await?,
-        Commands::Wifi => handle_wifi().await?,
-        Commands::Devices => handle_devices().await?,
-        Commands::Clients { command } => handle_clients(command).await?,
+async fn dispatch(ctx: &Context, command: Commands) -> Result<()> {
+    match command {
+        Commands::Config {
+            command: Some(ConfigCommands::Test),
+            ..
+        } => handle_config_test(ctx).await?,
+        Commands::Config {
+            command: Some(ConfigCommands::Show),
+            ..
+        } => {
+            let origins = config::load_config(&ctx.config_path)?.explain(
+                &ctx.config_path,
+                &ctx.flags,
+                ctx.profile.as_deref(),
+                ctx.host.as_deref(),
+                ctx.api_key.as_deref(),
+                ctx.insecure,
+            )?;
+            ctx.print_list(&origins, output::CONFIG_ORIGINS)?;
+        }
+        Commands::Config {
+            command: None,
+            username,
+            password,
+            legacy,
+            encrypt,
+            decrypt,
+        } => {
+            // `--host` and `--api-key` are global; here they are what gets saved
+            let (host, api_key) = (ctx.host.clone(), ctx.api_key.clone());
+            handle_config(
+                ctx, host, api_key, username, password, legacy, encrypt, decrypt,
+            )
+            .await?
+        }
+        Commands::Internet { command } => handle_internet(ctx, command).await?,
+        Commands::Dns { command } => handle_dns(ctx, command).await?,
+        Commands::Security { command } => handle_security(ctx, command).await?,
+        Commands::Firewall { command } => handle_firewall(ctx, command).await?,
+        Commands::Vpn { command } => handle_vpn(ctx, command).await?,
+        Commands::Networks { command } => handle_networks(ctx, command).await?,
+        Commands::Wifi { command } => handle_wifi(ctx, command).await?,
+        Commands::Devices {
+            wide,
+            short,
+            command,
+        } => handle_devices(ctx, wide, short, command).await?,
+        Commands::Clients { command } => handle_clients(ctx, command).await?,
+        Commands::System { command } => handle_system(ctx, command).await?,
+        Commands::Maintenance { command } => handle_maintenance(ctx, command).await?,
+        Commands::Portal { command } => handle_portal(ctx, command).await?,
+        Commands::Oui { command } => handle_oui(ctx, command).await?,
+        Commands::Switch { command } => handle_switch(ctx, command).await?,
+        Commands::Stats { command } => handle_stats(ctx, command).await?,
+        Commands::Api { command } => handle_api(ctx, command).await?,
+        Commands::Overview => {
+            let client = get_client(ctx).await?;
+            ctx.print(&client.overview().await?)?;
+        }
+        Commands::Search { term } => {
+            let client = get_client(ctx).await?;
+            ctx.print_list(&client.search(&term).await?, output::SEARCH)?;
+        }
+        Commands::Resolve { id } => {
+            let client = get_client(ctx).await?;
+            ctx.print_list(&client.resolve_id(&id).await?, output::RESOLVED)?;
+        }
+        Commands::Sites => {
+            let client = get_client(ctx).await?;
+            ctx.print_list(&client.get_sites().await?, output::SITES)?;
+        }
+        Commands::Discover { address, wait } => handle_discover(ctx, address, wait).await?,
+        Commands::Doctor => handle_doctor(ctx).await?,
+        Commands::Permissions => {
+            let client = get_client(ctx).await?;
+            let permissions = client.probe_permissions(ctx.dry_run).await;
+            ctx.print_list(&permissions, output::PERMISSIONS)?;
+        }
+        Commands::Repl => anyhow::bail!("Already in the REPL"),
+        Commands::Diag { command } => handle_diag(ctx, command).await?,
+        Commands::Automate {
+            rules,
+            interval,
+            cooldown,
+        } => handle_automate(ctx, rules, interval, cooldown).await?,
+        Commands::Audit { command } => handle_audit(ctx, command).await?,
+        Commands::Hooks { command } => handle_hooks(ctx, command).await?,
+        Commands::Alias { command } => handle_alias(ctx, command)?,
+        Commands::Copy {
+            from,
+            to,
+            resources,
+            rename_prefix,
+        } => handle_copy(ctx, from, to, resources, rename_prefix).await?,
+        Commands::Report {
+            period,
+            sections,
+            format,
+            out,
+            mail,
+        } => handle_report(ctx, period, sections, format, out, mail).await?,
+        Commands::Serve {
+            listen,
+            token,
+            cache_ttl,
+            people,
+        } => handle_serve(ctx, listen, token, cache_ttl, people).await?,
     }
 
     Ok(())
 }
+
+/// Key of the `columns` config section for a command line: the subcommand path joined with
+/// `_`, dashes included (`clients online` is `clients_online`)
+fn command_key(matches: &clap::ArgMatches) -> String {
+    let mut path = Vec::new();
+    let mut current = matches;
+    while let Some((name, sub)) = current.subcommand() {
+        path.push(name.replace('-', "_"));
+        current = sub;
+    }
+    path.join("_")
+}
+
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // A broken config surfaces from the command that needs it; here only defaults, aliases,
+    // columns, and write headers matter
+    let config_path = config::config_path(cli.config.as_deref());
+    let migrated = config::migrate_json_config(&config_path);
+    let cfg = config::load_config(&config_path).unwrap_or_default();
+    if let Some(tz) = &cfg.defaults.timezone
+        && std::env::var_os("TZ").is_none()
+    {
+        // SAFETY: the runtime is not started yet, so no other thread reads the environment
+        unsafe { std::env::set_var("TZ", tz) };
+    }
+
+    // RUST_LOG (e.g., `unifi=trace`) takes precedence over --log-level, which beats -v
+    let level = match (cli.log_level, cli.verbose, cli.debug) {
+        (Some(LogLevel::Error), _, _) => "error",
+        (Some(LogLevel::Warn), _, _) => "warn",
+        (Some(LogLevel::Info), _, _) => "info",
+        (Some(LogLevel::Debug), _, _) => "debug",
+        (Some(LogLevel::Trace), _, _) => "trace",
+        (None, _, true) | (None, 2.., _) => "debug",
+        (None, 1, _) => "info",
+        _ => "warn",
+    };
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| format!("unifi={level}"));
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+        .with_writer(std::io::stderr);
+    match cli.log_format {
+        LogFormat::Json => logs.json().with_current_span(true).init(),
+        LogFormat::Text => logs.with_ansi(std::io::stderr().is_terminal()).init(),
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    let color = color_choice(&cli, &cfg.defaults);
+    match runtime.block_on(run(cli, &matches, &cfg, migrated)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            if !err.is::<EmptyResult>() {
+                let label = style::Style::Red.paint("Error:", color.stderr());
+                eprintln!("{label} {err:?}");
+            }
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
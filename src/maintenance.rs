@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::api::{Client, NotFound};
+use crate::{config, duration};
+
+/// IPS modes that drop traffic; `ids` only reports it
+const BLOCKING_IPS_MODES: &[&str] = &["ips", "ipsInline"];
+
+/// An open maintenance window and what to put back when it closes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Window {
+    /// Unix seconds
+    pub started: i64,
+    /// Unix seconds
+    pub until: i64,
+    /// Backup taken when the window opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    /// Alert notifications (`setting/mgmt` `alert_enabled`) were on and are paused
+    pub alerts_paused: bool,
+    /// IPS mode before blocking was paused (detection keeps running as `ids`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ips_mode: Option<String>,
+}
+
+impl Window {
+    /// The window with local times and the time left, for display
+    pub fn view(&self) -> Value {
+        let local = |unix: i64| {
+            chrono::DateTime::from_timestamp(unix, 0)
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                })
+                .unwrap_or_default()
+        };
+        let left = self.until - duration::now_secs();
+        json!({
+            "started": local(self.started),
+            "until": local(self.until),
+            "remaining": match left > 0 {
+                true => duration::humanize(left),
+                false => "ended (run 'unifi maintenance end')".to_string(),
+            },
+            "backup": self.backup,
+            "alerts_paused": self.alerts_paused,
+            "ips_mode": self.ips_mode,
+        })
+    }
+}
+
+/// The window is kept on disk so `unifi maintenance end` can close it from another process
+fn window_path() -> PathBuf {
+    config::config_dir().join("maintenance.json")
+}
+
+/// The open maintenance window, if any
+pub fn load() -> Result<Option<Window>> {
+    let path = window_path();
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)?;
+    let window = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(window))
+}
+
+pub fn save(window: &Window) -> Result<()> {
+    let path = window_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(window)?)?;
+    Ok(())
+}
+
+pub fn clear() -> Result<()> {
+    let path = window_path();
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+impl Client {
+    /// Read the current alert and IPS settings into a window ending at `until`; IPS blocking
+    /// is only paused when `pause_ips` is set
+    pub async fn maintenance_window(&self, until: i64, pause_ips: bool) -> Result<Window> {
+        let mgmt = self.get_setting("mgmt").await?;
+        let ips_mode = match pause_ips {
+            true => {
+                let ips = self
+                    .get_setting("ips")
+                    .await
+                    .map_err(|e| match e.is::<NotFound>() {
+                        true => anyhow::anyhow!("This site has no IPS settings (no gateway?)"),
+                        false => e,
+                    })?;
+                ips.get("ips_mode")
+                    .and_then(|v| v.as_str())
+                    .filter(|mode| BLOCKING_IPS_MODES.contains(mode))
+                    .map(String::from)
+            }
+            false => None,
+        };
+        Ok(Window {
+            started: duration::now_secs(),
+            until,
+            backup: None,
+            alerts_paused: mgmt.get("alert_enabled").and_then(|v| v.as_bool()) == Some(true),
+            ips_mode,
+        })
+    }
+
+    /// Pause alert notifications and IPS blocking as recorded in `window`
+    pub async fn open_maintenance(&self, window: &Window) -> Result<()> {
+        if window.alerts_paused {
+            self.set_alerts(false).await?;
+        }
+        if window.ips_mode.is_some() {
+            self.set_ips_mode("ids").await?;
+        }
+        Ok(())
+    }
+
+    /// Put back what `open_maintenance` changed
+    pub async fn close_maintenance(&self, window: &Window) -> Result<()> {
+        if window.alerts_paused {
+            self.set_alerts(true).await?;
+        }
+        if let Some(mode) = &window.ips_mode {
+            self.set_ips_mode(mode).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_alerts(&self, enabled: bool) -> Result<Value> {
+        let fields = json!({"alert_enabled": enabled});
+        self.update_setting("mgmt", fields.as_object().unwrap())
+            .await
+    }
+
+    async fn set_ips_mode(&self, mode: &str) -> Result<Value> {
+        let fields = json!({"ips_mode": mode});
+        self.update_setting("ips", fields.as_object().unwrap())
+            .await
+    }
+}
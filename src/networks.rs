@@ -1,11 +1,679 @@
-use anyhow::Result;
-use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr};
 
-use crate::api::Client;
+use anyhow::{Result, bail};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::api::{Client, NotFound};
+use crate::clients::normalize_mac;
+use crate::firewall::{self, Flow};
+
+/// Private IPv4 ranges blocked by `isolate_network`
+const RFC1918: &[&str] = &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"];
+
+/// User-defined LAN rules start at this index
+const FIRST_USER_RULE_INDEX: u64 = 2000;
+
+/// A firewall rule `isolate_network` or `expose_service` wants in place
+#[derive(Debug, Serialize)]
+pub struct PlannedRule {
+    pub name: String,
+    pub ruleset: String,
+    pub rule_index: Option<u64>,
+    pub action: String,
+    /// `created`, or `exists` when a rule with this name was already there
+    pub status: &'static str,
+}
+
+/// One new connection replayed against the rules after a change
+#[derive(Debug, Serialize)]
+pub struct FlowCheck {
+    pub ruleset: String,
+    pub from: String,
+    pub to: String,
+    pub protocol: String,
+    pub port: Option<u16>,
+    pub expected: &'static str,
+    /// Action of the first matching rule, `default` when none matched
+    pub verdict: String,
+    pub rule: Option<String>,
+    pub ok: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IsolationReport {
+    pub network: String,
+    pub subnet: String,
+    pub gateway: String,
+    pub rules: Vec<PlannedRule>,
+    pub checks: Vec<FlowCheck>,
+    pub verified: bool,
+}
+
+/// Services `expose_service` knows how to open across networks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    /// mDNS/Bonjour discovery only
+    Mdns,
+    /// Printers: IPP/AirPrint, raw (JetDirect), and LPD
+    Print,
+    /// Chromecast and other Google Cast receivers
+    Cast,
+}
+
+impl Service {
+    fn label(self) -> &'static str {
+        match self {
+            Service::Mdns => "mDNS",
+            Service::Print => "printing",
+            Service::Cast => "Cast",
+        }
+    }
+
+    /// (protocol, destination ports) that clients open towards the device
+    fn ports(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Service::Mdns => &[],
+            Service::Print => &[("tcp", "631,9100,515")],
+            Service::Cast => &[("tcp", "8008-8009,8443")],
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExposeReport {
+    pub device: String,
+    pub ip: String,
+    pub service: Service,
+    /// Network the device is on, when its address is in a known subnet
+    pub network: Option<String>,
+    pub to: String,
+    /// Networks that had mDNS turned on
+    pub mdns_enabled: Vec<String>,
+    pub rules: Vec<PlannedRule>,
+    pub checks: Vec<FlowCheck>,
+    pub verified: bool,
+}
+
+/// A network's gateway address and its subnet in `a.b.c.0/n` form, from `ip_subnet`
+/// (`192.168.10.1/24`)
+fn subnet_of(network: &Value) -> Option<(Ipv4Addr, String)> {
+    let (gateway, len) = network.get("ip_subnet")?.as_str()?.split_once('/')?;
+    let gateway: Ipv4Addr = gateway.parse().ok()?;
+    let len: u32 = len.parse().ok().filter(|l| *l <= 32)?;
+    let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+    let base = Ipv4Addr::from(u32::from(gateway) & mask);
+    Some((gateway, format!("{base}/{len}")))
+}
+
+/// A network or user group by name (any case) or `_id`
+fn find_named<'a>(records: &'a [Value], name: &str) -> Option<&'a Value> {
+    records.iter().find(|n| {
+        n.get("name")
+            .and_then(|v| v.as_str())
+            .is_some_and(|v| v.eq_ignore_ascii_case(name))
+            || n.get("_id").and_then(|v| v.as_str()) == Some(name)
+    })
+}
+
+/// Any address inside a CIDR other than `avoid`, to stand in for a host there
+fn sample_host(cidr: &str, avoid: Ipv4Addr) -> Option<Ipv4Addr> {
+    let (base, _) = cidr.split_once('/').unwrap_or((cidr, ""));
+    let base: Ipv4Addr = base.parse().ok()?;
+    let host = Ipv4Addr::from(u32::from(base) | 1);
+    Some(if host == avoid {
+        Ipv4Addr::from(u32::from(base) | 2)
+    } else {
+        host
+    })
+}
+
+fn text(record: &Value, key: &str) -> String {
+    record
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn text_of(fields: &Map<String, Value>, key: &str) -> String {
+    fields
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Run `flow` through the rules and compare the verdict with `expected` (`accept` or `drop`)
+fn replay(rules: &[Value], flow: &Flow, to: &str, expected: &'static str) -> FlowCheck {
+    let hit = firewall::simulate(rules, flow);
+    let verdict = hit
+        .and_then(|r| r.get("action").and_then(|v| v.as_str()))
+        .unwrap_or("default")
+        .to_string();
+    let ok = match expected {
+        "accept" => verdict == "accept",
+        _ => matches!(verdict.as_str(), "drop" | "reject"),
+    };
+    FlowCheck {
+        ruleset: flow.ruleset.to_string(),
+        from: flow.src.to_string(),
+        to: format!("{to} ({})", flow.dst),
+        protocol: flow.protocol.to_string(),
+        port: flow.dst_port,
+        expected,
+        verdict,
+        rule: hit
+            .and_then(|r| r.get("name").and_then(|v| v.as_str()))
+            .map(String::from),
+        ok,
+    }
+}
+
+fn rule(name: String, ruleset: &str, action: &str, protocol: &str) -> Map<String, Value> {
+    [
+        ("name", json!(name)),
+        ("ruleset", json!(ruleset)),
+        ("action", json!(action)),
+        ("protocol", json!(protocol)),
+        ("enabled", json!(true)),
+        ("src_address", json!("")),
+        ("dst_address", json!("")),
+        ("dst_port", json!("")),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+}
+
+/// Index for a new rule in `ruleset`: after the existing rules, or when `before` is given,
+/// the highest free index below it
+fn free_index(rules: &[Value], ruleset: &str, before: Option<u64>) -> Result<u64> {
+    let taken: Vec<u64> = rules
+        .iter()
+        .filter(|r| text(r, "ruleset") == ruleset)
+        .filter_map(|r| r.get("rule_index").and_then(|v| v.as_u64()))
+        .collect();
+    match before {
+        None => Ok(taken.iter().max().map_or(FIRST_USER_RULE_INDEX, |i| {
+            (i + 1).max(FIRST_USER_RULE_INDEX)
+        })),
+        Some(before) => (FIRST_USER_RULE_INDEX..before)
+            .rev()
+            .find(|i| !taken.contains(i))
+            .ok_or_else(|| {
+                anyhow::anyhow!("No free {ruleset} rule index below {before}; renumber the rules")
+            }),
+    }
+}
 
 impl Client {
+    /// Create `rule` unless one with its name exists, at `free_index(.., before)`, and add it
+    /// to `rules` for later replays
+    async fn place_rule(
+        &self,
+        rules: &mut Vec<Value>,
+        mut rule: Map<String, Value>,
+        before: Option<u64>,
+    ) -> Result<PlannedRule> {
+        let name = text_of(&rule, "name");
+        let ruleset = text_of(&rule, "ruleset");
+        if let Some(current) = rules.iter().find(|r| text(r, "name") == name) {
+            return Ok(PlannedRule {
+                name,
+                ruleset,
+                rule_index: current.get("rule_index").and_then(|v| v.as_u64()),
+                action: text(current, "action"),
+                status: "exists",
+            });
+        }
+        let index = free_index(rules, &ruleset, before)?;
+        rule.insert("rule_index".into(), json!(index));
+        self.create_firewall_rule(&rule).await?;
+        let action = text_of(&rule, "action");
+        rules.push(Value::Object(rule));
+        Ok(PlannedRule {
+            name,
+            ruleset,
+            rule_index: Some(index),
+            action,
+            status: "created",
+        })
+    }
+
     /// Get all networks (LANs, VLANs, VPN)
     pub async fn get_networks(&self) -> Result<Value> {
         self.get_rest("networkconf").await
     }
+
+    /// Get user groups (bandwidth profiles)
+    pub async fn get_user_groups(&self) -> Result<Value> {
+        self.get_rest("usergroup").await
+    }
+
+    /// Networks with the name of their default user group added as `usergroup`
+    pub async fn list_networks(&self) -> Result<Value> {
+        let (mut networks, groups) = tokio::try_join!(self.get_networks(), self.get_user_groups())?;
+        let groups = groups.as_array().map(Vec::as_slice).unwrap_or_default();
+        for network in networks.as_array_mut().into_iter().flatten() {
+            let group = network
+                .get("usergroup_id")
+                .and_then(|v| v.as_str())
+                .filter(|id| !id.is_empty())
+                .map(|id| {
+                    find_named(groups, id).map_or_else(|| id.to_string(), |g| text(g, "name"))
+                });
+            if let (Some(obj), Some(group)) = (network.as_object_mut(), group) {
+                obj.insert("usergroup".into(), json!(group));
+            }
+        }
+        Ok(networks)
+    }
+
+    /// Make `group` (name or ID) the default user group, and so the bandwidth profile, for
+    /// clients joining `network`
+    pub async fn set_network_user_group(&self, network: &str, group: &str) -> Result<Value> {
+        let (networks, groups) = tokio::try_join!(self.get_networks(), self.get_user_groups())?;
+        let networks = networks.as_array().map(Vec::as_slice).unwrap_or_default();
+        let groups = groups.as_array().map(Vec::as_slice).unwrap_or_default();
+        let found = find_named(networks, network)
+            .ok_or_else(|| NotFound(format!("Network '{network}' not found")))?;
+        let group_id = find_named(groups, group)
+            .and_then(|g| g.get("_id"))
+            .cloned()
+            .ok_or_else(|| NotFound(format!("User group '{group}' not found")))?;
+        let mut fields = Map::new();
+        fields.insert("usergroup_id".into(), group_id);
+        self.update_network(found, &fields).await
+    }
+
+    /// Create the standard isolation rule set for a network and replay sample connections
+    /// against the resulting rules.
+    ///
+    /// LAN_IN gets allow established/related, an allow rule per `allow_to` (network name or
+    /// CIDR), then drops to every RFC1918 range. LAN_LOCAL allows DHCP and, with `allow_dns`,
+    /// DNS to the network's gateway, then drops the rest to private addresses (the gateway's
+    /// management UI included). Rules are named `Isolate <network>: ...` and appended after the
+    /// existing rules; rules whose name already exists are left alone.
+    pub async fn isolate_network(
+        &self,
+        network: &str,
+        allow_dns: bool,
+        allow_to: &[String],
+    ) -> Result<IsolationReport> {
+        let (networks, existing) =
+            tokio::try_join!(self.get_networks(), self.get_firewall_rules())?;
+        let networks = networks.as_array().map(Vec::as_slice).unwrap_or_default();
+        let found = find_named(networks, network)
+            .ok_or_else(|| NotFound(format!("Network '{network}' not found")))?;
+        let name = found
+            .get("name")
+            .and_then(|v| v.as_str())
+            .unwrap_or(network)
+            .to_string();
+        let Some((gateway, subnet)) = subnet_of(found) else {
+            bail!("Network '{name}' has no IPv4 subnet to isolate");
+        };
+
+        let mut exceptions = Vec::new();
+        for target in allow_to {
+            let cidr = match find_named(networks, target) {
+                Some(n) => match subnet_of(n) {
+                    Some((_, cidr)) => cidr,
+                    None => bail!("Network '{target}' has no IPv4 subnet"),
+                },
+                None if crate::audit::parse_network(target).is_ok() => target.clone(),
+                None => return Err(NotFound(format!("Network '{target}' not found")).into()),
+            };
+            exceptions.push((target.clone(), cidr));
+        }
+
+        let prefix = format!("Isolate {name}");
+        let mut planned = Vec::new();
+        let mut established = rule(
+            format!("{prefix}: allow established/related"),
+            "LAN_IN",
+            "accept",
+            "all",
+        );
+        established.insert("state_established".into(), json!(true));
+        established.insert("state_related".into(), json!(true));
+        planned.push(established);
+        for (target, cidr) in &exceptions {
+            let mut r = rule(
+                format!("{prefix}: allow to {target}"),
+                "LAN_IN",
+                "accept",
+                "all",
+            );
+            r.insert("src_address".into(), json!(subnet));
+            r.insert("dst_address".into(), json!(cidr));
+            planned.push(r);
+        }
+        for range in RFC1918 {
+            let mut r = rule(format!("{prefix}: block {range}"), "LAN_IN", "drop", "all");
+            r.insert("src_address".into(), json!(subnet));
+            r.insert("dst_address".into(), json!(range));
+            planned.push(r);
+        }
+        let mut local_allows = vec![("DHCP", "udp", "67")];
+        if allow_dns {
+            local_allows.push(("DNS", "tcp_udp", "53"));
+        }
+        for (service, protocol, port) in local_allows {
+            let mut r = rule(
+                format!("{prefix}: allow {service} to gateway"),
+                "LAN_LOCAL",
+                "accept",
+                protocol,
+            );
+            r.insert("src_address".into(), json!(subnet));
+            r.insert("dst_address".into(), json!(gateway.to_string()));
+            r.insert("dst_port".into(), json!(port));
+            planned.push(r);
+        }
+        for range in RFC1918 {
+            let mut r = rule(
+                format!("{prefix}: block gateway {range}"),
+                "LAN_LOCAL",
+                "drop",
+                "all",
+            );
+            r.insert("src_address".into(), json!(subnet));
+            r.insert("dst_address".into(), json!(range));
+            planned.push(r);
+        }
+
+        let mut rules: Vec<Value> = existing.as_array().cloned().unwrap_or_default();
+        let mut report_rules = Vec::new();
+        for r in planned {
+            report_rules.push(self.place_rule(&mut rules, r, None).await?);
+        }
+
+        // Replay new connections from a host in the network
+        let Some(host) = sample_host(&subnet, gateway) else {
+            bail!("Cannot pick a sample host in {subnet}");
+        };
+        let check = |ruleset, to: String, ip, protocol, port, expected| {
+            let flow = Flow {
+                ruleset,
+                src: IpAddr::V4(host),
+                dst: IpAddr::V4(ip),
+                protocol,
+                dst_port: port,
+            };
+            replay(&rules, &flow, &to, expected)
+        };
+        let mut checks = Vec::new();
+        for (target, cidr) in &exceptions {
+            if let Some(ip) = sample_host(cidr, gateway) {
+                checks.push(check(
+                    "LAN_IN",
+                    target.clone(),
+                    ip,
+                    "tcp",
+                    Some(443),
+                    "accept",
+                ));
+            }
+        }
+        let allowed = |ip: Ipv4Addr| {
+            exceptions.iter().any(|(_, cidr)| {
+                crate::audit::parse_network(cidr).is_ok_and(|n| n.contains(IpAddr::V4(ip)))
+            })
+        };
+        for other in networks {
+            let other_name = other
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            let Some((other_gateway, other_subnet)) = subnet_of(other) else {
+                continue;
+            };
+            if other_subnet == subnet {
+                continue;
+            }
+            if let Some(ip) = sample_host(&other_subnet, other_gateway)
+                && !allowed(ip)
+            {
+                checks.push(check(
+                    "LAN_IN",
+                    other_name.to_string(),
+                    ip,
+                    "tcp",
+                    Some(443),
+                    "drop",
+                ));
+            }
+        }
+        for range in RFC1918 {
+            if let Some(ip) = sample_host(range, gateway)
+                && !allowed(ip)
+                && !crate::audit::parse_network(&subnet).is_ok_and(|n| n.contains(IpAddr::V4(ip)))
+            {
+                checks.push(check(
+                    "LAN_IN",
+                    range.to_string(),
+                    ip,
+                    "tcp",
+                    Some(443),
+                    "drop",
+                ));
+            }
+        }
+        let dns = if allow_dns { "accept" } else { "drop" };
+        checks.push(check(
+            "LAN_LOCAL",
+            "gateway DHCP".into(),
+            gateway,
+            "udp",
+            Some(67),
+            "accept",
+        ));
+        checks.push(check(
+            "LAN_LOCAL",
+            "gateway DNS".into(),
+            gateway,
+            "udp",
+            Some(53),
+            dns,
+        ));
+        checks.push(check(
+            "LAN_LOCAL",
+            "gateway UI".into(),
+            gateway,
+            "tcp",
+            Some(443),
+            "drop",
+        ));
+
+        Ok(IsolationReport {
+            network: name,
+            subnet,
+            gateway: gateway.to_string(),
+            verified: checks.iter().all(|c| c.ok),
+            rules: report_rules,
+            checks,
+        })
+    }
+
+    /// Update fields of a network, sending the rest of its current settings along
+    pub async fn update_network(
+        &self,
+        network: &Value,
+        fields: &Map<String, Value>,
+    ) -> Result<Value> {
+        let id = network
+            .get("_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Network has no _id"))?;
+        let mut body = network.clone();
+        if let Some(obj) = body.as_object_mut() {
+            obj.extend(fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        let url = self.site_url(&format!("rest/networkconf/{id}"));
+        let resp = self
+            .send(Method::PUT, &url, Some(&body), "update network")
+            .await?;
+        Ok(Self::first_data(resp.json().await?))
+    }
+
+    /// Let clients on `to` reach one service on a device in another network: turn on mDNS on
+    /// both networks for discovery, and add the LAN_IN allow rules (plus established/related
+    /// for the replies) where the current rules would drop the connection, placed just before
+    /// the rule that drops it.
+    ///
+    /// `device` is an IPv4 address, or a client name, hostname, or MAC address.
+    pub async fn expose_service(
+        &self,
+        device: &str,
+        service: Service,
+        to: &str,
+    ) -> Result<ExposeReport> {
+        let (networks, existing, online, known) = tokio::try_join!(
+            self.get_networks(),
+            self.get_firewall_rules(),
+            self.get_clients_online(),
+            self.get_clients_all()
+        )?;
+        let networks = networks.as_array().map(Vec::as_slice).unwrap_or_default();
+
+        let (label, ip) = match device.parse::<Ipv4Addr>() {
+            Ok(ip) => (device.to_string(), ip),
+            Err(_) => {
+                let wanted = device.to_lowercase();
+                let mac = normalize_mac(device);
+                let client = online
+                    .as_array()
+                    .into_iter()
+                    .chain(known.as_array())
+                    .flatten()
+                    .find(|c| {
+                        ["name", "hostname"]
+                            .iter()
+                            .any(|k| text(c, k).to_lowercase() == wanted)
+                            || text(c, "mac") == mac
+                    })
+                    .ok_or_else(|| NotFound(format!("No client or IP address '{device}'")))?;
+                let ip = ["ip", "fixed_ip", "last_ip"]
+                    .iter()
+                    .find_map(|k| text(client, k).parse::<Ipv4Addr>().ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Client '{device}' has no known IPv4 address")
+                    })?;
+                (device.to_string(), ip)
+            }
+        };
+
+        let target = find_named(networks, to)
+            .ok_or_else(|| NotFound(format!("Network '{to}' not found")))?;
+        let to_name = text(target, "name");
+        let Some((to_gateway, to_subnet)) = subnet_of(target) else {
+            bail!("Network '{to_name}' has no IPv4 subnet");
+        };
+        let Some(host) = sample_host(&to_subnet, to_gateway) else {
+            bail!("Cannot pick a sample host in {to_subnet}");
+        };
+        let home = networks.iter().find(|n| {
+            subnet_of(n).is_some_and(|(_, cidr)| {
+                crate::audit::parse_network(&cidr).is_ok_and(|net| net.contains(IpAddr::V4(ip)))
+            })
+        });
+
+        let mut rules: Vec<Value> = existing.as_array().cloned().unwrap_or_default();
+        let mut report_rules = Vec::new();
+        let dropped_by = |rules: &[Value], flow: &Flow| {
+            firewall::simulate(rules, flow)
+                .filter(|r| matches!(text(r, "action").as_str(), "drop" | "reject"))
+                .and_then(|r| r.get("rule_index").and_then(|v| v.as_u64()))
+        };
+        let prefix = format!("Expose {} on {label} to {to_name}", service.label());
+
+        // Replies from the device are new connections to the simulator; allow them by state
+        // ahead of whatever drops traffic from the device to the clients
+        let reply = Flow {
+            ruleset: "LAN_IN",
+            src: IpAddr::V4(ip),
+            dst: IpAddr::V4(host),
+            protocol: "tcp",
+            dst_port: None,
+        };
+        if !service.ports().is_empty()
+            && let Some(blocker) = dropped_by(&rules, &reply)
+        {
+            let has_state_rule = rules.iter().any(|r| {
+                text(r, "ruleset") == "LAN_IN"
+                    && text(r, "action") == "accept"
+                    && r.get("enabled").and_then(|v| v.as_bool()) != Some(false)
+                    && r.get("state_established").and_then(|v| v.as_bool()) == Some(true)
+                    && r.get("rule_index")
+                        .and_then(|v| v.as_u64())
+                        .is_some_and(|i| i < blocker)
+            });
+            if !has_state_rule {
+                let mut r = rule(
+                    format!("{prefix}: allow established/related"),
+                    "LAN_IN",
+                    "accept",
+                    "all",
+                );
+                r.insert("state_established".into(), json!(true));
+                r.insert("state_related".into(), json!(true));
+                report_rules.push(self.place_rule(&mut rules, r, Some(blocker)).await?);
+            }
+        }
+
+        let mut checks = Vec::new();
+        for (protocol, ports) in service.ports() {
+            let first_port = ports.split([',', '-']).next().and_then(|p| p.parse().ok());
+            let flow = Flow {
+                ruleset: "LAN_IN",
+                src: IpAddr::V4(host),
+                dst: IpAddr::V4(ip),
+                protocol,
+                dst_port: first_port,
+            };
+            if let Some(blocker) = dropped_by(&rules, &flow) {
+                let mut r = rule(
+                    format!("{prefix}: allow {protocol} {ports}"),
+                    "LAN_IN",
+                    "accept",
+                    protocol,
+                );
+                r.insert("src_address".into(), json!(to_subnet));
+                r.insert("dst_address".into(), json!(ip.to_string()));
+                r.insert("dst_port".into(), json!(ports));
+                report_rules.push(self.place_rule(&mut rules, r, Some(blocker)).await?);
+            }
+            checks.push(replay(&rules, &flow, &label, "accept"));
+        }
+
+        // Discovery last, once the rules are in place
+        let mut mdns_enabled = Vec::new();
+        for network in home.into_iter().chain([target]) {
+            let name = text(network, "name");
+            let on = network.get("mdns_enabled").and_then(|v| v.as_bool()) == Some(true);
+            if on || mdns_enabled.contains(&name) {
+                continue;
+            }
+            let mut fields = Map::new();
+            fields.insert("mdns_enabled".into(), json!(true));
+            self.update_network(network, &fields).await?;
+            mdns_enabled.push(name);
+        }
+
+        Ok(ExposeReport {
+            device: label,
+            ip: ip.to_string(),
+            service,
+            network: home.map(|n| text(n, "name")),
+            to: to_name,
+            mdns_enabled,
+            verified: checks.iter().all(|c| c.ok),
+            rules: report_rules,
+            checks,
+        })
+    }
 }
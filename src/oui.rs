@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// IEEE MA-L (OUI) registry in CSV form
+pub const IEEE_OUI_URL: &str = "https://standards-oui.ieee.org/oui/oui.csv";
+
+/// Vendor names keyed by the first three MAC octets as uppercase hex ("AABBCC")
+pub struct OuiTable(HashMap<String, String>);
+
+fn table_path() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("unifi")
+        .join("oui.csv")
+}
+
+/// Split one CSV line into fields, honoring double-quoted fields with `""` escapes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+impl OuiTable {
+    /// Parse the IEEE CSV (`Registry,Assignment,Organization Name,Organization Address`)
+    pub fn parse(content: &str) -> Self {
+        let map = content
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields = csv_fields(line);
+                let prefix = fields.get(1)?.trim().to_uppercase();
+                let name = fields.get(2)?.trim().to_string();
+                (prefix.len() == 6 && !name.is_empty()).then_some((prefix, name))
+            })
+            .collect();
+        Self(map)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The downloaded table, if `unifi oui update` has been run
+    pub fn cached() -> Option<&'static OuiTable> {
+        static TABLE: OnceLock<Option<OuiTable>> = OnceLock::new();
+        TABLE
+            .get_or_init(|| {
+                fs::read_to_string(table_path())
+                    .ok()
+                    .map(|c| Self::parse(&c))
+            })
+            .as_ref()
+    }
+
+    pub fn lookup(&self, mac: &str) -> Option<&str> {
+        let prefix: String = mac
+            .chars()
+            .filter(|c| c.is_ascii_hexdigit())
+            .take(6)
+            .collect::<String>()
+            .to_uppercase();
+        self.0.get(&prefix).map(String::as_str)
+    }
+}
+
+/// Locally administered MACs (e.g., phone "private address" randomization) have no vendor
+fn is_randomized(mac: &str) -> bool {
+    let first = mac.get(..2).and_then(|b| u8::from_str_radix(b, 16).ok());
+    first.is_some_and(|b| b & 0x02 != 0)
+}
+
+/// Vendor for a MAC: IEEE table first, then the controller's own `oui` field
+pub fn vendor(mac: &str, record: Option<&Value>) -> Option<String> {
+    if let Some(name) = OuiTable::cached().and_then(|t| t.lookup(mac)) {
+        return Some(name.to_string());
+    }
+    if let Some(oui) = record
+        .and_then(|r| r.get("oui"))
+        .and_then(|o| o.as_str())
+        .filter(|o| !o.is_empty())
+    {
+        return Some(oui.to_string());
+    }
+    is_randomized(mac).then(|| "(randomized)".to_string())
+}
+
+/// Add a `vendor` field to every object with a `mac` in a list response
+pub fn annotate(value: &mut Value) {
+    let Some(items) = value.as_array_mut() else {
+        return;
+    };
+    for item in items {
+        let Some(mac) = item.get("mac").and_then(|m| m.as_str()) else {
+            continue;
+        };
+        let vendor = vendor(mac, Some(item)).map(Value::String);
+        if let Some(obj) = item.as_object_mut() {
+            obj.insert("vendor".into(), vendor.unwrap_or(Value::Null));
+        }
+    }
+}
+
+/// Download the IEEE registry into the cache directory, returning the number of entries
+pub async fn update(url: &str) -> Result<usize> {
+    let content = reqwest::get(url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?
+        .text()
+        .await?;
+    let table = OuiTable::parse(&content);
+    anyhow::ensure!(!table.is_empty(), "No OUI entries found in {url}");
+
+    let path = table_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(table.len())
+}
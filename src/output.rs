@@ -0,0 +1,845 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::duration;
+use crate::style::{self, Style};
+
+/// How command output is written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Pretty-printed JSON
+    #[default]
+    Json,
+    /// Aligned columns of the most relevant fields
+    Table,
+    /// YAML (e.g., for Ansible vars files)
+    Yaml,
+    /// CSV with one row per record and nested fields flattened to `a.b` columns
+    Csv,
+}
+
+/// How a cell value is rendered
+#[derive(Debug, Clone, Copy)]
+pub enum Cell {
+    Plain,
+    /// Device `state` code
+    State,
+    /// Seconds as "3d 4h"
+    Uptime,
+}
+
+/// A table column: header and the fields to try, in order (`a.b` reaches into objects)
+pub struct Column {
+    pub header: &'static str,
+    pub fields: &'static [&'static str],
+    pub cell: Cell,
+}
+
+const fn col(header: &'static str, fields: &'static [&'static str]) -> Column {
+    Column {
+        header,
+        fields,
+        cell: Cell::Plain,
+    }
+}
+
+pub const CLIENTS: &[Column] = &[
+    col("NAME", &["name", "hostname", "mac"]),
+    col("IP", &["ip", "last_ip"]),
+    col("MAC", &["mac"]),
+    col("NETWORK", &["network", "last_connection_network_name"]),
+    col("UPLINK", &["essid", "last_uplink_name", "last_uplink"]),
+    col("VENDOR", &["vendor"]),
+    col("SAT", &["satisfaction"]),
+    col("LAST SEEN", &["last_seen_ago"]),
+];
+
+pub const UNHAPPY_CLIENTS: &[Column] = &[
+    col("NAME", &["name"]),
+    col("SAT", &["satisfaction"]),
+    col("AP", &["ap"]),
+    col("SSID", &["essid"]),
+    col("RADIO", &["radio"]),
+    col("CHANNEL", &["channel"]),
+    col("SIGNAL", &["signal"]),
+    col("CAUSES", &["causes"]),
+];
+
+pub const DEVICES: &[Column] = &[
+    col("NAME", &["name", "mac"]),
+    col("MODEL", &["model"]),
+    col("TYPE", &["type"]),
+    col("IP", &["ip"]),
+    col("MAC", &["mac"]),
+    col("VERSION", &["version"]),
+    Column {
+        header: "STATE",
+        fields: &["state"],
+        cell: Cell::State,
+    },
+    Column {
+        header: "UPTIME",
+        fields: &["uptime"],
+        cell: Cell::Uptime,
+    },
+];
+
+/// `devices --short`: just enough to spot a device that is down
+pub const DEVICES_SHORT: &[Column] = &[
+    col("NAME", &["name", "mac"]),
+    col("MODEL", &["model"]),
+    col("IP", &["ip"]),
+    Column {
+        header: "STATE",
+        fields: &["state"],
+        cell: Cell::State,
+    },
+];
+
+/// `devices --wide`: the default columns plus load
+pub const DEVICES_WIDE: &[Column] = &[
+    col("NAME", &["name", "mac"]),
+    col("MODEL", &["model"]),
+    col("TYPE", &["type"]),
+    col("IP", &["ip"]),
+    col("MAC", &["mac"]),
+    col("VERSION", &["version"]),
+    Column {
+        header: "STATE",
+        fields: &["state"],
+        cell: Cell::State,
+    },
+    Column {
+        header: "UPTIME",
+        fields: &["uptime"],
+        cell: Cell::Uptime,
+    },
+    col("CLIENTS", &["num_sta"]),
+    col("CPU", &["system-stats.cpu"]),
+    col("MEM", &["system-stats.mem"]),
+];
+
+pub const NETWORKS: &[Column] = &[
+    col("NAME", &["name"]),
+    col("PURPOSE", &["purpose"]),
+    col("VLAN", &["vlan"]),
+    col("SUBNET", &["ip_subnet"]),
+    col("DHCP", &["dhcpd_enabled"]),
+    col("USER GROUP", &["usergroup"]),
+    col("ENABLED", &["enabled"]),
+];
+
+pub const TIMELINE: &[Column] = &[
+    col("TIME", &["time"]),
+    col("OFFSET", &["offset"]),
+    col("SOURCE", &["source"]),
+    col("KIND", &["kind"]),
+    col("MESSAGE", &["message"]),
+];
+
+pub const DOCTOR: &[Column] = &[
+    col("CHECK", &["check"]),
+    col("STATUS", &["status"]),
+    col("DETAIL", &["detail"]),
+];
+
+pub const PERMISSIONS: &[Column] = &[
+    col("CAPABILITY", &["capability"]),
+    col("ACCESS", &["access"]),
+    col("PROBE", &["probe"]),
+    col("DETAIL", &["detail"]),
+];
+
+pub const CONFIG_ORIGINS: &[Column] = &[
+    col("SETTING", &["setting"]),
+    col("VALUE", &["value"]),
+    col("SOURCE", &["source"]),
+];
+
+pub const SEARCH: &[Column] = &[
+    col("TYPE", &["type"]),
+    col("NAME", &["name"]),
+    col("MAC", &["mac"]),
+    col("IP", &["ip"]),
+    col("MATCHED", &["matched"]),
+];
+
+pub const RESOLVED: &[Column] = &[
+    col("TYPE", &["type"]),
+    col("NAME", &["name"]),
+    col("COLLECTION", &["collection"]),
+    col("ID", &["id"]),
+];
+
+pub const SITES: &[Column] = &[
+    col("NAME", &["name"]),
+    col("DESCRIPTION", &["desc"]),
+    col("ID", &["_id"]),
+    col("ROLE", &["role"]),
+];
+
+pub const DISCOVERED: &[Column] = &[
+    col("IP", &["ip"]),
+    col("NAME", &["name"]),
+    col("MODEL", &["model"]),
+    col("VERSION", &["version"]),
+    col("MAC", &["mac"]),
+    col("CONSOLE", &["console"]),
+];
+
+pub const WIFI: &[Column] = &[
+    col("SSID", &["name"]),
+    col("SECURITY", &["security"]),
+    col("BAND", &["wlan_band", "wlan_bands"]),
+    col("VLAN", &["vlan"]),
+    col("HIDDEN", &["hide_ssid"]),
+    col("ENABLED", &["enabled"]),
+];
+
+pub const WLAN_OVERRIDES: &[Column] = &[
+    col("AP", &["ap"]),
+    col("WLAN", &["wlan"]),
+    col("RADIO", &["radio"]),
+    col("CHANGES", &["changes"]),
+];
+
+fn lookup<'a>(record: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(record, |v, key| v.get(key))
+        .filter(|v| !v.is_null() && v.as_str() != Some(""))
+}
+
+/// Keep only `fields` (dotted paths reach into objects) of each record; missing ones are null
+pub fn project(value: Value, fields: &[String]) -> Value {
+    let pick = |record: &Value| {
+        let obj = fields
+            .iter()
+            .map(|f| {
+                let v = f.split('.').try_fold(record, |v, key| v.get(key));
+                (f.clone(), v.cloned().unwrap_or(Value::Null))
+            })
+            .collect();
+        Value::Object(obj)
+    };
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|r| if r.is_object() { pick(&r) } else { r })
+                .collect(),
+        ),
+        Value::Object(_) => pick(&value),
+        other => other,
+    }
+}
+
+/// Reduce each record to the fields behind `columns`, keyed by the lowercased header
+/// (`LAST SEEN` becomes `last_seen`); values are left raw for machine-readable formats
+pub fn select(value: Value, columns: &[Column]) -> Value {
+    let pick = |record: &Value| {
+        let obj = columns
+            .iter()
+            .map(|c| {
+                let key = c.header.to_lowercase().replace(' ', "_");
+                let v = c.fields.iter().find_map(|f| lookup(record, f));
+                (key, v.cloned().unwrap_or(Value::Null))
+            })
+            .collect();
+        Value::Object(obj)
+    };
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|r| if r.is_object() { pick(&r) } else { r })
+                .collect(),
+        ),
+        Value::Object(_) => pick(&value),
+        other => other,
+    }
+}
+
+/// `--sort-by` key: a field (dotted paths reach into objects), optionally `:desc`
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, descending) = match s.rsplit_once(':') {
+            Some((field, "desc")) => (field, true),
+            Some((field, "asc")) => (field, false),
+            Some((_, other)) => return Err(format!("unknown order '{other}' (use asc or desc)")),
+            None => (s, false),
+        };
+        if field.is_empty() {
+            return Err("empty field".into());
+        }
+        Ok(Self {
+            field: field.to_string(),
+            descending,
+        })
+    }
+}
+
+/// `--filter` comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Equal,
+    NotEqual,
+    Contains,
+}
+
+/// `--filter` expression: `field=value`, `field!=value`, or `field~=substring`
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: String,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(eq) = s.find('=') else {
+            return Err("expected FIELD=VALUE, FIELD!=VALUE, or FIELD~=VALUE".into());
+        };
+        let (field, op) = match &s[..eq] {
+            f if f.ends_with('!') => (&f[..f.len() - 1], FilterOp::NotEqual),
+            f if f.ends_with('~') => (&f[..f.len() - 1], FilterOp::Contains),
+            f => (f, FilterOp::Equal),
+        };
+        if field.is_empty() {
+            return Err("empty field".into());
+        }
+        Ok(Self {
+            field: field.to_string(),
+            op,
+            value: s[eq + 1..].to_string(),
+        })
+    }
+}
+
+impl Filter {
+    /// Compare the field as text, ignoring case; a missing field reads as empty
+    pub fn matches(&self, record: &Value) -> bool {
+        let actual = match lookup(record, &self.field) {
+            Some(Value::String(s)) => s.to_lowercase(),
+            Some(other) => scalar(other).to_lowercase(),
+            None => String::new(),
+        };
+        let expected = self.value.to_lowercase();
+        match self.op {
+            FilterOp::Equal => actual == expected,
+            FilterOp::NotEqual => actual != expected,
+            FilterOp::Contains => actual.contains(&expected),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Field(String),
+}
+
+/// `--template` line: `{field}` placeholders (dotted paths reach into objects), `{{`/`}}` for
+/// literal braces, and `\t`, `\n`, `\\` escapes
+#[derive(Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') => text.push('\\'),
+                    Some(other) => {
+                        text.push('\\');
+                        text.push(other);
+                    }
+                    None => text.push('\\'),
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        field.push(c);
+                    }
+                    let field = field.trim();
+                    if !closed {
+                        return Err(format!("unclosed placeholder '{{{field}'"));
+                    }
+                    if field.is_empty() || field.contains('{') {
+                        return Err(format!("bad placeholder '{{{field}}}'"));
+                    }
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field.to_string()));
+                }
+                '}' => return Err("unmatched '}' (use '}}' for a literal brace)".into()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Self { pieces })
+    }
+}
+
+impl Template {
+    /// One line per record of a list (or for a single object); missing fields are empty,
+    /// arrays of scalars are comma-joined, and objects are written as JSON
+    pub fn render(&self, value: &Value) -> String {
+        let line = |record: &Value| {
+            let mut out = String::new();
+            for piece in &self.pieces {
+                match piece {
+                    Piece::Text(text) => out.push_str(text),
+                    Piece::Field(field) => {
+                        if let Some(v) = lookup(record, field) {
+                            out.push_str(&scalar(v));
+                        }
+                    }
+                }
+            }
+            out + "\n"
+        };
+        match value {
+            Value::Array(items) => items.iter().map(line).collect(),
+            other => line(other),
+        }
+    }
+}
+
+/// Numbers compare numerically, strings case-insensitively; values of different types
+/// group by type
+fn compare(a: &Value, b: &Value) -> Ordering {
+    let rank = |v: &Value| match v {
+        Value::Number(_) => 0,
+        Value::String(_) => 1,
+        Value::Bool(_) => 2,
+        _ => 3,
+    };
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+            x.total_cmp(&y)
+        }
+        (Value::String(x), Value::String(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.to_string().cmp(&b.to_string())),
+    }
+}
+
+/// Sort the records of a list by `key`; records missing the field go last either way
+pub fn sort(value: &mut Value, key: &SortKey) {
+    let Some(records) = value.as_array_mut() else {
+        return;
+    };
+    records.sort_by(
+        |a, b| match (lookup(a, &key.field), lookup(b, &key.field)) {
+            (Some(a), Some(b)) if key.descending => compare(b, a),
+            (Some(a), Some(b)) => compare(a, b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+    );
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "-".into(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.iter().all(|v| !v.is_object()) => {
+            items.iter().map(scalar).collect::<Vec<_>>().join(",")
+        }
+        other => other.to_string(),
+    }
+}
+
+fn cell(record: &Value, column: &Column) -> String {
+    let Some(value) = column.fields.iter().find_map(|f| lookup(record, f)) else {
+        return "-".into();
+    };
+    match (column.cell, value.as_i64()) {
+        (Cell::State, Some(1)) => "online".into(),
+        (Cell::State, Some(0)) => "offline".into(),
+        (Cell::State, Some(_)) => "pending".into(),
+        (Cell::Uptime, Some(secs)) => duration::humanize(secs),
+        _ => scalar(value),
+    }
+}
+
+/// Columns for records without a predefined layout: the scalar fields of the first record
+fn derived_columns(records: &[Value]) -> Vec<(String, String)> {
+    records
+        .first()
+        .and_then(|r| r.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter(|(_, v)| !v.is_object() && !v.is_array())
+                .map(|(k, _)| (k.to_uppercase(), k.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Columns whose values are colored by `style::status`
+fn is_status_column(header: &str) -> bool {
+    matches!(
+        header.to_uppercase().as_str(),
+        "STATE" | "STATUS" | "ACCESS" | "ENABLED" | "UP" | "CONNECTED" | "ONLINE"
+    )
+}
+
+/// Lay out rows so every column lines up; the last column is not padded.
+/// With `color`, the header is bold (unless `header` is false), status cells are colored by
+/// value, and missing values are dimmed.
+fn align(rows: &[Vec<String>], header: bool, color: bool) -> String {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    let status_columns: Vec<bool> = match rows.first() {
+        Some(first) if header => first.iter().map(|h| is_status_column(h)).collect(),
+        _ => vec![false; columns],
+    };
+    let mut out = String::new();
+    for (r, row) in rows.iter().enumerate() {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            // Key/value tables: color the value of a status key
+            let status_cell = status_columns[i] || (!header && i == 1 && is_status_column(&row[0]));
+            let style = match r {
+                0 if color && header => Some(Style::Bold),
+                _ if color && status_cell => style::status(cell),
+                _ if color && cell == "-" => Some(Style::Dim),
+                _ => None,
+            };
+            line.push_str(&style.map_or_else(|| cell.clone(), |s| s.paint(cell, true)));
+            // Pad separately so escape codes do not count towards the width
+            if i + 1 < columns {
+                line.push_str(&" ".repeat(widths[i] - cell.chars().count() + 2));
+            }
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Render a value as a table: lists get one row per record, objects one row per field
+pub fn table(value: &Value, columns: Option<&[Column]>, color: bool) -> String {
+    match value {
+        Value::Array(records) => {
+            let mut rows = Vec::with_capacity(records.len() + 1);
+            match columns {
+                Some(columns) => {
+                    rows.push(columns.iter().map(|c| c.header.to_string()).collect());
+                    rows.extend(
+                        records
+                            .iter()
+                            .map(|r| columns.iter().map(|c| cell(r, c)).collect()),
+                    );
+                }
+                None => {
+                    let columns = derived_columns(records);
+                    rows.push(columns.iter().map(|(h, _)| h.clone()).collect());
+                    rows.extend(records.iter().map(|r| {
+                        columns
+                            .iter()
+                            .map(|(_, k)| r.get(k).map(scalar).unwrap_or_else(|| "-".into()))
+                            .collect()
+                    }));
+                }
+            }
+            align(&rows, true, color)
+        }
+        Value::Object(obj) => {
+            let rows: Vec<Vec<String>> = obj
+                .iter()
+                .map(|(k, v)| vec![k.clone(), scalar(v)])
+                .collect();
+            align(&rows, false, color)
+        }
+        other => format!("{}\n", scalar(other)),
+    }
+}
+
+/// Nested arrays longer than this are folded on a terminal
+const FOLD_OVER: usize = 10;
+/// Items of a folded array that are still shown
+const FOLD_KEEP: usize = 3;
+
+/// Pretty-printed JSON as `serde_json` writes it; with `color`, keys, strings, numbers, and
+/// literals are highlighted, and with `fold`, nested arrays longer than `FOLD_OVER` keep their
+/// first items and a count of the rest (which is no longer valid JSON, so only for a terminal)
+pub fn json(value: &Value, color: bool, fold: bool) -> String {
+    if !color && !fold {
+        return serde_json::to_string_pretty(value).unwrap_or_default() + "\n";
+    }
+    let mut out = String::new();
+    write_json(value, 0, color, fold, &mut out);
+    out.push('\n');
+    out
+}
+
+fn write_json(value: &Value, depth: usize, color: bool, fold: bool, out: &mut String) {
+    let indent = |depth: usize| "  ".repeat(depth);
+    let quoted = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            let shown = match fold && depth > 0 && items.len() > FOLD_OVER {
+                true => FOLD_KEEP,
+                false => items.len(),
+            };
+            out.push_str("[\n");
+            for (i, item) in items[..shown].iter().enumerate() {
+                out.push_str(&indent(depth + 1));
+                write_json(item, depth + 1, color, fold, out);
+                if i + 1 < items.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            if shown < items.len() {
+                let more = format!(
+                    "... {} more items (--no-fold shows all)",
+                    items.len() - shown
+                );
+                out.push_str(&indent(depth + 1));
+                out.push_str(&Style::Dim.paint(&more, color));
+                out.push('\n');
+            }
+            out.push_str(&indent(depth));
+            out.push(']');
+        }
+        Value::Object(obj) if !obj.is_empty() => {
+            out.push_str("{\n");
+            for (i, (key, item)) in obj.iter().enumerate() {
+                out.push_str(&indent(depth + 1));
+                out.push_str(&Style::Cyan.paint(&quoted(key), color));
+                out.push_str(": ");
+                write_json(item, depth + 1, color, fold, out);
+                if i + 1 < obj.len() {
+                    out.push(',');
+                }
+                out.push('\n');
+            }
+            out.push_str(&indent(depth));
+            out.push('}');
+        }
+        Value::String(s) => out.push_str(&Style::Green.paint(&quoted(s), color)),
+        Value::Number(n) => out.push_str(&Style::Yellow.paint(&n.to_string(), color)),
+        Value::Bool(_) | Value::Null => out.push_str(&Style::Dim.paint(&value.to_string(), color)),
+        // Empty array or object
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// Flatten nested objects into `parent.child` keys; scalar arrays become `a;b;c`
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{prefix}.{k}")
+                };
+                flatten(&key, v, out);
+            }
+        }
+        Value::Array(items) if items.iter().all(|v| !v.is_object() && !v.is_array()) => {
+            let joined = items
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(String::from)
+                        .unwrap_or_else(|| v.to_string())
+                })
+                .collect::<Vec<_>>()
+                .join(";");
+            out.insert(prefix.to_string(), joined);
+        }
+        Value::Null => {
+            out.insert(prefix.to_string(), String::new());
+        }
+        Value::String(s) => {
+            out.insert(prefix.to_string(), s.clone());
+        }
+        other => {
+            out.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) || s.starts_with(' ') || s.ends_with(' ') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Render records as CSV; the header is the sorted union of all flattened keys
+pub fn csv(value: &Value) -> String {
+    let records: Vec<&Value> = match value {
+        Value::Array(items) => items.iter().collect(),
+        other => vec![other],
+    };
+    let rows: Vec<BTreeMap<String, String>> = records
+        .iter()
+        .map(|r| {
+            let mut row = BTreeMap::new();
+            flatten(if r.is_object() { "" } else { "value" }, r, &mut row);
+            row
+        })
+        .collect();
+    let header: BTreeSet<&String> = rows.iter().flat_map(|r| r.keys()).collect();
+
+    let line = |fields: Vec<String>| fields.join(",") + "\n";
+    let mut out = line(header.iter().map(|h| csv_field(h)).collect());
+    for row in &rows {
+        out.push_str(&line(
+            header
+                .iter()
+                .map(|h| csv_field(row.get(*h).map(String::as_str).unwrap_or_default()))
+                .collect(),
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn filter_operators() {
+        let f = filter("network=IoT");
+        assert_eq!(
+            (f.field.as_str(), f.op, f.value.as_str()),
+            ("network", FilterOp::Equal, "IoT")
+        );
+        let f = filter("state!=1");
+        assert_eq!(
+            (f.field.as_str(), f.op, f.value.as_str()),
+            ("state", FilterOp::NotEqual, "1")
+        );
+        let f = filter("name~=cam");
+        assert_eq!(
+            (f.field.as_str(), f.op, f.value.as_str()),
+            ("name", FilterOp::Contains, "cam")
+        );
+        // Only the first `=` separates; the value may hold more
+        let f = filter("note=a!=b");
+        assert_eq!(
+            (f.field.as_str(), f.op, f.value.as_str()),
+            ("note", FilterOp::Equal, "a!=b")
+        );
+        assert_eq!(filter("hostname!=").value, "");
+    }
+
+    #[test]
+    fn rejects_bad_filters() {
+        for bad in ["network", "=IoT", "!=IoT", "~=cam"] {
+            assert!(bad.parse::<Filter>().is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn filter_matches() {
+        let record = json!({"name": "Living Room", "state": 1, "uplink": {"name": "sw-core"}});
+        assert!(filter("name=living room").matches(&record));
+        assert!(filter("state=1").matches(&record));
+        assert!(filter("uplink.name~=CORE").matches(&record));
+        assert!(filter("ip=").matches(&record));
+        assert!(filter("ip!=10.0.0.1").matches(&record));
+        assert!(!filter("state!=1").matches(&record));
+    }
+
+    #[test]
+    fn sort_keys() {
+        let key: SortKey = "uptime:desc".parse().unwrap();
+        assert_eq!((key.field.as_str(), key.descending), ("uptime", true));
+        let key: SortKey = "stat.rx:asc".parse().unwrap();
+        assert_eq!((key.field.as_str(), key.descending), ("stat.rx", false));
+        let key: SortKey = "name".parse().unwrap();
+        assert_eq!((key.field.as_str(), key.descending), ("name", false));
+        assert!("name:up".parse::<SortKey>().is_err());
+        assert!(":desc".parse::<SortKey>().is_err());
+        assert!("".parse::<SortKey>().is_err());
+    }
+
+    #[test]
+    fn sort_puts_missing_fields_last() {
+        let mut records = json!([{"n": 2}, {}, {"n": 10}, {"n": 1}]);
+        sort(&mut records, &"n:desc".parse().unwrap());
+        assert_eq!(records, json!([{"n": 10}, {"n": 2}, {"n": 1}, {}]));
+    }
+
+    #[test]
+    fn templates() {
+        let template: Template = r"{name}\t{ uplink.name }\n{{{mac}}}".parse().unwrap();
+        let records = json!([
+            {"name": "ap", "mac": "aa", "uplink": {"name": "sw"}},
+            {"name": "tv"},
+        ]);
+        assert_eq!(template.render(&records), "ap\tsw\n{aa}\ntv\t\n{}\n");
+        let template: Template = r"C:\path {tags}".parse().unwrap();
+        assert_eq!(
+            template.render(&json!({"tags": ["a", "b"]})),
+            "C:\\path a,b\n"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for bad in ["{name", "name}", "{}", "{ }", "{a{b}"] {
+            assert!(bad.parse::<Template>().is_err(), "accepted {bad:?}");
+        }
+    }
+}
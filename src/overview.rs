@@ -0,0 +1,109 @@
+//! One-call site summary for dashboards embedding the crate.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::Client;
+use crate::ha::{self, WanStatus};
+
+#[derive(Debug, Serialize)]
+pub struct Overview {
+    pub health: Vec<SubsystemHealth>,
+    pub counts: Counts,
+    pub wan: WanStatus,
+    /// Alarms that have not been archived, newest first
+    pub alerts: Vec<Alert>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubsystemHealth {
+    /// `wan`, `www`, `lan`, `wlan`, or `vpn`
+    pub subsystem: String,
+    /// `ok`, `warning`, `error`, or `unknown`
+    pub status: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Counts {
+    pub devices: usize,
+    pub devices_online: usize,
+    pub clients: usize,
+    pub wireless_clients: usize,
+    pub wired_clients: usize,
+    pub guests: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Alert {
+    /// Unix timestamp in seconds
+    pub time: Option<i64>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+fn records(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn is_true(record: &Value, key: &str) -> bool {
+    record.get(key).and_then(|v| v.as_bool()) == Some(true)
+}
+
+impl Client {
+    /// Active (unarchived) alarms
+    pub async fn get_alarms(&self) -> Result<Value> {
+        self.get_stat("alarm?archived=false").await
+    }
+
+    /// Health, device and client counts, WAN state, and active alerts, fetched concurrently
+    pub async fn overview(&self) -> Result<Overview> {
+        let (health, devices, clients, alarms) = tokio::try_join!(
+            self.get_health(),
+            self.get_devices(),
+            self.get_clients_online(),
+            self.get_alarms()
+        )?;
+
+        let devices = records(&devices);
+        let clients = records(&clients);
+        let wired = clients.iter().filter(|c| is_true(c, "is_wired")).count();
+        let counts = Counts {
+            devices: devices.len(),
+            devices_online: devices
+                .iter()
+                .filter(|d| d.get("state").and_then(|v| v.as_i64()) == Some(1))
+                .count(),
+            clients: clients.len(),
+            wireless_clients: clients.len() - wired,
+            wired_clients: wired,
+            guests: clients.iter().filter(|c| is_true(c, "is_guest")).count(),
+        };
+
+        let text = |v: &Value, k: &str| v.get(k).and_then(|s| s.as_str()).map(String::from);
+        let mut alerts: Vec<Alert> = records(&alarms)
+            .iter()
+            .map(|a| Alert {
+                time: a.get("time").and_then(|t| t.as_i64()).map(|ms| ms / 1000),
+                key: text(a, "key"),
+                message: text(a, "msg").unwrap_or_default(),
+            })
+            .collect();
+        alerts.sort_by_key(|a| std::cmp::Reverse(a.time));
+
+        Ok(Overview {
+            health: records(&health)
+                .iter()
+                .filter_map(|s| {
+                    Some(SubsystemHealth {
+                        subsystem: text(s, "subsystem")?,
+                        status: text(s, "status").unwrap_or_else(|| "unknown".into()),
+                    })
+                })
+                .collect(),
+            counts,
+            wan: ha::wan_from_health(&health),
+            alerts,
+        })
+    }
+}
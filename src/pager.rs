@@ -0,0 +1,46 @@
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// Print `text`, through `$PAGER` (default `less`) when it is taller than the terminal.
+///
+/// Like git, `LESS=FRX` is set unless already defined, so `less` quits on its own when the
+/// text fits on one screen and keeps colors. `PAGER=` or `PAGER=cat` disables paging.
+pub fn show(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".into());
+    let fits = std::env::var("LINES")
+        .ok()
+        .and_then(|l| l.parse::<usize>().ok())
+        .is_some_and(|rows| text.lines().count() < rows);
+    if fits || pager.trim().is_empty() || pager.trim() == "cat" {
+        print!("{text}");
+        return Ok(());
+    }
+
+    let mut child = Command::new("sh")
+        .args(["-c", &pager])
+        .env(
+            "LESS",
+            std::env::var("LESS").unwrap_or_else(|_| "FRX".into()),
+        )
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start pager '{pager}'"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user quitting the pager early closes the pipe; that is not an error
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let status = child.wait().context("Pager failed")?;
+    if status.success() {
+        return Ok(());
+    }
+    // `sh` starts even when the pager does not (it exits 127 for a missing `less`); like git,
+    // print directly rather than lose the output
+    tracing::warn!("Pager '{pager}' failed ({status}), printing directly");
+    print!("{text}");
+    Ok(())
+}
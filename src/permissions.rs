@@ -0,0 +1,159 @@
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::api::{ApiError, Client};
+
+/// An ID no object has, so write probes are refused for permission or for the missing
+/// object, and change nothing either way
+const NO_SUCH_ID: &str = "000000000000000000000000";
+/// A MAC no client or device has
+const NO_SUCH_MAC: &str = "00:00:00:00:00:00";
+
+/// One request standing in for what a group of commands needs
+struct Probe {
+    capability: &'static str,
+    method: Method,
+    /// Under the site, as for `Client::raw`
+    path: String,
+    body: Option<Value>,
+}
+
+impl Probe {
+    fn read(capability: &'static str, path: &str) -> Self {
+        Probe {
+            capability,
+            method: Method::GET,
+            path: path.to_string(),
+            body: None,
+        }
+    }
+
+    fn write(capability: &'static str, method: Method, path: String, body: Value) -> Self {
+        Probe {
+            capability,
+            method,
+            path,
+            body: Some(body),
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        self.method != Method::GET
+    }
+}
+
+fn probes() -> Vec<Probe> {
+    vec![
+        Probe::read("read devices", "api/s/{site}/stat/device"),
+        Probe::read("read clients", "api/s/{site}/stat/sta"),
+        Probe::read("read networks", "api/s/{site}/rest/networkconf"),
+        Probe::read("read firewall", "api/s/{site}/rest/firewallrule"),
+        Probe::read("read settings", "api/s/{site}/rest/setting"),
+        Probe::read("read admins", "api/stat/admin"),
+        Probe::write(
+            "write firewall",
+            Method::PUT,
+            format!("api/s/{{site}}/rest/firewallrule/{NO_SUCH_ID}"),
+            json!({}),
+        ),
+        Probe::write(
+            "write networks",
+            Method::PUT,
+            format!("api/s/{{site}}/rest/networkconf/{NO_SUCH_ID}"),
+            json!({}),
+        ),
+        Probe::write(
+            "write settings",
+            Method::PUT,
+            format!("api/s/{{site}}/rest/setting/mgmt/{NO_SUCH_ID}"),
+            json!({}),
+        ),
+        Probe::write(
+            "manage clients",
+            Method::POST,
+            "api/s/{site}/cmd/stamgr".to_string(),
+            json!({"cmd": "unblock-sta", "mac": NO_SUCH_MAC}),
+        ),
+        Probe::write(
+            "manage devices",
+            Method::POST,
+            "api/s/{site}/cmd/devmgr".to_string(),
+            json!({"cmd": "unset-locate", "mac": NO_SUCH_MAC}),
+        ),
+    ]
+}
+
+/// What a probe found out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Allowed,
+    Denied,
+    /// The answer did not tell (e.g., a server error or no answer)
+    Unknown,
+    /// Write probes are not sent under `--dry-run`
+    Skipped,
+}
+
+/// One row of the capability matrix
+#[derive(Debug, Serialize)]
+pub struct Permission {
+    pub capability: &'static str,
+    pub access: Access,
+    /// Method and path of the request sent
+    pub probe: String,
+    pub detail: String,
+}
+
+impl Client {
+    /// Send one harmless request per capability and report which the credentials may use.
+    /// Writes target an object that does not exist, so an authorized write fails with 400 or
+    /// 404 instead of 401/403 and changes nothing.
+    pub async fn probe_permissions(&self, dry_run: bool) -> Vec<Permission> {
+        let mut permissions = Vec::new();
+        for probe in probes() {
+            let label = format!(
+                "{} {}",
+                probe.method,
+                probe.path.replace("{site}", &self.site)
+            );
+            let (access, detail) = if dry_run && probe.is_write() {
+                (
+                    Access::Skipped,
+                    "write probes are not sent with --dry-run".into(),
+                )
+            } else {
+                let result = self
+                    .raw(probe.method.clone(), &probe.path, probe.body.as_ref())
+                    .await;
+                classify(&probe, result)
+            };
+            permissions.push(Permission {
+                capability: probe.capability,
+                access,
+                probe: label,
+                detail,
+            });
+        }
+        permissions
+    }
+}
+
+fn classify(probe: &Probe, result: anyhow::Result<Value>) -> (Access, String) {
+    let err = match result {
+        Ok(_) => return (Access::Allowed, "ok".into()),
+        Err(err) => err,
+    };
+    let Some(status) = err.downcast_ref::<ApiError>().map(|e| e.status) else {
+        return (Access::Unknown, format!("{err:#}"));
+    };
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => (Access::Denied, status.to_string()),
+        StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND if probe.is_write() => (
+            Access::Allowed,
+            format!("{status} for the placeholder object, past the permission check"),
+        ),
+        _ => (Access::Unknown, status.to_string()),
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+use crate::api::Client;
+
+/// Guest portal branding fields live in `setting/guest_access` under this prefix
+const PORTAL_PREFIX: &str = "portal_customized";
+
+/// Validate a `#RRGGBB` color
+pub fn parse_color(s: &str) -> Result<String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    anyhow::ensure!(
+        hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        "Invalid color '{s}' (expected #RRGGBB)"
+    );
+    Ok(format!("#{}", hex.to_lowercase()))
+}
+
+impl Client {
+    /// Get guest portal branding (title, colors, logo, languages, texts)
+    pub async fn get_portal_settings(&self) -> Result<Value> {
+        let guest_access = self.get_setting("guest_access").await?;
+        let portal: Map<String, Value> = guest_access
+            .as_object()
+            .map(|o| {
+                o.iter()
+                    .filter(|(k, _)| k.starts_with(PORTAL_PREFIX) || *k == "portal_enabled")
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Value::Object(portal))
+    }
+
+    /// Update guest portal branding; `fields` use the short names without the `portal_customized_` prefix
+    pub async fn update_portal_settings(&self, fields: &Map<String, Value>) -> Result<Value> {
+        let mut body: Map<String, Value> = fields
+            .iter()
+            .map(|(k, v)| (format!("{PORTAL_PREFIX}_{k}"), v.clone()))
+            .collect();
+        body.insert(PORTAL_PREFIX.into(), Value::Bool(true));
+        self.update_setting("guest_access", &body).await?;
+        self.get_portal_settings().await
+    }
+
+    /// Upload a portal logo image and return the stored file name for `logo_file`
+    pub async fn upload_portal_logo(&self, path: &Path) -> Result<String> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("logo.png");
+        let uploaded = self
+            .upload("upload/portal-logo", file_name, bytes, "upload portal logo")
+            .await?;
+        uploaded
+            .get("filename")
+            .or_else(|| uploaded.get("_id"))
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .ok_or_else(|| anyhow::anyhow!("Unexpected upload response: {uploaded}"))
+    }
+}
@@ -0,0 +1,75 @@
+//! Line handling for `unifi repl`: shell-like word splitting and `$_` (the last result)
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+/// Split a command line into words: whitespace separates, single quotes keep everything
+/// literally, double quotes keep spaces, and a backslash escapes the next character outside
+/// single quotes
+pub fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => bail!("Unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Replace a word that is `$_`, or `$_.` followed by a path (keys and list indexes, e.g.
+/// `$_.0.mac`), with that part of the last result: strings and numbers as they are, anything
+/// else as compact JSON. Other words are returned unchanged.
+pub fn expand_last(word: &str, last: Option<&Value>) -> Result<String> {
+    let path = match word.strip_prefix("$_") {
+        Some("") => None,
+        Some(rest) => match rest.strip_prefix('.') {
+            Some(path) => Some(path),
+            None => return Ok(word.to_string()),
+        },
+        None => return Ok(word.to_string()),
+    };
+    let Some(mut value) = last else {
+        bail!("No previous result for {word}");
+    };
+    for key in path.into_iter().flat_map(|p| p.split('.')) {
+        let next = match (value, key.parse::<usize>()) {
+            (Value::Array(items), Ok(index)) => items.get(index),
+            (value, _) => value.get(key),
+        };
+        let Some(next) = next else {
+            bail!("{word}: the previous result has no '{key}'");
+        };
+        value = next;
+    }
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        other => other.to_string(),
+    })
+}
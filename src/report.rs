@@ -0,0 +1,397 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::api::Client;
+use crate::duration;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Period {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Period {
+    fn days(self) -> i64 {
+        match self {
+            Period::Daily => 1,
+            Period::Weekly => 7,
+            Period::Monthly => 30,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Period::Daily => "Daily",
+            Period::Weekly => "Weekly",
+            Period::Monthly => "Monthly",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum SectionKind {
+    Wan,
+    Clients,
+    Threats,
+    Devices,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Md,
+    Html,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub title: String,
+    pub generated_at: String,
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Section {
+    pub title: String,
+    pub summary: Vec<(String, String)>,
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn str_of(v: &Value, key: &str) -> String {
+    v.get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn int_of(v: &Value, key: &str) -> i64 {
+    v.get(key).and_then(|v| v.as_i64()).unwrap_or(0)
+}
+
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Collect the requested sections; a failing section is reported inline rather than aborting
+pub async fn build(client: &Client, period: Period, sections: &[SectionKind]) -> Report {
+    let end = duration::now_secs();
+    let start = end - period.days() * 86400;
+
+    let mut sections = sections.to_vec();
+    sections.sort();
+    sections.dedup();
+
+    let mut out = Vec::new();
+    for kind in sections {
+        let (title, result) = match kind {
+            SectionKind::Wan => ("WAN", wan_section(client, start, end).await),
+            SectionKind::Clients => ("Clients", clients_section(client, start).await),
+            SectionKind::Threats => ("Threats", threats_section(client, start, end).await),
+            SectionKind::Devices => ("Devices", devices_section(client).await),
+        };
+        let mut section = result.unwrap_or_else(|err| Section {
+            error: Some(format!("{err:#}")),
+            ..Default::default()
+        });
+        section.title = title.to_string();
+        out.push(section);
+    }
+
+    Report {
+        title: format!("UniFi {} Report", period.label()),
+        generated_at: chrono::Local::now().format("%Y-%m-%d %H:%M %Z").to_string(),
+        sections: out,
+    }
+}
+
+async fn wan_section(client: &Client, start: i64, end: i64) -> Result<Section> {
+    let health = client.get_health().await?;
+    let subsystem = |name: &str| {
+        health
+            .as_array()
+            .and_then(|a| a.iter().find(|s| str_of(s, "subsystem") == name))
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    let (wan, www) = (subsystem("wan"), subsystem("www"));
+
+    let daily = client
+        .query_stat(
+            "report/daily.site",
+            &json!({
+                "attrs": ["time", "wan-rx_bytes", "wan-tx_bytes"],
+                "start": start * 1000,
+                "end": end * 1000,
+            }),
+        )
+        .await?;
+    let total = |key: &str| {
+        daily
+            .as_array()
+            .map(|a| a.iter().filter_map(|d| d.get(key)?.as_f64()).sum::<f64>())
+            .unwrap_or(0.0)
+    };
+
+    Ok(Section {
+        summary: vec![
+            ("Status".into(), str_of(&www, "status")),
+            ("ISP".into(), str_of(&wan, "isp_name")),
+            ("WAN IP".into(), str_of(&wan, "wan_ip")),
+            ("Latency".into(), format!("{} ms", int_of(&www, "latency"))),
+            ("Uptime".into(), duration::humanize(int_of(&www, "uptime"))),
+            ("Downloaded".into(), human_bytes(total("wan-rx_bytes"))),
+            ("Uploaded".into(), human_bytes(total("wan-tx_bytes"))),
+        ],
+        ..Default::default()
+    })
+}
+
+async fn clients_section(client: &Client, start: i64) -> Result<Section> {
+    let (online, all) = tokio::try_join!(client.get_clients_online(), client.get_clients_all())?;
+    let online = online.as_array().cloned().unwrap_or_default();
+    let all = all.as_array().cloned().unwrap_or_default();
+
+    let wired = online
+        .iter()
+        .filter(|c| c.get("is_wired").and_then(|w| w.as_bool()) == Some(true))
+        .count();
+    let new = all
+        .iter()
+        .filter(|c| int_of(c, "first_seen") >= start)
+        .count();
+
+    let mut top = online.clone();
+    top.sort_by_key(|c| -(int_of(c, "rx_bytes") + int_of(c, "tx_bytes")));
+    let rows = top
+        .iter()
+        .take(10)
+        .map(|c| {
+            let name = [str_of(c, "name"), str_of(c, "hostname"), str_of(c, "mac")]
+                .into_iter()
+                .find(|s| !s.is_empty())
+                .unwrap_or_default();
+            vec![
+                name,
+                str_of(c, "ip"),
+                human_bytes(int_of(c, "rx_bytes") as f64),
+                human_bytes(int_of(c, "tx_bytes") as f64),
+            ]
+        })
+        .collect();
+
+    Ok(Section {
+        summary: vec![
+            ("Online".into(), online.len().to_string()),
+            ("Wired".into(), wired.to_string()),
+            ("Wireless".into(), (online.len() - wired).to_string()),
+            ("Known".into(), all.len().to_string()),
+            ("New this period".into(), new.to_string()),
+        ],
+        headers: ["Top clients", "IP", "Received", "Sent"]
+            .map(String::from)
+            .to_vec(),
+        rows,
+        ..Default::default()
+    })
+}
+
+async fn threats_section(client: &Client, start: i64, end: i64) -> Result<Section> {
+    let events = client
+        .query_stat(
+            "ips/event",
+            &json!({"start": start * 1000, "end": end * 1000, "_limit": 10000}),
+        )
+        .await?;
+    let events = events.as_array().cloned().unwrap_or_default();
+
+    let mut by_signature: std::collections::BTreeMap<String, usize> = Default::default();
+    for e in &events {
+        let sig = str_of(e, "inner_alert_signature");
+        let sig = if sig.is_empty() {
+            str_of(e, "msg")
+        } else {
+            sig
+        };
+        *by_signature.entry(sig).or_default() += 1;
+    }
+    let mut top: Vec<_> = by_signature.into_iter().collect();
+    top.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+
+    let blocked = events
+        .iter()
+        .filter(|e| str_of(e, "inner_alert_action") == "blocked")
+        .count();
+
+    Ok(Section {
+        summary: vec![
+            ("Events".into(), events.len().to_string()),
+            ("Blocked".into(), blocked.to_string()),
+        ],
+        headers: vec!["Signature".into(), "Count".into()],
+        rows: top
+            .into_iter()
+            .take(10)
+            .map(|(sig, n)| vec![sig, n.to_string()])
+            .collect(),
+        ..Default::default()
+    })
+}
+
+async fn devices_section(client: &Client) -> Result<Section> {
+    let devices = client.get_devices().await?;
+    let devices = devices.as_array().cloned().unwrap_or_default();
+
+    let online = devices.iter().filter(|d| int_of(d, "state") == 1).count();
+    let upgradable = devices
+        .iter()
+        .filter(|d| d.get("upgradable").and_then(|u| u.as_bool()) == Some(true))
+        .count();
+
+    Ok(Section {
+        summary: vec![
+            ("Devices".into(), devices.len().to_string()),
+            ("Online".into(), online.to_string()),
+            ("Firmware updates pending".into(), upgradable.to_string()),
+        ],
+        headers: ["Name", "Model", "State", "Version", "Uptime"]
+            .map(String::from)
+            .to_vec(),
+        rows: devices
+            .iter()
+            .map(|d| {
+                vec![
+                    str_of(d, "name"),
+                    str_of(d, "model"),
+                    if int_of(d, "state") == 1 {
+                        "online"
+                    } else {
+                        "offline"
+                    }
+                    .into(),
+                    str_of(d, "version"),
+                    duration::humanize(int_of(d, "uptime")),
+                ]
+            })
+            .collect(),
+        ..Default::default()
+    })
+}
+
+pub fn render(report: &Report, format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Md => render_md(report),
+        Format::Html => render_html(report),
+        Format::Json => serde_json::to_string_pretty(report)?,
+    })
+}
+
+fn render_md(report: &Report) -> String {
+    let mut out = format!("# {}\n\nGenerated {}\n", report.title, report.generated_at);
+    for section in &report.sections {
+        out.push_str(&format!("\n## {}\n\n", section.title));
+        if let Some(err) = &section.error {
+            out.push_str(&format!("_Unavailable: {err}_\n"));
+            continue;
+        }
+        for (k, v) in &section.summary {
+            out.push_str(&format!("- **{k}:** {v}\n"));
+        }
+        if !section.rows.is_empty() {
+            out.push_str(&format!("\n| {} |\n", section.headers.join(" | ")));
+            out.push_str(&format!("|{}\n", "---|".repeat(section.headers.len())));
+            for row in &section.rows {
+                out.push_str(&format!("| {} |\n", row.join(" | ")));
+            }
+        }
+    }
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn render_html(report: &Report) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\n\
+         <style>body{{font-family:sans-serif;max-width:50em;margin:auto}}\
+         table{{border-collapse:collapse}}td,th{{border:1px solid #ccc;padding:4px 8px;text-align:left}}</style>\n\
+         </head><body>\n<h1>{0}</h1>\n<p>Generated {1}</p>\n",
+        escape(&report.title),
+        escape(&report.generated_at)
+    );
+    for section in &report.sections {
+        out.push_str(&format!("<h2>{}</h2>\n", escape(&section.title)));
+        if let Some(err) = &section.error {
+            out.push_str(&format!("<p><em>Unavailable: {}</em></p>\n", escape(err)));
+            continue;
+        }
+        out.push_str("<ul>\n");
+        for (k, v) in &section.summary {
+            out.push_str(&format!("<li><b>{}:</b> {}</li>\n", escape(k), escape(v)));
+        }
+        out.push_str("</ul>\n");
+        if !section.rows.is_empty() {
+            out.push_str("<table>\n<tr>");
+            for h in &section.headers {
+                out.push_str(&format!("<th>{}</th>", escape(h)));
+            }
+            out.push_str("</tr>\n");
+            for row in &section.rows {
+                out.push_str("<tr>");
+                for cell in row {
+                    out.push_str(&format!("<td>{}</td>", escape(cell)));
+                }
+                out.push_str("</tr>\n");
+            }
+            out.push_str("</table>\n");
+        }
+    }
+    out.push_str("</body></html>\n");
+    out
+}
+
+/// Send the rendered report through the local `sendmail`
+pub fn mail(to: &str, subject: &str, body: &str, format: Format) -> Result<()> {
+    let content_type = match format {
+        Format::Html => "text/html",
+        Format::Json => "application/json",
+        Format::Md => "text/markdown",
+    };
+    let mut child = Command::new("sendmail")
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run sendmail")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to open sendmail stdin")?;
+    write!(
+        stdin,
+        "To: {to}\r\nSubject: {subject}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: {content_type}; charset=utf-8\r\n\r\n{body}"
+    )?;
+    drop(stdin);
+
+    let status = child.wait()?;
+    anyhow::ensure!(status.success(), "sendmail exited with {status}");
+    Ok(())
+}
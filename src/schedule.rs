@@ -0,0 +1,258 @@
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A rule schedule as given on the command line, before the site timezone is checked
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Controller `schedule` object
+    pub value: Value,
+    /// Timezone the user wrote the times in, if any
+    pub timezone: Option<String>,
+}
+
+fn day_index(s: &str) -> Result<usize> {
+    let s = s.to_lowercase();
+    DAYS.iter()
+        .position(|d| s.starts_with(d))
+        .with_context(|| format!("Unknown day '{s}' (use mon..sun)"))
+}
+
+/// Parse `mon-fri`, `sat,sun`, `weekdays`, `weekends`, or `daily` into day indices
+fn parse_days(s: &str) -> Result<Vec<usize>> {
+    let mut days = match s.to_lowercase().as_str() {
+        "daily" | "everyday" => (0..7).collect(),
+        "weekdays" => (0..5).collect(),
+        "weekends" => vec![5, 6],
+        _ => {
+            let mut days = Vec::new();
+            for part in s.split(',') {
+                match part.split_once('-') {
+                    Some((from, to)) => {
+                        let (from, to) = (day_index(from)?, day_index(to)?);
+                        // Ranges may wrap around the week (fri-mon)
+                        let mut d = from;
+                        loop {
+                            days.push(d);
+                            if d == to {
+                                break;
+                            }
+                            d = (d + 1) % 7;
+                        }
+                    }
+                    None => days.push(day_index(part)?),
+                }
+            }
+            days
+        }
+    };
+    days.sort();
+    days.dedup();
+    Ok(days)
+}
+
+fn parse_time(s: &str) -> Result<String> {
+    let (h, m) = s.split_once(':').unwrap_or((s, "00"));
+    let (h, m): (u32, u32) = (
+        h.parse().with_context(|| format!("Invalid time '{s}'"))?,
+        m.parse().with_context(|| format!("Invalid time '{s}'"))?,
+    );
+    anyhow::ensure!(h < 24 && m < 60, "Invalid time '{s}' (expected HH:MM)");
+    Ok(format!("{h:02}:{m:02}"))
+}
+
+/// Parse an hourly start slot like `sun 04:00`, `daily 03:00`, or `04:00` into the day (`None`
+/// for every day) and the hour
+pub fn parse_start_hour(s: &str) -> Result<(Option<&'static str>, u32)> {
+    let (day, time) = match s.split_whitespace().collect::<Vec<_>>()[..] {
+        [time] => (None, time),
+        [day, time] if matches!(day.to_lowercase().as_str(), "daily" | "everyday") => (None, time),
+        [day, time] => (Some(DAYS[day_index(day)?]), time),
+        _ => bail!("Invalid start '{s}' (expected e.g. 'sun 04:00' or 'daily 03:00')"),
+    };
+    let time = parse_time(time)?;
+    let (hour, minute) = time.split_once(':').unwrap_or_default();
+    anyhow::ensure!(minute == "00", "Starts are on the hour, got '{time}'");
+    Ok((day, hour.parse()?))
+}
+
+/// Parse a schedule like `mon-fri 08:00-17:00`, `weekends`, `daily 22:00-06:00 Europe/Paris`,
+/// or `always`
+pub fn parse(s: &str) -> Result<Schedule> {
+    let mut parts = s.split_whitespace();
+    let Some(days) = parts.next() else {
+        bail!("Empty schedule");
+    };
+    if days.eq_ignore_ascii_case("always") {
+        return Ok(Schedule {
+            value: json!({"mode": "ALWAYS"}),
+            timezone: None,
+        });
+    }
+
+    let days = parse_days(days)?;
+    let mut value = json!({
+        "mode": if days.len() == 7 { "EVERY_DAY" } else { "EVERY_WEEK" },
+        "repeat_on_days": days.iter().map(|d| DAYS[*d]).collect::<Vec<_>>(),
+        "time_all_day": true,
+    });
+
+    let mut timezone = None;
+    for part in parts {
+        if let Some((start, end)) = part.split_once('-').filter(|_| part.contains(':')) {
+            value["time_all_day"] = json!(false);
+            value["time_range_start"] = json!(parse_time(start)?);
+            value["time_range_end"] = json!(parse_time(end)?);
+        } else if timezone.is_none() {
+            timezone = Some(part.to_string());
+        } else {
+            bail!("Unexpected '{part}' in schedule '{s}'");
+        }
+    }
+    Ok(Schedule { value, timezone })
+}
+
+impl Schedule {
+    /// The controller evaluates schedules in the site timezone; refuse times written for another
+    pub fn for_site(self, site_timezone: Option<&str>) -> Result<Value> {
+        if let (Some(tz), Some(site)) = (&self.timezone, site_timezone)
+            && !tz.eq_ignore_ascii_case(site)
+        {
+            bail!(
+                "Schedule timezone {tz} differs from the site timezone {site}; \
+                 give the times in {site}"
+            );
+        }
+        Ok(self.value)
+    }
+}
+
+/// Render a controller schedule object as e.g. "Mon-Fri 08:00-17:00"
+pub fn describe(schedule: &Value) -> Option<String> {
+    let mode = schedule.get("mode")?.as_str()?;
+    let days = match mode {
+        "ALWAYS" => return Some("Always".into()),
+        "EVERY_DAY" => "Daily".to_string(),
+        "EVERY_WEEK" | "CUSTOM" => {
+            let mut idx: Vec<usize> = schedule
+                .get("repeat_on_days")?
+                .as_array()?
+                .iter()
+                .filter_map(|d| d.as_str().and_then(|d| day_index(d).ok()))
+                .collect();
+            idx.sort();
+            let contiguous = idx.windows(2).all(|w| w[1] == w[0] + 1);
+            let title = |d: usize| {
+                let d = DAYS[d];
+                d[..1].to_uppercase() + &d[1..]
+            };
+            match idx.as_slice() {
+                [] => return None,
+                [one] => title(*one),
+                [first, .., last] if contiguous => format!("{}-{}", title(*first), title(*last)),
+                _ => idx.iter().map(|d| title(*d)).collect::<Vec<_>>().join(","),
+            }
+        }
+        "ONE_TIME_ONLY" => schedule
+            .get("date")
+            .and_then(|d| d.as_str())
+            .unwrap_or("Once")
+            .to_string(),
+        other => other.to_string(),
+    };
+    if schedule.get("time_all_day").and_then(|v| v.as_bool()) == Some(false) {
+        let start = schedule.get("time_range_start")?.as_str()?;
+        let end = schedule.get("time_range_end")?.as_str()?;
+        Some(format!("{days} {start}-{end}"))
+    } else {
+        Some(days)
+    }
+}
+
+/// Add a readable `schedule_text` next to every `schedule` in a rule listing
+pub fn annotate(rules: &mut Value) {
+    for rule in rules.as_array_mut().into_iter().flatten() {
+        let text = rule.get("schedule").and_then(describe);
+        if let (Some(text), Some(obj)) = (text, rule.as_object_mut()) {
+            obj.insert("schedule_text".into(), Value::String(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documented_schedules() {
+        let office = parse("mon-fri 08:00-17:00").unwrap();
+        assert_eq!(
+            office.value,
+            json!({
+                "mode": "EVERY_WEEK",
+                "repeat_on_days": ["mon", "tue", "wed", "thu", "fri"],
+                "time_all_day": false,
+                "time_range_start": "08:00",
+                "time_range_end": "17:00",
+            })
+        );
+        assert_eq!(office.timezone, None);
+
+        let weekends = parse("weekends").unwrap().value;
+        assert_eq!(weekends["repeat_on_days"], json!(["sat", "sun"]));
+        assert_eq!(weekends["time_all_day"], json!(true));
+
+        let night = parse("daily 22:00-06:00 Europe/Paris").unwrap();
+        assert_eq!(night.value["mode"], "EVERY_DAY");
+        assert_eq!(night.value["time_range_start"], "22:00");
+        assert_eq!(night.timezone.as_deref(), Some("Europe/Paris"));
+
+        assert_eq!(parse("always").unwrap().value, json!({"mode": "ALWAYS"}));
+    }
+
+    #[test]
+    fn day_ranges_wrap_around_the_week() {
+        assert_eq!(parse_days("fri-mon").unwrap(), [0, 4, 5, 6]);
+        assert_eq!(parse_days("sat,mon-tue,sat").unwrap(), [0, 1, 5]);
+        assert_eq!(parse_days("Monday-Wednesday").unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn timezone_may_contain_a_dash() {
+        let schedule = parse("sat 9-17:00 America/Port-au-Prince").unwrap();
+        assert_eq!(schedule.timezone.as_deref(), Some("America/Port-au-Prince"));
+        assert_eq!(schedule.value["time_range_start"], "09:00");
+    }
+
+    #[test]
+    fn rejects_bad_schedules() {
+        for bad in [
+            "",
+            "funday",
+            "mon-xyz",
+            "mon-fri 08:00-24:00",
+            "mon-fri 08:60-17:00",
+            "mon-fri 08:00-17:00 Europe/Paris extra",
+        ] {
+            assert!(parse(bad).is_err(), "accepted {bad:?}");
+        }
+    }
+
+    #[test]
+    fn refuses_times_for_another_timezone() {
+        let schedule = parse("daily 22:00-06:00 Europe/Paris").unwrap();
+        assert!(schedule.clone().for_site(Some("europe/paris")).is_ok());
+        assert!(schedule.clone().for_site(None).is_ok());
+        assert!(schedule.for_site(Some("America/New_York")).is_err());
+    }
+
+    #[test]
+    fn start_hours() {
+        assert_eq!(parse_start_hour("sun 04:00").unwrap(), (Some("sun"), 4));
+        assert_eq!(parse_start_hour("daily 03:00").unwrap(), (None, 3));
+        assert_eq!(parse_start_hour("04:00").unwrap(), (None, 4));
+        assert!(parse_start_hour("04:30").is_err());
+        assert!(parse_start_hour("sun 04:00 extra").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::{Client, NotFound};
+use crate::audit;
+use crate::clients::normalize_mac;
+
+/// One object matching a search term
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    /// `client`, `device`, `network`, or `wlan`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    pub mac: Option<String>,
+    pub ip: Option<String>,
+    /// The field that matched and its value
+    pub matched: String,
+}
+
+/// The object behind a controller `_id`
+#[derive(Debug, Serialize)]
+pub struct Resolved {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    /// Collection it was found in, e.g. `rest/networkconf`
+    pub collection: String,
+    pub id: String,
+}
+
+/// `rest/` collections searched by `resolve`, with the type reported for them
+const REST_COLLECTIONS: &[(&str, &str)] = &[
+    ("network", "networkconf"),
+    ("wlan", "wlanconf"),
+    ("client", "user"),
+    ("user_group", "usergroup"),
+    ("firewall_rule", "firewallrule"),
+    ("firewall_group", "firewallgroup"),
+    ("port_forward", "portforward"),
+    ("route", "routing"),
+    ("wlan_group", "wlangroup"),
+    ("radius_profile", "radiusprofile"),
+    ("radius_account", "account"),
+    ("hotspot_operator", "hotspotop"),
+    ("dynamic_dns", "dynamicdns"),
+    ("scheduled_task", "scheduletask"),
+    ("tag", "tag"),
+    ("setting", "setting"),
+];
+
+fn text<'a>(record: &'a Value, key: &str) -> Option<&'a str> {
+    record
+        .get(key)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+}
+
+/// First of `fields` whose value contains `term` (case-insensitive), as `field=value`
+fn find_match(record: &Value, fields: &[&str], term: &str) -> Option<String> {
+    fields.iter().find_map(|f| {
+        let value = text(record, f)?;
+        value
+            .to_lowercase()
+            .contains(term)
+            .then(|| format!("{f}={value}"))
+    })
+}
+
+fn hit(kind: &'static str, record: &Value, name_keys: &[&str], matched: String) -> Hit {
+    Hit {
+        kind,
+        name: name_keys
+            .iter()
+            .find_map(|k| text(record, k))
+            .unwrap_or_default()
+            .to_string(),
+        mac: text(record, "mac").map(String::from),
+        ip: ["ip", "last_ip", "fixed_ip", "ip_subnet"]
+            .iter()
+            .find_map(|k| text(record, k))
+            .map(String::from),
+        matched,
+    }
+}
+
+fn records(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+impl Client {
+    /// Clients, devices, networks, and WLANs whose name, MAC, IP, or SSID contains `term`;
+    /// an IP address also finds the network whose subnet contains it
+    pub async fn search(&self, term: &str) -> Result<Vec<Hit>> {
+        let (online, known, devices, networks, wlans) = tokio::try_join!(
+            self.get_clients_online(),
+            self.get_clients_all(),
+            self.get_devices(),
+            self.get_networks(),
+            self.get_wifi()
+        )?;
+        let needle = term.trim().to_lowercase();
+        let mac_needle = normalize_mac(&needle);
+        let ip: Option<IpAddr> = needle.parse().ok();
+        let client_fields = [
+            "name", "hostname", "mac", "ip", "last_ip", "fixed_ip", "essid",
+        ];
+        let mut hits = Vec::new();
+
+        // Online records first: they carry the current IP
+        let mut seen_clients = HashSet::new();
+        for c in records(&online).iter().chain(records(&known)) {
+            let Some(mac) = text(c, "mac") else { continue };
+            if seen_clients.contains(mac) {
+                continue;
+            }
+            let matched = find_match(c, &client_fields, &needle)
+                .or_else(|| find_match(c, &["mac"], &mac_needle));
+            if let Some(matched) = matched {
+                seen_clients.insert(mac.to_string());
+                hits.push(hit("client", c, &["name", "hostname", "mac"], matched));
+            }
+        }
+        for d in records(&devices) {
+            let matched = find_match(d, &["name", "mac", "ip", "model"], &needle)
+                .or_else(|| find_match(d, &["mac"], &mac_needle));
+            if let Some(matched) = matched {
+                hits.push(hit("device", d, &["name", "mac"], matched));
+            }
+        }
+        for n in records(&networks) {
+            let in_subnet = || {
+                let subnet = text(n, "ip_subnet")?;
+                let net = audit::parse_network(subnet).ok()?;
+                net.contains(ip?).then(|| format!("ip_subnet={subnet}"))
+            };
+            if let Some(matched) = find_match(n, &["name", "ip_subnet"], &needle).or_else(in_subnet)
+            {
+                hits.push(hit("network", n, &["name"], matched));
+            }
+        }
+        for w in records(&wlans) {
+            if let Some(matched) = find_match(w, &["name"], &needle) {
+                hits.push(hit("wlan", w, &["name"], matched));
+            }
+        }
+        Ok(hits)
+    }
+
+    /// What a controller object ID refers to: devices, sites, and the configuration
+    /// collections are searched; collections the controller does not have are skipped
+    pub async fn resolve_id(&self, id: &str) -> Result<Vec<Resolved>> {
+        let id = id.trim();
+        let mut found = Vec::new();
+        let mut collect = |kind: &'static str, collection: String, value: &Value| {
+            for r in records(value).iter().filter(|r| text(r, "_id") == Some(id)) {
+                let name = ["name", "hostname", "key", "desc", "mac"]
+                    .iter()
+                    .find_map(|k| text(r, k))
+                    .unwrap_or_default()
+                    .to_string();
+                found.push(Resolved {
+                    kind,
+                    name,
+                    collection: collection.clone(),
+                    id: id.to_string(),
+                });
+            }
+        };
+
+        let (devices, sites) = tokio::join!(self.get_devices(), self.get_sites());
+        for (kind, collection, result) in [
+            ("device", "stat/device", devices),
+            ("site", "self/sites", sites),
+        ] {
+            match result {
+                Ok(value) => collect(kind, collection.to_string(), &value),
+                Err(err) => tracing::debug!(collection, "skipped: {err:#}"),
+            }
+        }
+        for (kind, endpoint) in REST_COLLECTIONS {
+            match self.get_rest(endpoint).await {
+                Ok(value) => collect(kind, format!("rest/{endpoint}"), &value),
+                Err(err) => tracing::debug!(collection = endpoint, "skipped: {err:#}"),
+            }
+        }
+        if found.is_empty() {
+            return Err(NotFound(format!("No object with ID '{id}'")).into());
+        }
+        Ok(found)
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Read;
+
+use anyhow::{Context, Result};
+
+/// Fill a buffer from the OS random source
+pub fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read random bytes")?;
+    Ok(bytes)
+}
+
+/// A random hex token (e.g., bearer tokens)
+pub fn random_token() -> Result<String> {
+    Ok(random_bytes::<24>()?
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// A random alphanumeric password
+pub fn random_password() -> Result<String> {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+    const LEN: usize = 24;
+    // Bytes from here up would make the first characters likelier, so they are drawn again
+    let limit = 256 - 256 % CHARSET.len();
+    let mut password = String::with_capacity(LEN);
+    while password.len() < LEN {
+        for b in random_bytes::<LEN>()? {
+            if (b as usize) < limit && password.len() < LEN {
+                password.push(CHARSET[b as usize % CHARSET.len()] as char);
+            }
+        }
+    }
+    Ok(password)
+}
+
+/// A secret for display: all but the last 4 characters hidden, or just a marker when it is
+/// encrypted
+pub fn mask(value: &str) -> String {
+    if is_encrypted(value) {
+        return "(encrypted)".into();
+    }
+    let chars: Vec<char> = value.chars().collect();
+    match chars.len() {
+        // Too short to show any of it
+        0..=8 => "********".into(),
+        n => format!("****{}", chars[n - 4..].iter().collect::<String>()),
+    }
+}
+
+/// Prefix of values produced by `encrypt`
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Whether a stored value was produced by `encrypt`
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// 256-bit key from a passphrase (Argon2id with its default cost)
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+    let mut key = chacha20poly1305::Key::default();
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {e}"))?;
+    Ok(key)
+}
+
+/// Encrypt a secret with a passphrase (ChaCha20-Poly1305 under an Argon2id key) as
+/// `enc:v1:` followed by the hex salt, nonce, and ciphertext
+pub fn encrypt(plaintext: &str, passphrase: &str) -> Result<String> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+
+    let salt = random_bytes::<SALT_LEN>()?;
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(&nonce.into(), plaintext.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    let hex: String = [&salt[..], &nonce[..], &ciphertext]
+        .concat()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Ok(format!("{ENCRYPTED_PREFIX}{hex}"))
+}
+
+/// Reverse `encrypt`; fails on a wrong passphrase
+pub fn decrypt(value: &str, passphrase: &str) -> Result<String> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+
+    let hex = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .context("Not an encrypted value")?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .filter(|bytes| bytes.len() > SALT_LEN + NONCE_LEN)
+        .context("Encrypted value is damaged")?;
+    let (salt, rest) = bytes.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    let plaintext = cipher
+        .decrypt(nonce.into(), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase for the encrypted credentials"))?;
+    String::from_utf8(plaintext).context("Encrypted value is damaged")
+}
@@ -1,11 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
 use anyhow::Result;
-use serde_json::Value;
+use reqwest::Method;
+use serde_json::{Value, json};
+
+use crate::api::{Client, NotFound};
+use crate::clients::normalize_mac;
+use crate::duration;
+
+/// Parse a MAC allowlist: one address per line, `#` starts a comment
+pub fn parse_mac_list(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(normalize_mac)
+        .collect()
+}
 
-use crate::api::Client;
+/// Gateway device types (the device that holds UPnP mappings)
+const GATEWAY_TYPES: &[&str] = &["ugw", "udm", "uxg"];
+
+fn is_upnp(record: &Value) -> bool {
+    record.get("upnp").and_then(|v| v.as_bool()) == Some(true)
+        || ["type", "source"].iter().any(|k| {
+            record
+                .get(*k)
+                .and_then(|v| v.as_str())
+                .is_some_and(|s| s.eq_ignore_ascii_case("upnp") || s.eq_ignore_ascii_case("natpmp"))
+        })
+}
+
+fn first_of<'a>(record: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter()
+        .find_map(|k| record.get(*k).filter(|v| !v.is_null()))
+}
+
+/// Stable lease ID: the controller's `_id` when present, else `PROTO:EXTERNAL_PORT`
+fn lease_id(record: &Value) -> String {
+    if let Some(id) = record.get("_id").and_then(|v| v.as_str()) {
+        return id.to_string();
+    }
+    let proto = first_of(record, &["proto", "protocol"])
+        .and_then(|v| v.as_str())
+        .unwrap_or("tcp");
+    let port = first_of(record, &["dst_port", "wan_port", "external_port"])
+        .map(|v| {
+            v.as_str()
+                .map(String::from)
+                .unwrap_or_else(|| v.to_string())
+        })
+        .unwrap_or_default();
+    format!("{}:{port}", proto.to_lowercase())
+}
 
 impl Client {
     /// Get security settings (IPS, ad blocking, DNS filtering)
     pub async fn get_security_settings(&self) -> Result<Value> {
         self.get_setting("ips").await
     }
+
+    /// Get clients first seen within `since`, newest first, annotated with their network
+    pub async fn get_new_clients(&self, since: Duration) -> Result<Value> {
+        let (all, online, networks) = tokio::try_join!(
+            self.get_clients_all(),
+            self.get_clients_online(),
+            self.get_networks()
+        )?;
+
+        let now = duration::now_secs();
+        let cutoff = now - since.as_secs() as i64;
+
+        let network_names: HashMap<&str, &str> = networks
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|n| Some((n.get("_id")?.as_str()?, n.get("name")?.as_str()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let online_networks: HashMap<&str, &str> = online
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|c| Some((c.get("mac")?.as_str()?, c.get("network")?.as_str()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let Value::Array(all) = all else {
+            return Ok(Value::Array(vec![]));
+        };
+        let mut new: Vec<Value> = all
+            .into_iter()
+            .filter(|c| {
+                c.get("first_seen")
+                    .and_then(|v| v.as_i64())
+                    .is_some_and(|t| t >= cutoff)
+            })
+            .map(|mut c| {
+                let network = c
+                    .get("mac")
+                    .and_then(|m| m.as_str())
+                    .and_then(|mac| online_networks.get(mac))
+                    .or_else(|| {
+                        ["network_id", "last_connection_network_id"]
+                            .iter()
+                            .find_map(|k| c.get(*k)?.as_str())
+                            .and_then(|id| network_names.get(id))
+                    })
+                    .map(|n| Value::String(n.to_string()))
+                    .or_else(|| c.get("last_connection_network_name").cloned())
+                    .unwrap_or(Value::Null);
+                let first_seen = c.get("first_seen").and_then(|v| v.as_i64()).unwrap_or(now);
+                if let Some(obj) = c.as_object_mut() {
+                    obj.insert("network".into(), network);
+                    obj.insert(
+                        "first_seen_ago".into(),
+                        Value::String(format!("{} ago", duration::humanize(now - first_seen))),
+                    );
+                }
+                c
+            })
+            .collect();
+        new.sort_by_key(|c| -c.get("first_seen").and_then(|v| v.as_i64()).unwrap_or(0));
+
+        Ok(Value::Array(new))
+    }
+
+    /// Get online clients whose MAC is not in `allowed`
+    pub async fn get_unlisted_clients(&self, allowed: &HashSet<String>) -> Result<Vec<Value>> {
+        let online = self.get_clients_online().await?;
+        let Value::Array(online) = online else {
+            return Ok(vec![]);
+        };
+        Ok(online
+            .into_iter()
+            .filter(|c| {
+                c.get("mac")
+                    .and_then(|m| m.as_str())
+                    .is_some_and(|mac| !allowed.contains(&normalize_mac(mac)))
+            })
+            .collect())
+    }
+
+    /// Active UPnP / NAT-PMP port mappings, with the client that requested each one
+    pub async fn get_upnp_leases(&self) -> Result<Value> {
+        let (forwards, online) =
+            tokio::try_join!(self.get_stat("portforward"), self.get_clients_online())?;
+
+        let clients_by_ip: HashMap<&str, &str> = online
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|c| {
+                        let ip = c.get("ip")?.as_str()?;
+                        let name = ["name", "hostname", "mac"]
+                            .iter()
+                            .find_map(|k| c.get(*k)?.as_str().filter(|s| !s.is_empty()))?;
+                        Some((ip, name))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let leases = forwards
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter(|r| is_upnp(r))
+            .map(|r| {
+                let internal_ip = first_of(r, &["fwd", "fwd_ip", "internal_ip"]);
+                json!({
+                    "id": lease_id(r),
+                    "protocol": first_of(r, &["proto", "protocol"]),
+                    "external_port": first_of(r, &["dst_port", "wan_port", "external_port"]),
+                    "internal_ip": internal_ip,
+                    "internal_port": first_of(r, &["fwd_port", "internal_port"]),
+                    "description": first_of(r, &["name", "description"]),
+                    "client": internal_ip
+                        .and_then(|ip| ip.as_str())
+                        .and_then(|ip| clients_by_ip.get(ip)),
+                    "expires": first_of(r, &["expires", "lease_duration"]),
+                })
+            })
+            .collect();
+        Ok(Value::Array(leases))
+    }
+
+    /// Remove one UPnP mapping from the gateway without disabling UPnP
+    pub async fn revoke_upnp_lease(&self, id: &str) -> Result<Value> {
+        let (leases, devices) = tokio::try_join!(self.get_upnp_leases(), self.get_devices())?;
+        let lease = leases
+            .as_array()
+            .and_then(|a| {
+                a.iter()
+                    .find(|l| l.get("id").and_then(|v| v.as_str()) == Some(id))
+            })
+            .cloned()
+            .ok_or_else(|| NotFound(format!("UPnP lease '{id}' not found")))?;
+        let gateway = devices
+            .as_array()
+            .and_then(|a| {
+                a.iter().find(|d| {
+                    d.get("type")
+                        .and_then(|t| t.as_str())
+                        .is_some_and(|t| GATEWAY_TYPES.contains(&t))
+                })
+            })
+            .and_then(|d| d.get("mac")?.as_str())
+            .ok_or_else(|| NotFound("No gateway found on this site".into()))?;
+
+        let url = self.site_url("cmd/devmgr");
+        let body = json!({
+            "cmd": "delete-upnp-lease",
+            "mac": gateway,
+            "protocol": lease["protocol"],
+            "port": lease["external_port"],
+        });
+        self.send(
+            Method::POST,
+            &url,
+            Some(&body),
+            &format!("revoke UPnP lease {id}"),
+        )
+        .await?;
+        Ok(lease)
+    }
 }
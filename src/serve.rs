@@ -0,0 +1,148 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::Json;
+use axum::Router;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde_json::{Value, json};
+
+use crate::api::{ApiError, Client, NotFound};
+use crate::ha::{self, Person};
+
+struct AppState {
+    client: Client,
+    token: String,
+    people: Vec<Person>,
+}
+
+/// Serve read-only controller data over a local REST API, authenticated with a bearer token
+pub async fn serve(
+    client: Client,
+    listen: SocketAddr,
+    token: String,
+    people: Vec<Person>,
+) -> Result<()> {
+    let state = Arc::new(AppState {
+        client,
+        token,
+        people,
+    });
+    let app = Router::new()
+        .route("/health", get(|| async { Json(json!({"status": "ok"})) }))
+        .route("/v1/{*path}", get(handle_get))
+        .route("/ha/presence", get(handle_ha_presence_all))
+        .route("/ha/presence/{person}", get(handle_ha_presence))
+        .route("/ha/wan", get(handle_ha_wan))
+        .route("/ha/guest-wifi", get(handle_ha_guest_wifi))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("Failed to listen on {listen}"))?;
+    axum::serve(listener, app).await.context("Server error")
+}
+
+fn authorized(state: &AppState, headers: &HeaderMap) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| t == state.token)
+}
+
+/// Authenticate the caller, then render the handler's result as JSON
+async fn respond<T: serde::Serialize>(
+    state: &AppState,
+    headers: &HeaderMap,
+    result: impl Future<Output = Result<T>>,
+) -> Response {
+    if !authorized(state, headers) {
+        return error_response(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token");
+    }
+
+    match result.await {
+        Ok(value) => Json(value).into_response(),
+        Err(err) => {
+            let status = if let Some(e) = err.downcast_ref::<ApiError>() {
+                e.status
+            } else if err.is::<NotFound>() {
+                StatusCode::NOT_FOUND
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            error_response(status, &format!("{err:#}"))
+        }
+    }
+}
+
+async fn handle_get(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&state, &headers, dispatch(&state.client, &path)).await
+}
+
+async fn handle_ha_presence_all(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&state, &headers, ha::presence(&state.client, &state.people)).await
+}
+
+async fn handle_ha_presence(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let result = async {
+        let person = state
+            .people
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| NotFound(format!("Unknown person '{name}'")))?;
+        let mut presence = ha::presence(&state.client, std::slice::from_ref(person)).await?;
+        Ok(presence.remove(&name))
+    };
+    respond(&state, &headers, result).await
+}
+
+async fn handle_ha_wan(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    respond(&state, &headers, ha::wan(&state.client)).await
+}
+
+async fn handle_ha_guest_wifi(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    respond(&state, &headers, ha::guest_wifi(&state.client)).await
+}
+
+/// Route a `/v1/...` path to the matching client call
+async fn dispatch(client: &Client, path: &str) -> Result<Value> {
+    match path.trim_end_matches('/') {
+        "clients/online" => client.get_clients_online().await,
+        "clients/all" => client.get_clients_all().await,
+        "clients/offline" => client.get_clients_offline(None).await,
+        "devices" => client.get_devices().await,
+        "networks" => client.get_networks().await,
+        "wifi" => client.get_wifi().await,
+        "security" => client.get_security_settings().await,
+        "dns" => client.get_dns_records().await,
+        "internet/wan" => client.get_wan_settings().await,
+        "internet/dns" => Ok(serde_json::to_value(client.get_dns_settings().await?)?),
+        "firewall/rules" => client.get_firewall_rules().await,
+        "firewall/groups" => client.get_firewall_groups().await,
+        "firewall/traffic" => client.get_traffic_rules().await,
+        "vpn/teleport" => client.get_vpn_teleport().await,
+        "vpn/site-to-site" => client.get_vpn_site_to_site().await,
+        "vpn/servers" => client.get_vpn_servers().await,
+        "vpn/clients" => client.get_vpn_clients().await,
+        _ => Err(NotFound(format!("Unknown endpoint '/v1/{path}'")).into()),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({"error": message}))).into_response()
+}
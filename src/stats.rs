@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::api::Client;
+use crate::clients::normalize_mac;
+use crate::duration;
+
+/// One client sample from the controller's per-client reports
+#[derive(Debug, Serialize)]
+pub struct Sample {
+    pub time: i64,
+    pub mac: String,
+    pub name: String,
+    pub latency_ms: Option<f64>,
+    pub satisfaction: Option<f64>,
+}
+
+/// Report granularity the controller keeps for a look-back window
+fn interval_for(range: Duration) -> (&'static str, i64) {
+    match range.as_secs() {
+        0..=43_200 => ("5minutes", 300),
+        43_201..=604_800 => ("hourly", 3600),
+        _ => ("daily", 86_400),
+    }
+}
+
+impl Client {
+    /// Per-client latency and satisfaction samples over `range`, oldest first
+    pub async fn get_client_samples(
+        &self,
+        range: Duration,
+        client: Option<&str>,
+    ) -> Result<Vec<Sample>> {
+        let end = duration::now_secs();
+        let start = end - range.as_secs() as i64;
+        let (interval, _) = interval_for(range);
+
+        let mut query = json!({
+            "attrs": ["time", "user", "latency", "satisfaction"],
+            "start": start * 1000,
+            "end": end * 1000,
+        });
+        if let Some(mac) = client {
+            query["macs"] = json!([normalize_mac(mac)]);
+        }
+        let endpoint = format!("report/{interval}.user");
+        let (rows, clients) =
+            tokio::try_join!(self.query_stat(&endpoint, &query), self.get_clients_all())?;
+
+        let names: HashMap<&str, &str> = clients
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|c| {
+                        let mac = c.get("mac")?.as_str()?;
+                        let name = ["name", "hostname"]
+                            .iter()
+                            .find_map(|k| c.get(*k)?.as_str().filter(|s| !s.is_empty()))
+                            .unwrap_or(mac);
+                        Some((mac, name))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut samples: Vec<Sample> = rows
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|r| {
+                let mac = r.get("user")?.as_str()?.to_string();
+                Some(Sample {
+                    time: r.get("time")?.as_i64()? / 1000,
+                    name: names.get(mac.as_str()).unwrap_or(&mac.as_str()).to_string(),
+                    mac,
+                    latency_ms: r.get("latency").and_then(|v| v.as_f64()),
+                    satisfaction: r.get("satisfaction").and_then(|v| v.as_f64()),
+                })
+            })
+            .collect();
+        samples.sort_by_key(|s| s.time);
+        Ok(samples)
+    }
+}
+
+/// Latency bands: <20 ms, <50, <100, <200, and worse
+fn shade(latency: f64) -> char {
+    match latency {
+        l if l < 20.0 => '░',
+        l if l < 50.0 => '▒',
+        l if l < 100.0 => '▓',
+        l if l < 200.0 => '█',
+        _ => '#',
+    }
+}
+
+/// One row per client, one column per time bucket (at most `width`), worst latency wins
+pub fn heatmap(samples: &[Sample], range: Duration, width: usize) -> String {
+    let end = duration::now_secs();
+    let start = end - range.as_secs() as i64;
+    let (_, step) = interval_for(range);
+    let buckets = ((range.as_secs() as i64 / step) as usize).clamp(1, width);
+    let bucket_secs = (range.as_secs() as i64 / buckets as i64).max(1);
+
+    let mut rows: BTreeMap<&str, Vec<Option<f64>>> = BTreeMap::new();
+    for s in samples {
+        let row = rows.entry(&s.name).or_insert_with(|| vec![None; buckets]);
+        let Some(latency) = s.latency_ms else {
+            continue;
+        };
+        let i = (((s.time - start) / bucket_secs).max(0) as usize).min(buckets - 1);
+        row[i] = Some(row[i].map_or(latency, |l: f64| l.max(latency)));
+    }
+
+    let label_width = rows.keys().map(|n| n.chars().count()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (name, cells) in &rows {
+        let line: String = cells.iter().map(|c| c.map_or('·', shade)).collect();
+        out.push_str(&format!("{name:<label_width$}  {line}\n"));
+    }
+    let first = chrono::DateTime::from_timestamp(start, 0)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%a %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    out.push_str(&format!(
+        "{:label_width$}  ^ {first}, {} per column\n",
+        "",
+        duration::humanize(bucket_secs)
+    ));
+    out.push_str("Legend: ░ <20ms  ▒ <50ms  ▓ <100ms  █ <200ms  # ≥200ms  · no data\n");
+    out
+}
+
+/// CSV with one line per sample
+pub fn csv(samples: &[Sample]) -> String {
+    let mut out = String::from("time,mac,name,latency_ms,satisfaction\n");
+    for s in samples {
+        let time = chrono::DateTime::from_timestamp(s.time, 0)
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+        let opt = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+        out.push_str(&format!(
+            "{time},{},\"{}\",{},{}\n",
+            s.mac,
+            s.name.replace('"', "\"\""),
+            opt(s.latency_ms),
+            opt(s.satisfaction)
+        ));
+    }
+    out
+}
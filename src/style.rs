@@ -0,0 +1,83 @@
+use std::io::IsTerminal;
+
+use serde::{Deserialize, Serialize};
+
+/// When to use ANSI colors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Only on a terminal, and only if `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        }
+    }
+
+    /// Whether to color what goes to stdout
+    pub fn stdout(self) -> bool {
+        self.enabled(std::io::stdout().is_terminal())
+    }
+
+    /// Whether to color what goes to stderr
+    pub fn stderr(self) -> bool {
+        self.enabled(std::io::stderr().is_terminal())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Dim,
+    Green,
+    Yellow,
+    Red,
+    Cyan,
+    Reverse,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Green => "32",
+            Style::Yellow => "33",
+            Style::Red => "31",
+            Style::Cyan => "36",
+            Style::Reverse => "7",
+        }
+    }
+
+    /// Wrap `text` in this style, or return it unchanged when color is off
+    pub fn paint(self, text: &str, enabled: bool) -> String {
+        if enabled && !text.is_empty() {
+            format!("\x1b[{}m{text}\x1b[0m", self.code())
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Color for a status word as used by devices, health subsystems, and copy results;
+/// booleans count as enabled/disabled
+pub fn status(text: &str) -> Option<Style> {
+    match text.trim().to_lowercase().as_str() {
+        "ok" | "online" | "connected" | "up" | "enabled" | "true" | "created" | "updated"
+        | "added" | "allowed" => Some(Style::Green),
+        "warning" | "pending" | "unknown" | "skipped" | "exists" => Some(Style::Yellow),
+        "error" | "offline" | "disconnected" | "down" | "disabled" | "false" | "failed"
+        | "removed" | "denied" => Some(Style::Red),
+        _ => None,
+    }
+}
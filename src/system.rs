@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use reqwest::Method;
+use serde_json::{Value, json};
+
+use crate::api::{Client, NotFound};
+
+impl Client {
+    /// Get controller health per subsystem (wan, www, lan, wlan, vpn)
+    pub async fn get_health(&self) -> Result<Value> {
+        self.get_stat("health").await
+    }
+
+    /// Controller model, hostname, and software versions (`stat/sysinfo`)
+    pub async fn get_sysinfo(&self) -> Result<Value> {
+        let info = self.get_stat("sysinfo").await?;
+        Ok(info
+            .as_array()
+            .and_then(|a| a.first().cloned())
+            .unwrap_or(info))
+    }
+
+    /// Have the controller generate a support file and download it
+    pub async fn download_support_bundle(&self) -> Result<Vec<u8>> {
+        let url = self.site_url("cmd/system");
+        let body = json!({"cmd": "gen-support-file"});
+        let resp = self
+            .send(Method::POST, &url, Some(&body), "generate support file")
+            .await?;
+        let generated = Self::first_data(resp.json().await?);
+        let link = generated
+            .get("url")
+            .and_then(|v| v.as_str())
+            .context("Controller did not return a support file link")?;
+        self.download(link, "download support file").await
+    }
+
+    /// Have the controller write a backup (settings only, no statistics) and download it
+    pub async fn download_backup(&self) -> Result<Vec<u8>> {
+        let url = self.site_url("cmd/backup");
+        let body = json!({"cmd": "backup", "days": 0});
+        let resp = self
+            .send(Method::POST, &url, Some(&body), "generate backup")
+            .await?;
+        let generated = Self::first_data(resp.json().await?);
+        let link = generated
+            .get("url")
+            .and_then(|v| v.as_str())
+            .context("Controller did not return a backup link")?;
+        self.download(link, "download backup").await
+    }
+
+    /// The site's IANA timezone (setting/locale), used to evaluate schedules
+    pub async fn get_site_timezone(&self) -> Result<Option<String>> {
+        let locale = self.get_setting("locale").await?;
+        Ok(locale
+            .get("timezone")
+            .and_then(|v| v.as_str())
+            .filter(|tz| !tz.is_empty())
+            .map(String::from))
+    }
+
+    /// Keep events and other non-statistics data for `hours`
+    pub async fn set_event_retention(&self, hours: u64) -> Result<Value> {
+        let fields = json!({
+            "data_retention_time_enabled": true,
+            "data_retention_time_in_hours_for_others": hours,
+        });
+        self.update_setting("super_mgmt", fields.as_object().unwrap())
+            .await
+    }
+}
+
+/// Parse an OpenSSH public key line (`type base64 [comment]`) into a controller key entry
+pub fn parse_ssh_key(line: &str, fallback_name: &str) -> Result<Value> {
+    let mut parts = line.split_whitespace();
+    let (Some(kind), Some(key)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("Expected an OpenSSH public key (TYPE KEY [COMMENT])");
+    };
+    anyhow::ensure!(
+        kind.starts_with("ssh-") || kind.starts_with("ecdsa-"),
+        "Unsupported key type '{kind}'"
+    );
+    let comment = parts.collect::<Vec<_>>().join(" ");
+    let name = if comment.is_empty() {
+        fallback_name
+    } else {
+        &comment
+    };
+    Ok(json!({
+        "name": name,
+        "type": kind,
+        "key": key,
+        "comment": comment,
+        "date": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    }))
+}
+
+/// Changes to the device SSH credentials; `None` leaves a field as is
+#[derive(Default)]
+pub struct DeviceSshUpdate {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub add_keys: Vec<Value>,
+}
+
+fn ssh_view(mgmt: &Value, devices: &Value) -> Value {
+    let username = mgmt
+        .get("x_ssh_username")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let keys: Vec<Value> = mgmt
+        .get("x_ssh_keys")
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|k| json!({"name": k.get("name"), "type": k.get("type"), "date": k.get("date")}))
+        .collect();
+    let endpoints: Vec<Value> = devices
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|d| {
+            let ip = d.get("ip").and_then(|v| v.as_str())?;
+            Some(json!({
+                "name": d.get("name"),
+                "mac": d.get("mac"),
+                "model": d.get("model"),
+                "ip": ip,
+                "ssh": format!("ssh {username}@{ip}"),
+            }))
+        })
+        .collect();
+    json!({
+        "enabled": mgmt.get("x_ssh_enabled"),
+        "username": username,
+        "password_auth": mgmt.get("x_ssh_auth_password_enabled"),
+        "password_set": mgmt
+            .get("x_ssh_password")
+            .and_then(|v| v.as_str())
+            .is_some_and(|p| !p.is_empty()),
+        "keys": keys,
+        "devices": endpoints,
+    })
+}
+
+impl Client {
+    /// Device SSH settings (password masked) with an SSH endpoint per adopted device
+    pub async fn get_device_ssh(&self) -> Result<Value> {
+        let (mgmt, devices) = tokio::try_join!(self.get_setting("mgmt"), self.get_devices())?;
+        Ok(ssh_view(&mgmt, &devices))
+    }
+
+    /// Update device SSH credentials; keys with the same name or key material are replaced
+    pub async fn update_device_ssh(&self, update: DeviceSshUpdate) -> Result<Value> {
+        let mut fields = serde_json::Map::new();
+        if let Some(username) = update.username {
+            fields.insert("x_ssh_username".into(), Value::String(username));
+        }
+        if let Some(password) = update.password {
+            fields.insert("x_ssh_password".into(), Value::String(password));
+        }
+        if !update.add_keys.is_empty() {
+            let current = self.get_setting("mgmt").await?;
+            let mut keys: Vec<Value> = current
+                .get("x_ssh_keys")
+                .and_then(|v| v.as_array())
+                .cloned()
+                .unwrap_or_default();
+            for new in update.add_keys {
+                keys.retain(|k| k.get("name") != new.get("name") && k.get("key") != new.get("key"));
+                keys.push(new);
+            }
+            fields.insert("x_ssh_keys".into(), Value::Array(keys));
+        }
+        let (mgmt, devices) =
+            tokio::try_join!(self.update_setting("mgmt", &fields), self.get_devices())?;
+        Ok(ssh_view(&mgmt, &devices))
+    }
+}
+
+/// Device firmware release channel
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum FirmwareChannel {
+    /// General availability releases
+    Release,
+    /// Release candidates (early access)
+    ReleaseCandidate,
+    Beta,
+}
+
+impl FirmwareChannel {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Release => "release",
+            Self::ReleaseCandidate => "release-candidate",
+            Self::Beta => "beta",
+        }
+    }
+}
+
+/// Changes to the automatic device update policy; `None` leaves a setting as is
+#[derive(Default)]
+pub struct AutoUpdatePolicy {
+    pub devices: Option<bool>,
+    /// Day (`None` for every day) and hour updates start, in the site timezone
+    pub start: Option<(Option<&'static str>, u32)>,
+    pub channel: Option<FirmwareChannel>,
+}
+
+fn auto_update_view(mgmt: &Value, fwupdate: &Value) -> Value {
+    let day = mgmt
+        .get("auto_upgrade_day")
+        .and_then(|v| v.as_str())
+        .filter(|d| !d.is_empty())
+        .unwrap_or("daily");
+    let start = mgmt
+        .get("auto_upgrade_hour")
+        .and_then(|v| v.as_u64())
+        .map(|hour| format!("{day} {hour:02}:00"));
+    json!({
+        "devices": mgmt.get("auto_upgrade"),
+        "schedule": start,
+        "channel": fwupdate.get("firmware_channel"),
+    })
+}
+
+impl Client {
+    /// `setting/super_fwupdate`, or null on controllers without it
+    async fn get_fwupdate_setting(&self) -> Result<Value> {
+        match self.get_setting("super_fwupdate").await {
+            Err(e) if e.is::<NotFound>() => Ok(Value::Null),
+            other => other,
+        }
+    }
+
+    /// Automatic device updates: whether they are on, when they start, and the channel
+    pub async fn get_auto_update(&self) -> Result<Value> {
+        let (mgmt, fwupdate) =
+            tokio::try_join!(self.get_setting("mgmt"), self.get_fwupdate_setting())?;
+        Ok(auto_update_view(&mgmt, &fwupdate))
+    }
+
+    /// Change the automatic device update policy
+    pub async fn update_auto_update(&self, policy: AutoUpdatePolicy) -> Result<Value> {
+        let mut fields = serde_json::Map::new();
+        if let Some(devices) = policy.devices {
+            fields.insert("auto_upgrade".into(), Value::Bool(devices));
+        }
+        if let Some((day, hour)) = policy.start {
+            fields.insert("auto_upgrade_hour".into(), json!(hour));
+            fields.insert("auto_upgrade_day".into(), json!(day.unwrap_or_default()));
+        }
+        let mgmt = match fields.is_empty() {
+            true => self.get_setting("mgmt").await?,
+            false => self.update_setting("mgmt", &fields).await?,
+        };
+        let fwupdate = match policy.channel {
+            Some(channel) => {
+                let fields = json!({"firmware_channel": channel.as_str()});
+                self.update_setting("super_fwupdate", fields.as_object().unwrap())
+                    .await?
+            }
+            None => self.get_fwupdate_setting().await?,
+        };
+        Ok(auto_update_view(&mgmt, &fwupdate))
+    }
+}
@@ -0,0 +1,127 @@
+//! Certificate checks beyond the system roots: pinning a controller's certificate by its
+//! SHA-256 fingerprint, for consoles with a self-signed one
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+
+/// SHA-256 of a DER certificate as colon-separated uppercase hex, as browsers show it
+pub fn fingerprint(der: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Whether a DER certificate has this SHA-256 fingerprint
+pub fn matches(der: &[u8], sha256: &[u8; 32]) -> bool {
+    ring::digest::digest(&ring::digest::SHA256, der).as_ref() == sha256
+}
+
+/// A SHA-256 fingerprint as 64 hex digits, with or without colons and in any case
+pub fn parse_fingerprint(s: &str) -> Result<[u8; 32]> {
+    let hex: String = s.chars().filter(|c| *c != ':').collect();
+    // Only ASCII hex digits, so the byte slicing below stays on character boundaries
+    anyhow::ensure!(
+        hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        "Invalid certificate fingerprint '{s}': expected a SHA-256 hash (64 hex digits)"
+    );
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+/// Accepts exactly the certificate with a given fingerprint, whoever issued it and whatever
+/// names and dates it carries; handshake signatures are still checked against it
+#[derive(Debug)]
+struct PinnedCertificate {
+    sha256: [u8; 32],
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if matches(end_entity, &self.sha256) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        Err(rustls::Error::General(format!(
+            "certificate fingerprint {} does not match the pinned one",
+            fingerprint(end_entity)
+        )))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// TLS settings that trust only the certificate with this SHA-256 fingerprint
+pub fn pinned_config(sha256: [u8; 32]) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = PinnedCertificate {
+        sha256,
+        algorithms: provider.signature_verification_algorithms,
+    };
+    Ok(
+        rustls::ClientConfig::builder_with_provider(provider as Arc<CryptoProvider>)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fingerprints() {
+        let hex = "ab".repeat(32);
+        assert_eq!(parse_fingerprint(&hex).unwrap(), [0xab; 32]);
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_fingerprint(&colons).unwrap(), [0xab; 32]);
+    }
+
+    #[test]
+    fn rejects_bad_fingerprints() {
+        // 64 bytes, but 32 two-byte characters
+        let multibyte = "é".repeat(32);
+        for bad in ["", "ab", &"ab".repeat(33), &"zz".repeat(32), &multibyte] {
+            assert!(parse_fingerprint(bad).is_err(), "accepted {bad:?}");
+        }
+    }
+}
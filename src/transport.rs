@@ -0,0 +1,34 @@
+use std::future::Future;
+use std::pin::Pin;
+
+/// What `Transport::execute` returns
+#[cfg(not(target_arch = "wasm32"))]
+pub type ResponseFuture<'a> =
+    Pin<Box<dyn Future<Output = reqwest::Result<reqwest::Response>> + Send + 'a>>;
+
+/// What `Transport::execute` returns (browser futures are not `Send`)
+#[cfg(target_arch = "wasm32")]
+pub type ResponseFuture<'a> =
+    Pin<Box<dyn Future<Output = reqwest::Result<reqwest::Response>> + 'a>>;
+
+/// Sends the requests a `Client` builds.
+///
+/// The default is a `reqwest::Client` configured by `ClientBuilder` (TLS, timeout, user agent,
+/// cookie store). Implement it to answer requests from a mock in tests, or to wrap another
+/// transport with retries, rate limiting, or recording. Responses can be made with
+/// `reqwest::Response::from(http::Response<_>)`.
+pub trait Transport: Send + Sync {
+    fn execute(&self, request: reqwest::Request) -> ResponseFuture<'_>;
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, request: reqwest::Request) -> ResponseFuture<'_> {
+        Box::pin(reqwest::Client::execute(self, request))
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
+    fn execute(&self, request: reqwest::Request) -> ResponseFuture<'_> {
+        (**self).execute(request)
+    }
+}
@@ -1,7 +1,59 @@
 use anyhow::Result;
+use serde::Serialize;
 use serde_json::Value;
 
-use crate::api::Client;
+use crate::api::{Client, NotFound};
+
+/// One line of `vpn verify`
+#[derive(Debug, Serialize)]
+pub struct VpnCheck {
+    pub check: &'static str,
+    /// `ok` or `error`
+    pub status: &'static str,
+    pub detail: String,
+}
+
+impl VpnCheck {
+    fn new(check: &'static str, ok: bool, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: if ok { "ok" } else { "error" },
+            detail: detail.into(),
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == "error"
+    }
+}
+
+fn text<'a>(record: &'a Value, key: &str) -> &'a str {
+    record.get(key).and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+fn enabled(record: &Value) -> bool {
+    record.get("enabled").and_then(|v| v.as_bool()) != Some(false)
+}
+
+fn by_name<'a>(records: &'a [Value], name: &str) -> Option<&'a Value> {
+    records
+        .iter()
+        .find(|r| text(r, "name").eq_ignore_ascii_case(name) || text(r, "_id") == name)
+}
+
+/// Whether a traffic route applies to every client of the network `network_id`
+fn targets_network(route: &Value, network_id: &str) -> bool {
+    route
+        .get("target_devices")
+        .and_then(|v| v.as_array())
+        .is_some_and(|targets| {
+            targets.iter().any(|t| match text(t, "type") {
+                "ALL_CLIENTS" => true,
+                "NETWORK" => text(t, "network_id") == network_id,
+                _ => false,
+            })
+        })
+}
 
 impl Client {
     /// Get Teleport VPN settings
@@ -34,4 +86,116 @@ impl Client {
             .await
             .or_else(|_| Ok(Value::Array(vec![])))
     }
+
+    /// Get traffic routes (policy-based routing)
+    pub async fn get_traffic_routes(&self) -> Result<Value> {
+        self.get_v2("trafficroutes").await
+    }
+
+    /// Check that `network` can reach the internet only through the VPN client `vpn`: an
+    /// enabled internet route sends the whole network through it, no other route sends any
+    /// of its traffic elsewhere, and either the route's kill switch or a firewall rule stops
+    /// traffic from falling back to the WAN when the tunnel is down
+    pub async fn verify_vpn_exit(&self, network: &str, vpn: &str) -> Result<Vec<VpnCheck>> {
+        let (networks, routes, rules) = tokio::try_join!(
+            self.get_networks(),
+            self.get_traffic_routes(),
+            self.get_firewall_rules()
+        )?;
+        let networks = networks.as_array().map(Vec::as_slice).unwrap_or_default();
+        let routes = routes.as_array().map(Vec::as_slice).unwrap_or_default();
+        let rules = rules.as_array().map(Vec::as_slice).unwrap_or_default();
+        let lan = by_name(networks, network)
+            .ok_or_else(|| NotFound(format!("Network '{network}' not found")))?;
+        let tunnels: Vec<Value> = networks
+            .iter()
+            .filter(|n| text(n, "purpose") == "vpn-client")
+            .cloned()
+            .collect();
+        let tunnel = by_name(&tunnels, vpn)
+            .ok_or_else(|| NotFound(format!("VPN client '{vpn}' not found")))?;
+        let (lan_id, tunnel_id) = (text(lan, "_id"), text(tunnel, "_id"));
+        let (lan_name, tunnel_name) = (text(lan, "name"), text(tunnel, "name"));
+        let mut checks = vec![VpnCheck::new(
+            "vpn client",
+            enabled(tunnel),
+            format!(
+                "{tunnel_name} is {}",
+                if enabled(tunnel) {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            ),
+        )];
+
+        let applies = |r: &&Value| enabled(r) && targets_network(r, lan_id);
+        let route = routes.iter().filter(applies).find(|r| {
+            text(r, "matching_target") == "INTERNET" && text(r, "network_id") == tunnel_id
+        });
+        checks.push(match route {
+            Some(r) => VpnCheck::new(
+                "route",
+                true,
+                format!(
+                    "'{}' sends all internet traffic of {lan_name} via {tunnel_name}",
+                    text(r, "description")
+                ),
+            ),
+            None => VpnCheck::new(
+                "route",
+                false,
+                format!("No enabled internet route sends {lan_name} via {tunnel_name}"),
+            ),
+        });
+
+        let elsewhere: Vec<String> = routes
+            .iter()
+            .filter(applies)
+            .filter(|r| text(r, "network_id") != tunnel_id)
+            .map(|r| {
+                let via = networks
+                    .iter()
+                    .find(|n| text(n, "_id") == text(r, "network_id"))
+                    .map_or(text(r, "network_id"), |n| text(n, "name"));
+                format!(
+                    "'{}' ({} via {via})",
+                    text(r, "description"),
+                    text(r, "matching_target")
+                )
+            })
+            .collect();
+        checks.push(if elsewhere.is_empty() {
+            VpnCheck::new(
+                "other routes",
+                true,
+                format!("No route sends {lan_name} traffic elsewhere"),
+            )
+        } else {
+            VpnCheck::new(
+                "other routes",
+                false,
+                format!("Routes leak around the VPN: {}", elsewhere.join(", ")),
+            )
+        });
+
+        let kill_switch = route
+            .is_some_and(|r| r.get("kill_switch_enabled").and_then(|v| v.as_bool()) == Some(true));
+        // Without the kill switch, a rule dropping everything else from the network does it
+        let blocker = rules.iter().find(|r| {
+            enabled(r)
+                && matches!(text(r, "action"), "drop" | "reject")
+                && matches!(text(r, "ruleset"), "LAN_IN" | "WAN_OUT")
+                && text(r, "src_networkconf_id") == lan_id
+                && text(r, "dst_address").is_empty()
+                && text(r, "dst_networkconf_id").is_empty()
+                && matches!(text(r, "protocol"), "" | "all")
+        });
+        checks.push(match (kill_switch, blocker) {
+            (true, _) => VpnCheck::new("fallback", true, "The route's kill switch blocks traffic while the VPN is down"),
+            (false, Some(r)) => VpnCheck::new("fallback", true, format!("Firewall rule '{}' drops {lan_name} traffic that is not routed via the VPN", text(r, "name"))),
+            (false, None) => VpnCheck::new("fallback", false, format!("No kill switch or firewall rule: {lan_name} falls back to the WAN when the VPN is down")),
+        });
+        Ok(checks)
+    }
 }
@@ -1,11 +1,316 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::api::{Client, NotFound};
+use crate::clients::normalize_mac;
+use crate::output;
+
+/// Keep wireless clients whose controller-computed satisfaction is at least `min`
+pub fn filter_satisfaction(clients: &mut Value, min: u8) {
+    if let Some(items) = clients.as_array_mut() {
+        items.retain(|c| {
+            c.get("satisfaction")
+                .and_then(|v| v.as_u64())
+                .is_some_and(|s| s >= min as u64)
+        });
+    }
+}
+
+/// Likely reasons for a poor experience, from the client's live radio stats
+fn probable_causes(c: &Value) -> Vec<&'static str> {
+    let num = |k: &str| c.get(k).and_then(|v| v.as_f64());
+    let mut causes = Vec::new();
+    if num("signal").is_some_and(|s| s < -75.0) {
+        causes.push("weak signal");
+    }
+    if let (Some(retries), Some(packets)) = (num("tx_retries"), num("tx_packets"))
+        && packets + retries > 0.0
+        && retries / (packets + retries) > 0.15
+    {
+        causes.push("high retries");
+    }
+    // tx_rate is in kbps
+    if num("tx_rate").is_some_and(|r| r > 0.0 && r < 24_000.0) {
+        causes.push("low link rate");
+    }
+    if c.get("radio").and_then(|v| v.as_str()) == Some("ng") {
+        causes.push("on 2.4 GHz");
+    }
+    if c.get("roam_count")
+        .and_then(|v| v.as_u64())
+        .is_some_and(|n| n > 10)
+    {
+        causes.push("frequent roaming");
+    }
+    causes
+}
+
+/// One signal reading of a wireless client; connection fields are empty while it is offline
+#[derive(Debug, Default, Serialize)]
+pub struct SurveySample {
+    pub time: String,
+    pub mac: String,
+    pub ap: Option<String>,
+    pub ap_mac: Option<String>,
+    pub essid: Option<String>,
+    pub radio: Option<String>,
+    pub channel: Option<i64>,
+    /// Signal strength in dBm
+    pub signal: Option<i64>,
+    /// Signal above noise floor as reported by the AP
+    pub rssi: Option<i64>,
+    pub noise: Option<i64>,
+}
+
+pub const SURVEY_CSV_HEADER: &str = "time,mac,ap,ap_mac,essid,radio,channel,signal,rssi,noise";
+
+impl SurveySample {
+    pub fn csv_line(&self) -> String {
+        let text = |v: &Option<String>| output::csv_field(v.as_deref().unwrap_or_default());
+        let num = |v: Option<i64>| v.map(|n| n.to_string()).unwrap_or_default();
+        [
+            self.time.clone(),
+            self.mac.clone(),
+            text(&self.ap),
+            text(&self.ap_mac),
+            text(&self.essid),
+            text(&self.radio),
+            num(self.channel),
+            num(self.signal),
+            num(self.rssi),
+            num(self.noise),
+        ]
+        .join(",")
+    }
+}
+
+/// A per-AP override of a WLAN (`wlan_overrides` on the device) that makes the AP behave
+/// differently from the others
+#[derive(Debug, Serialize)]
+pub struct WlanOverride {
+    pub ap: String,
+    pub ap_mac: String,
+    /// SSID of the WLAN as configured site-wide
+    pub wlan: String,
+    pub radio: Option<String>,
+    /// What differs on this AP, e.g. "disabled" or "VLAN 30 instead of 10"
+    pub changes: Vec<String>,
+}
 
-use crate::api::Client;
+/// A VLAN ID given as a number or a string, `None` when empty
+fn vlan_id(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// How `over` differs from `wlan`; the passphrase itself is never shown
+fn override_changes(over: &Value, wlan: &Value) -> Vec<String> {
+    let text = |v: &Value, k: &str| v.get(k).and_then(|v| v.as_str()).map(String::from);
+    let flag = |v: &Value, k: &str| v.get(k).and_then(|v| v.as_bool());
+    let mut changes = Vec::new();
+    if flag(over, "enabled") == Some(false) && flag(wlan, "enabled") != Some(false) {
+        changes.push("disabled".to_string());
+    }
+    if let Some(ssid) = text(over, "name").filter(|s| !s.is_empty())
+        && Some(&ssid) != text(wlan, "name").as_ref()
+    {
+        changes.push(format!("SSID '{ssid}'"));
+    }
+    if text(over, "x_passphrase").is_some_and(|p| !p.is_empty())
+        && text(over, "x_passphrase") != text(wlan, "x_passphrase")
+    {
+        changes.push("passphrase differs".to_string());
+    }
+    let wlan_vlan = vlan_id(wlan.get("vlan")).filter(|_| flag(wlan, "vlan_enabled") != Some(false));
+    let over_vlan = match flag(over, "vlan_enabled") {
+        Some(true) => vlan_id(over.get("vlan")),
+        Some(false) => None,
+        // Not overridden
+        None => wlan_vlan.clone(),
+    };
+    if over_vlan != wlan_vlan {
+        let show = |vlan: &Option<String>| match vlan {
+            Some(id) => format!("VLAN {id}"),
+            None => "no VLAN".to_string(),
+        };
+        changes.push(format!(
+            "{} instead of {}",
+            show(&over_vlan),
+            show(&wlan_vlan)
+        ));
+    }
+    changes
+}
 
 impl Client {
     /// Get WiFi/WLAN configurations
     pub async fn get_wifi(&self) -> Result<Value> {
         self.get_rest("wlanconf").await
     }
+
+    /// Find a WLAN configuration by SSID
+    pub async fn find_wlan(&self, ssid: &str) -> Result<Value> {
+        let wlans = self.get_wifi().await?;
+        wlans
+            .as_array()
+            .and_then(|a| {
+                a.iter()
+                    .find(|w| w.get("name").and_then(|n| n.as_str()) == Some(ssid))
+            })
+            .cloned()
+            .ok_or_else(|| NotFound(format!("WLAN '{ssid}' not found")).into())
+    }
+
+    /// Online wireless clients with the lowest satisfaction, worst first
+    pub async fn get_unhappy_clients(&self, limit: usize) -> Result<Value> {
+        let (online, devices) = tokio::try_join!(self.get_clients_online(), self.get_devices())?;
+        let ap_names: HashMap<&str, &str> = devices
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|d| Some((d.get("mac")?.as_str()?, d.get("name")?.as_str()?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut wireless: Vec<&Value> = online
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter(|c| c.get("is_wired").and_then(|v| v.as_bool()) != Some(true))
+            .filter(|c| c.get("satisfaction").is_some_and(|v| v.is_number()))
+            .collect();
+        wireless.sort_by_key(|c| c.get("satisfaction").and_then(|v| v.as_u64()));
+
+        let unhappy = wireless
+            .into_iter()
+            .take(limit)
+            .map(|c| {
+                let ap = c.get("ap_mac").and_then(|v| v.as_str());
+                let name = ["name", "hostname", "mac"]
+                    .iter()
+                    .find_map(|k| c.get(*k)?.as_str().filter(|s| !s.is_empty()));
+                json!({
+                    "name": name,
+                    "mac": c.get("mac"),
+                    "satisfaction": c.get("satisfaction"),
+                    "ap": ap.map(|mac| ap_names.get(mac).copied().unwrap_or(mac)),
+                    "essid": c.get("essid"),
+                    "radio": c.get("radio"),
+                    "channel": c.get("channel"),
+                    "signal": c.get("signal"),
+                    "tx_rate": c.get("tx_rate"),
+                    "tx_retries": c.get("tx_retries"),
+                    "causes": probable_causes(c),
+                })
+            })
+            .collect();
+        Ok(Value::Array(unhappy))
+    }
+
+    /// Per-AP WLAN overrides that change something (SSID, passphrase, VLAN, or enabled
+    /// state), by AP
+    pub async fn get_wlan_overrides(&self) -> Result<Vec<WlanOverride>> {
+        let (wlans, devices) = tokio::try_join!(self.get_wifi(), self.get_devices())?;
+        let wlans: HashMap<&str, &Value> = wlans
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|w| Some((w.get("_id")?.as_str()?, w)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut overrides = Vec::new();
+        for device in devices.as_array().map(Vec::as_slice).unwrap_or_default() {
+            let text = |k: &str| device.get(k).and_then(|v| v.as_str()).unwrap_or_default();
+            let entries = device.get("wlan_overrides").and_then(|v| v.as_array());
+            for over in entries.map(Vec::as_slice).unwrap_or_default() {
+                let wlan_id = over
+                    .get("wlan_id")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                // An override of a deleted WLAN does nothing
+                let Some(wlan) = wlans.get(wlan_id) else {
+                    continue;
+                };
+                let changes = override_changes(over, wlan);
+                if changes.is_empty() {
+                    continue;
+                }
+                overrides.push(WlanOverride {
+                    ap: [text("name"), text("mac")]
+                        .into_iter()
+                        .find(|s| !s.is_empty())
+                        .unwrap_or_default()
+                        .to_string(),
+                    ap_mac: text("mac").to_string(),
+                    wlan: wlan
+                        .get("name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or(wlan_id)
+                        .to_string(),
+                    radio: over.get("radio").and_then(|v| v.as_str()).map(String::from),
+                    changes,
+                });
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// AP names by MAC, for labelling survey samples
+    pub async fn get_ap_names(&self) -> Result<HashMap<String, String>> {
+        let devices = self.get_devices().await?;
+        Ok(devices
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|d| {
+                        let mac = d.get("mac")?.as_str()?;
+                        let name = d.get("name").and_then(|n| n.as_str()).unwrap_or(mac);
+                        Some((mac.to_string(), name.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Current signal of one client, as seen by the AP it is associated with
+    pub async fn survey_sample(
+        &self,
+        mac: &str,
+        ap_names: &HashMap<String, String>,
+    ) -> Result<SurveySample> {
+        let mac = normalize_mac(mac);
+        let stations = self.get_stat(&format!("sta/{mac}")).await?;
+        let mut sample = SurveySample {
+            time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            mac: mac.clone(),
+            ..Default::default()
+        };
+        let Some(sta) = stations
+            .as_array()
+            .and_then(|a| a.first())
+            .filter(|c| c.get("is_wired").and_then(|v| v.as_bool()) != Some(true))
+        else {
+            return Ok(sample);
+        };
+        let text = |k: &str| sta.get(k).and_then(|v| v.as_str()).map(String::from);
+        let num = |k: &str| sta.get(k).and_then(|v| v.as_i64());
+        sample.ap = text("ap_mac").map(|ap| ap_names.get(&ap).cloned().unwrap_or(ap));
+        sample.ap_mac = text("ap_mac");
+        sample.essid = text("essid");
+        sample.radio = text("radio");
+        sample.channel = num("channel");
+        sample.signal = num("signal");
+        sample.rssi = num("rssi");
+        sample.noise = num("noise");
+        Ok(sample)
+    }
 }
@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use image::Luma;
+use qrcode::{Color, QrCode};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CardFormat {
+    /// Join QR code as a PNG image
+    QrPng,
+    /// Printable credential card with QR code
+    Pdf,
+    /// Plain-text credentials
+    Txt,
+}
+
+/// What a guest needs to join a WLAN
+pub struct Credentials {
+    pub ssid: String,
+    pub password: Option<String>,
+    /// QR `T:` value: WPA, WEP, or nopass
+    pub auth: &'static str,
+    pub hidden: bool,
+}
+
+impl Credentials {
+    pub fn from_wlan(wlan: &Value) -> Self {
+        let security = wlan.get("security").and_then(|v| v.as_str()).unwrap_or("");
+        let auth = match security {
+            "open" => "nopass",
+            "wep" => "WEP",
+            _ => "WPA",
+        };
+        Self {
+            ssid: wlan
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            password: wlan
+                .get("x_passphrase")
+                .and_then(|v| v.as_str())
+                .filter(|p| !p.is_empty() && auth != "nopass")
+                .map(String::from),
+            auth,
+            hidden: wlan.get("hide_ssid").and_then(|v| v.as_bool()) == Some(true),
+        }
+    }
+
+    /// The `WIFI:` payload understood by iOS and Android camera apps
+    pub fn qr_payload(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.chars()
+                .flat_map(|c| match c {
+                    '\\' | ';' | ',' | ':' | '"' => vec!['\\', c],
+                    _ => vec![c],
+                })
+                .collect()
+        }
+        let mut payload = format!("WIFI:T:{};S:{};", self.auth, escape(&self.ssid));
+        if let Some(password) = &self.password {
+            payload.push_str(&format!("P:{};", escape(password)));
+        }
+        if self.hidden {
+            payload.push_str("H:true;");
+        }
+        payload.push(';');
+        payload
+    }
+
+    fn text(&self) -> String {
+        let mut out = format!("Wi-Fi network: {}\n", self.ssid);
+        match &self.password {
+            Some(password) => out.push_str(&format!("Password: {password}\n")),
+            None => out.push_str("Password: (none)\n"),
+        }
+        if self.hidden {
+            out.push_str("Hidden network: add it manually by name\n");
+        }
+        out
+    }
+}
+
+/// File name stem that is safe on every filesystem
+fn file_stem(ssid: &str) -> String {
+    let stem: String = ssid
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() { "wifi".into() } else { stem }
+}
+
+/// Write the requested artifacts into `dir`, returning the paths written
+pub fn export(creds: &Credentials, formats: &[CardFormat], dir: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let code = QrCode::new(creds.qr_payload().as_bytes()).context("Failed to encode QR code")?;
+    let stem = file_stem(&creds.ssid);
+
+    let mut written = Vec::new();
+    for format in formats {
+        let path = match format {
+            CardFormat::QrPng => {
+                let path = dir.join(format!("{stem}.png"));
+                code.render::<Luma<u8>>()
+                    .min_dimensions(400, 400)
+                    .build()
+                    .save(&path)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                path
+            }
+            CardFormat::Pdf => {
+                let path = dir.join(format!("{stem}.pdf"));
+                fs::write(&path, render_pdf(creds, &code))?;
+                path
+            }
+            CardFormat::Txt => {
+                let path = dir.join(format!("{stem}.txt"));
+                fs::write(&path, creds.text())?;
+                path
+            }
+        };
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Escape text for a PDF string literal; the base-14 fonts only cover Latin-1
+fn pdf_text(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{c}"),
+            c if (c as u32) < 0x80 => c.to_string(),
+            c if (c as u32) < 0x100 => format!("\\{:03o}", c as u32),
+            _ => "?".into(),
+        })
+        .collect()
+}
+
+/// A single A6 page with the network name, password, and the QR code drawn as vector squares
+fn render_pdf(creds: &Credentials, code: &QrCode) -> Vec<u8> {
+    let (page_w, page_h) = (298.0, 420.0);
+    let width = code.width();
+    let quiet = 4;
+    let module = 200.0 / (width + 2 * quiet) as f64;
+    let origin_x = (page_w - module * (width + 2 * quiet) as f64) / 2.0;
+    let origin_y = 40.0;
+
+    let mut content = String::new();
+    content.push_str(&format!(
+        "BT /F1 20 Tf 30 370 Td (Wi-Fi: {}) Tj ET\n",
+        pdf_text(&creds.ssid)
+    ));
+    let password = creds.password.as_deref().unwrap_or("(none)");
+    content.push_str(&format!(
+        "BT /F1 14 Tf 30 340 Td (Password: {}) Tj ET\n",
+        pdf_text(password)
+    ));
+    content.push_str("BT /F1 10 Tf 30 310 Td (Scan with your phone camera to join) Tj ET\n");
+    content.push_str("0 g\n");
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            let (x, y) = (i % width + quiet, i / width + quiet);
+            // PDF y axis points up; QR rows go down
+            let px = origin_x + x as f64 * module;
+            let py = origin_y + (width + 2 * quiet - 1 - y) as f64 * module;
+            content.push_str(&format!("{px:.2} {py:.2} {module:.2} {module:.2} re\n"));
+        }
+    }
+    content.push_str("f\n");
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {page_w} {page_h}] \
+             /Resources << /Font << /F1 4 0 R >> >> /Contents 5 0 R >>"
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        format!(
+            "<< /Length {} >>\nstream\n{content}endstream",
+            content.len()
+        ),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, obj) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{obj}\nendobj\n", i + 1).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    pdf
+}